@@ -0,0 +1,428 @@
+use crate::encoding;
+use crate::error::Error;
+use crate::extract::{self, ExtractError, IntermediateResult, OutputFormat};
+use crate::follow::Follower;
+use crate::http::{Fetcher, HttpOptions};
+use crate::output::Output;
+use crate::pagination;
+use crate::pool::{self, PoolOptions};
+use crate::template::CompiledTemplate;
+
+use url::Url;
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// An input of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Url(String),
+    File(PathBuf),
+    Stdin
+}
+
+impl Source {
+    /// An entry of a list of inputs: a URL if it starts with `http://` or `https://`, a path
+    /// otherwise.
+    pub fn parse(entry: &str) -> Self {
+        if entry.starts_with("http://") || entry.starts_with("https://") {
+            Source::Url(entry.to_string())
+        } else {
+            Source::File(PathBuf::from(entry))
+        }
+    }
+
+    /// The `host:port` of a URL, for the per-host limits of a batch.
+    pub fn host(&self) -> Option<String> {
+        match self {
+            Source::Url(url) => Url::parse(url).ok().as_ref().and_then(pool::host_key),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Self::Url(url) => write!(f, "{}", url),
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Stdin => write!(f, "-")
+        }
+    }
+}
+
+/// An input of a run, with the HTTP options that override the ones of the run for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Input {
+    pub source: Source,
+    pub http: Option<HttpOptions>,
+    /// The URL the links of a file or of stdin are resolved against. A page fetched from a URL is
+    /// resolved against its own URL.
+    pub base_url: Option<Url>
+}
+
+impl Input {
+    /// An entry of a list of inputs: a URL or a path, see `Source::parse`, or a JSON object with
+    /// the `url` and its HTTP options, like `{"url": "https://a.com/", "user_agent": "bot/1.0"}`.
+    pub fn parse(entry: &str) -> Result<Self, String> {
+        if !entry.starts_with('{') {
+            return Ok(Source::parse(entry).into());
+        }
+        let mut object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(entry).map_err(|err| err.to_string())?;
+        let url = match object.remove("url") {
+            Some(serde_json::Value::String(url)) => url,
+            _ => return Err("expected a `url` string".to_string())
+        };
+        let http = serde_json::from_value(serde_json::Value::Object(object)).map_err(|err| err.to_string())?;
+        Ok(Self { source: Source::parse(&url), http: Some(http), base_url: None })
+    }
+}
+
+impl From<Source> for Input {
+    fn from(source: Source) -> Self {
+        Self { source, http: None, base_url: None }
+    }
+}
+
+/// The counts of a batch, reported at its end.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BatchSummary {
+    pub total: usize,
+    pub failed: usize,
+    /// The inputs in which the template matched at least one record.
+    pub matched: usize
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} inputs: {} succeeded ({} with matches), {} failed", self.total, self.total - self.failed, self.matched, self.failed)
+    }
+}
+
+/// Collect the results of the same template applied to many inputs. Every object is tagged with
+/// the input it comes from. NDJSON lines are written as soon as the result of an input is added,
+/// the other formats are built by `finish`, once every input is done.
+pub struct Batch<'t> {
+    template: &'t CompiledTemplate,
+    output_format: OutputFormat,
+    objects: IntermediateResult,
+    texts: Vec<String>,
+    summary: BatchSummary
+}
+
+impl<'t> Batch<'t> {
+    pub fn new(template: &'t CompiledTemplate, output_format: OutputFormat) -> Self {
+        Self {
+            template,
+            output_format,
+            objects: Vec::new(),
+            texts: Vec::new(),
+            summary: BatchSummary::default()
+        }
+    }
+
+    /// Add the result of one input. A failed input is only counted, it does not stop the batch.
+    pub fn add<W: Write>(&mut self, source: &Source, result: Result<IntermediateResult, Error>, writer: &mut W) -> Result<(), ExtractError> {
+        self.summary.total += 1;
+        let mut intermediate = match result {
+            Ok(intermediate) => intermediate,
+            Err(_) => {
+                self.summary.failed += 1;
+                return Ok(());
+            }
+        };
+        if intermediate.iter().any(|object| !object.records.is_empty()) {
+            self.summary.matched += 1;
+        }
+        for object in intermediate.iter_mut() {
+            object.source = Some(source.to_string());
+        }
+        match self.output_format {
+            OutputFormat::Ndjson => {
+                extract::write_ndjson(&intermediate, writer)?;
+            },
+            // the first value of every input, on its own line
+            OutputFormat::Text => {
+                if let Output::Text(text) = extract::intermediate_to_output(&intermediate, self.template.idents(), &self.output_format)? {
+                    self.texts.push(text);
+                }
+            },
+            _ => self.objects.extend(intermediate)
+        }
+        Ok(())
+    }
+
+    /// The output of all the inputs, `Output::Empty` for NDJSON which is already written.
+    pub fn finish(self) -> Result<(Output, BatchSummary), ExtractError> {
+        let output = match self.output_format {
+            OutputFormat::Ndjson => Output::Empty,
+            OutputFormat::Text => Output::Text(self.texts.join("\n")),
+            _ => extract::intermediate_to_output(&self.objects, self.template.idents(), &self.output_format)?
+        };
+        Ok((output, self.summary))
+    }
+}
+
+/// Fetch one input and extract its records. With the pagination of the template, the records of
+/// the next pages are appended to the ones of their object, and the links it follows get their
+/// detail records. `report` is called with every detail page that cannot be read, whose link gets
+/// an `NA` detail.
+pub fn extract_input<R: FnMut(Url, Error)>(input: &Input, fetcher: &Fetcher, template: &CompiledTemplate, output_format: OutputFormat,
+        mut report: R) -> Result<IntermediateResult, Error> {
+    let mut result: Option<IntermediateResult> = None;
+    let mut follower = Follower::new(input, fetcher, template);
+    pagination::for_each_page(input, fetcher, template, output_format, |extractor, base_url| {
+        let mut page = extractor.extract(template)?;
+        follower.follow(&mut page, template, base_url)?;
+        for (url, err) in follower.take_failed() {
+            report(url, err);
+        }
+        let matched = page.iter().any(|object| !object.records.is_empty());
+        match &mut result {
+            None => result = Some(page),
+            Some(result) => for (object, page_object) in result.iter_mut().zip(page) {
+                object.records.extend(page_object.records);
+            }
+        }
+        Ok(matched)
+    })?;
+    Ok(result.unwrap_or_default())
+}
+
+/// Fetch and extract every input with a pool of workers, and collect the results in a `Batch`.
+/// `report` is called with every input that fails, as soon as it fails, and with every detail page
+/// that cannot be read, once its input is done.
+pub fn run_inputs<W: Write, E: FnMut(&Source, &Error)>(inputs: &[Input], fetcher: &Fetcher, template: &CompiledTemplate, output_format: OutputFormat,
+        options: &PoolOptions, writer: &mut W, mut report: E) -> Result<(Output, BatchSummary), ExtractError> {
+    let mut batch = Batch::new(template, output_format);
+    let mut failure = None;
+    let work = |input: &Input| {
+        let mut failed_details = Vec::new();
+        let result = extract_input(input, fetcher, template, output_format, |url, err| failed_details.push((Source::Url(url.to_string()), err)));
+        (result, failed_details)
+    };
+    pool::run(inputs, options, |input| input.source.host(), work, |index, (result, failed_details)| {
+        for (source, err) in &failed_details {
+            report(source, err);
+        }
+        if let Err(err) = &result {
+            report(&inputs[index].source, err);
+        }
+        if failure.is_none() {
+            failure = batch.add(&inputs[index].source, result, writer).err();
+        }
+    });
+    match failure {
+        Some(err) => Err(err),
+        None => batch.finish()
+    }
+}
+
+/// Read the HTML of an input, fetching a URL with the HTTP options of the run, merged with the
+/// ones of the input. Files and stdin are decoded like the fetched pages, without the header.
+pub fn read_html(input: &Input, fetcher: &Fetcher) -> Result<String, Error> {
+    let bytes = match &input.source {
+        Source::Url(url) => match compression(url.split(['?', '#']).next().unwrap_or_default()) {
+            Some(compression) => decompress(fetcher.fetch_bytes(url, input.http.as_ref())?.as_slice(), Some(compression))
+                .map_err(|err| Error::Io(format!("cannot decompress `{}`: {}", url, err)))?,
+            None => return fetcher.fetch(url, input.http.as_ref())
+        },
+        Source::File(path) => read_file(path).map_err(|err| Error::Io(format!("cannot read `{}`: {}", path.display(), err)))?,
+        Source::Stdin => read_bytes_from_stdin()?
+    };
+    let label = input.http.as_ref().and_then(|http| http.encoding.as_deref()).or(fetcher.options().encoding.as_deref());
+    let forced = label.map(encoding::for_label).transpose()?;
+    Ok(encoding::decode(&bytes, forced, None))
+}
+
+// the content of a file, decompressed if it ends with `.gz` or `.br`
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let file = fs::File::open(path)?;
+    decompress(io::BufReader::new(file), compression(&path.to_string_lossy()))
+}
+
+// the compression of a file or of a URL path by its extension, `gz` or `br`
+fn compression(path: &str) -> Option<&'static str> {
+    if path.ends_with(".gz") {
+        Some("gz")
+    } else if path.ends_with(".br") {
+        Some("br")
+    } else {
+        None
+    }
+}
+
+fn decompress<R: Read>(mut reader: R, compression: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    match compression {
+        Some("gz") => flate2::read::MultiGzDecoder::new(reader).read_to_end(&mut bytes)?,
+        Some("br") => brotli::Decompressor::new(reader, 64 * 1024).read_to_end(&mut bytes)?,
+        _ => reader.read_to_end(&mut bytes)?
+    };
+    Ok(bytes)
+}
+
+fn read_bytes_from_stdin() -> Result<Vec<u8>, Error> {
+    let mut buff = Vec::new();
+    io::stdin().read_to_end(&mut buff).map_err(|err| Error::Io(format!("cannot read the HTML from stdin: {}", err)))?;
+    Ok(buff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::Extractor;
+    use crate::http::Auth;
+    use crate::idents;
+    use crate::table::TableLayout;
+    use crate::test_server::{Response, TestServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const TEMPLATE: &str = r#"[{"object_id": "names", "css_selector": "li", "properties": [{"id": "name", "css_selector": "b", "value_type": "Str"}]}]"#;
+
+    fn run(output_format: OutputFormat, pages: &[(&str, Option<&str>)]) -> (Output, BatchSummary, String) {
+        let template = CompiledTemplate::compile(&idents::from_json_str(TEMPLATE).unwrap()).unwrap();
+        let mut batch = Batch::new(&template, output_format);
+        let mut written = Vec::new();
+        for (name, page) in pages {
+            let result = match page {
+                Some(html) => Ok(Extractor::new(html, output_format).extract(&template).unwrap()),
+                None => Err(Error::Io(format!("cannot read {}", name)))
+            };
+            batch.add(&Source::parse(name), result, &mut written).unwrap();
+        }
+        let (output, summary) = batch.finish().unwrap();
+        (output, summary, String::from_utf8(written).unwrap())
+    }
+
+    #[test]
+    fn source_parse() {
+        assert_eq!(Source::parse("https://a.com/x"), Source::Url("https://a.com/x".to_string()));
+        assert_eq!(Source::parse("pages/a.html"), Source::File(PathBuf::from("pages/a.html")));
+        assert_eq!(Source::Stdin.to_string(), "-");
+        assert_eq!(Source::parse("https://a.com/x").host(), Some("a.com:443".to_string()));
+        assert_eq!(Source::parse("http://a.com:8080/x").host(), Some("a.com:8080".to_string()));
+        assert_eq!(Source::parse("pages/a.html").host(), None);
+    }
+
+    #[test]
+    fn input_parse() {
+        assert_eq!(Input::parse("pages/a.html"), Ok(Source::File(PathBuf::from("pages/a.html")).into()));
+        let input = Input::parse(r#"{"url": "https://a.com/", "headers": {"Accept-Language": "fr"}, "auth": {"basic": {"user": "ann"}}}"#).unwrap();
+        assert_eq!(input.source, Source::Url("https://a.com/".to_string()));
+        let http = input.http.unwrap();
+        assert_eq!(http.headers.get("Accept-Language"), Some(&"fr".to_string()));
+        assert_eq!(http.auth, Some(Auth::Basic { user: "ann".to_string(), password: None }));
+        assert!(Input::parse(r#"{"headers": {}}"#).is_err());
+        assert!(Input::parse(r#"{"url": "https://a.com/", "useragent": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn batch_json_tags_sources() {
+        let (output, summary, _) = run(OutputFormat::Json, &[("a.html", Some("<li><b>Ada</b></li>")), ("missing.html", None), ("b.html", Some("<p></p>"))]);
+        assert_eq!(output, Output::Json(r#"[{"source":"a.html","object_id":"names","records":[{"name":"Ada"}]},{"source":"b.html","object_id":"names","records":[]}]"#.to_string()));
+        assert_eq!(summary, BatchSummary { total: 3, failed: 1, matched: 1 });
+        assert_eq!(summary.to_string(), "3 inputs: 2 succeeded (1 with matches), 1 failed");
+    }
+
+    #[test]
+    fn batch_ndjson_streams() {
+        let (output, _, written) = run(OutputFormat::Ndjson, &[("a.html", Some("<li><b>Ada</b></li><li><b>Alan</b></li>")), ("https://b.com/", Some("<li><b>Bob</b></li>"))]);
+        assert_eq!(output, Output::Empty);
+        assert_eq!(written, r#"{"source":"a.html","object_id":"names","record":{"name":"Ada"}}
+{"source":"a.html","object_id":"names","record":{"name":"Alan"}}
+{"source":"https://b.com/","object_id":"names","record":{"name":"Bob"}}
+"#);
+    }
+
+    #[test]
+    fn batch_tables_have_source_column() {
+        let pages = [("a.html", Some("<li><b>Ada</b></li>")), ("b.html", Some("<li><b>Bob</b></li>"))];
+        let (output, _, _) = run(OutputFormat::Csv(TableLayout::Stream), &pages);
+        assert_eq!(output, Output::Csv("object_id,source,name\nnames,a.html,Ada\nnames,b.html,Bob\n".to_string()));
+        let (output, _, _) = run(OutputFormat::Csv(TableLayout::PerObject), &pages);
+        assert_eq!(output, Output::Files(vec![("names.csv".to_string(), "source,name\na.html,Ada\nb.html,Bob\n".to_string())]));
+    }
+
+    #[test]
+    fn batch_text_one_line_per_input() {
+        let (output, _, _) = run(OutputFormat::Text, &[("a.html", Some("<li><b>Ada</b></li>")), ("b.html", Some("<li><b>Bob</b></li>"))]);
+        assert_eq!(output, Output::Text("Ada\nBob".to_string()));
+    }
+
+    #[test]
+    fn run_sources_concurrently() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (server_active, server_peak) = (active.clone(), peak.clone());
+        let server = TestServer::start(move |request| {
+            let now = server_active.fetch_add(1, Ordering::SeqCst) + 1;
+            server_peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(30));
+            server_active.fetch_sub(1, Ordering::SeqCst);
+            Response::html(&format!("<li><b>{}</b></li>", &request.path[1..]))
+        });
+        let mut inputs: Vec<Input> = (0..8).map(|i| Source::Url(server.url(&format!("/page{}", i))).into()).collect();
+        inputs.insert(3, Source::File(PathBuf::from("/nonexistent/page.html")).into());
+        let template = CompiledTemplate::compile(&idents::from_json_str(TEMPLATE).unwrap()).unwrap();
+        let options = PoolOptions { concurrency: 6, per_host: 3, ordered: true, ..Default::default() };
+        let mut written = Vec::new();
+        let mut failed = Vec::new();
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        let (output, summary) = run_inputs(&inputs, &fetcher, &template, OutputFormat::Ndjson, &options, &mut written, |source, _| failed.push(source.clone())).unwrap();
+
+        assert_eq!(output, Output::Empty);
+        assert_eq!(summary, BatchSummary { total: 9, failed: 1, matched: 8 });
+        assert_eq!(failed, vec![inputs[3].source.clone()]);
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        let names: Vec<String> = String::from_utf8(written).unwrap().lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["record"]["name"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(names, (0..8).map(|i| format!("page{}", i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn host_limits_cover_every_request() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (server_active, server_peak) = (active.clone(), peak.clone());
+        let server = TestServer::start(move |request| {
+            let now = server_active.fetch_add(1, Ordering::SeqCst) + 1;
+            server_peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            server_active.fetch_sub(1, Ordering::SeqCst);
+            match request.path.strip_prefix("/list") {
+                Some(index) => Response::html(&format!("<li><a href='/detail{}'>{}</a></li>", index, index)),
+                None => Response::html(&format!("<main><b>{}</b></main>", &request.path[1..]))
+            }
+        });
+        let template = r#"[{"object_id": "names", "css_selector": "li", "properties": [{"id": "link", "css_selector": "a", "value_type": "Url", "value_from": {"Property": "href"}}],
+            "follow": {"property": "link", "object": {"object_id": "detail", "css_selector": "main", "properties": [{"id": "name", "css_selector": "b", "value_type": "Str"}]}}}]"#;
+        let template = CompiledTemplate::compile(&idents::from_json_str(template).unwrap()).unwrap();
+        let inputs: Vec<Input> = (0..4).map(|i| Source::Url(server.url(&format!("/list{}", i))).into()).collect();
+        // the pool runs the four inputs at once, the fetcher holds their pages and details to one at a time
+        let options = PoolOptions { concurrency: 4, per_host: 4, ..Default::default() };
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap().host_limits(&PoolOptions { per_host: 1, ..Default::default() });
+        let mut written = Vec::new();
+        let (_, summary) = run_inputs(&inputs, &fetcher, &template, OutputFormat::Ndjson, &options, &mut written, |_, _| {}).unwrap();
+        assert_eq!(summary, BatchSummary { total: 4, failed: 0, matched: 4 });
+        assert_eq!(server.requests().len(), 8);
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn read_html_decodes_files() {
+        let path = std::env::temp_dir().join(format!("dessert2-latin-{}.html", std::process::id()));
+        fs::write(&path, b"<meta charset=\"windows-1252\"><p>caf\xE9</p>").unwrap();
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        let input: Input = Source::File(path.clone()).into();
+        assert_eq!(read_html(&input, &fetcher).unwrap(), "<meta charset=\"windows-1252\"><p>café</p>");
+        let forced = Input { http: Some(HttpOptions { encoding: Some("koi8-r".to_string()), ..Default::default() }), ..input };
+        assert_eq!(read_html(&forced, &fetcher).unwrap(), "<meta charset=\"windows-1252\"><p>caf\u{0418}</p>");
+        fs::remove_file(&path).unwrap();
+    }
+}
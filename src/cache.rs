@@ -0,0 +1,138 @@
+use crate::error::Error;
+use crate::http::HttpOptions;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A response kept in the cache.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub url: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// Kept in a file of its own, next to the metadata.
+    #[serde(skip)]
+    pub body: Vec<u8>
+}
+
+/// An on-disk cache of the fetched pages. Every response is stored as `<key>.json`, its
+/// metadata, and `<key>.body`, where the key is a hash of the URL and of the request headers.
+/// A cached page is revalidated with `If-None-Match` and `If-Modified-Since`; offline, it is used
+/// as it is and a page that is not cached is an error.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    offline: bool
+}
+
+impl Cache {
+    pub fn new<P: Into<PathBuf>>(dir: P, offline: bool) -> Result<Self, Error> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|err| Error::Io(format!("cannot create the cache `{}`: {}", dir.display(), err)))?;
+        Ok(Self { dir, offline })
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// The key of a request: the URL, and the headers, user agent, credentials and `cookie`
+    /// header it is sent with, which may change the page.
+    pub fn key(url: &str, options: &HttpOptions, cookie: Option<&str>) -> String {
+        let mut headers: Vec<String> = options.headers.iter()
+            .map(|(name, value)| format!("{}: {}", name.to_ascii_lowercase(), value))
+            .collect();
+        headers.sort();
+        let text = format!("{}\n{}\n{:?}\n{:?}\n{:?}", url, headers.join("\n"), options.user_agent, options.auth, cookie);
+        format!("{:016x}", fnv1a(text.as_bytes()))
+    }
+
+    /// The cached response of the key. A missing or unreadable entry is a miss.
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        let meta = fs::read_to_string(self.dir.join(format!("{}.json", key))).ok()?;
+        let mut response: CachedResponse = serde_json::from_str(&meta).ok()?;
+        response.body = fs::read(self.dir.join(format!("{}.body", key))).ok()?;
+        Some(response)
+    }
+
+    /// Store the response. The files are written aside and renamed, so that the workers of a
+    /// batch never read a partial entry.
+    pub fn put(&self, key: &str, response: &CachedResponse) -> Result<(), Error> {
+        let meta = serde_json::to_string_pretty(response).map_err(|err| Error::Io(err.to_string()))?;
+        self.write(&format!("{}.body", key), &response.body)
+            .and_then(|_| self.write(&format!("{}.json", key), meta.as_bytes()))
+            .map_err(|err| Error::Io(format!("cannot write to the cache `{}`: {}", self.dir.display(), err)))
+    }
+
+    fn write(&self, name: &str, content: &[u8]) -> io::Result<()> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let temp = self.dir.join(format!("{}.{}-{}.tmp", name, process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        fs::write(&temp, content)?;
+        fs::rename(&temp, self.dir.join(name))
+    }
+}
+
+// the 64-bit FNV-1a hash, stable across runs and versions unlike the `Hash` of the standard library
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Auth;
+
+    #[test]
+    fn keys() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        let options = HttpOptions::default();
+        let key = Cache::key("https://a.com/", &options, None);
+        assert_eq!(key.len(), 16);
+        assert_eq!(key, Cache::key("https://a.com/", &options, None));
+        assert_ne!(key, Cache::key("https://a.com/?page=2", &options, None));
+        let mut french = HttpOptions::default();
+        french.headers.insert("Accept-Language".to_string(), "fr".to_string());
+        assert_ne!(key, Cache::key("https://a.com/", &french, None));
+        let mut french_lowercase = HttpOptions::default();
+        french_lowercase.headers.insert("accept-language".to_string(), "fr".to_string());
+        assert_eq!(Cache::key("https://a.com/", &french, None), Cache::key("https://a.com/", &french_lowercase, None));
+        let bearer = HttpOptions { auth: Some(Auth::Bearer("t0k".to_string())), ..Default::default() };
+        assert_ne!(key, Cache::key("https://a.com/", &bearer, None));
+        // the sessions of two cookie jars do not share their pages
+        let session = Cache::key("https://a.com/", &options, Some("session=abc"));
+        assert_ne!(key, session);
+        assert_ne!(session, Cache::key("https://a.com/", &options, Some("session=def")));
+    }
+
+    #[test]
+    fn put_and_get() {
+        let dir = std::env::temp_dir().join(format!("dessert2-cache-{}", process::id()));
+        let cache = Cache::new(&dir, false).unwrap();
+        assert_eq!(cache.get("0123"), None);
+        let response = CachedResponse {
+            url: "https://a.com/".to_string(),
+            status: 200,
+            content_type: Some("text/html".to_string()),
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+            body: b"<p>a</p>".to_vec()
+        };
+        cache.put("0123", &response).unwrap();
+        assert_eq!(cache.get("0123"), Some(response));
+        fs::write(dir.join("0123.json"), "{").unwrap();
+        assert_eq!(cache.get("0123"), None);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use crate::params::ParamError;
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, X_USER_DEFINED};
+use regex::bytes::Regex;
+
+use std::sync::OnceLock;
+
+/// How much of a document is searched for a `<meta>` charset, as browsers do.
+const META_PRESCAN_LEN: usize = 1024;
+
+/// The encoding of an `--encoding` label, like `shift_jis`, `gbk` or `windows-1252`.
+pub fn for_label(label: &str) -> Result<&'static Encoding, ParamError> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| ParamError::InvalidValue {
+        option: "encoding".to_string(),
+        value: label.to_string(),
+        expected: "an encoding label like `utf-8`, `shift_jis` or `windows-1252`".to_string()
+    })
+}
+
+/// Decode an HTML document. The encoding is, in this order of priority: `forced`, the byte order
+/// mark, the charset of the `Content-Type` header, the `<meta>` charset of the document, and
+/// UTF-8. Invalid sequences are replaced with U+FFFD instead of failing.
+pub fn decode(bytes: &[u8], forced: Option<&'static Encoding>, content_type: Option<&str>) -> String {
+    let (encoding, bom_len) = match (forced, Encoding::for_bom(bytes)) {
+        (Some(forced), Some((bom, len))) if forced == bom => (forced, len),
+        (Some(forced), _) => (forced, 0),
+        (None, Some((bom, len))) => (bom, len),
+        (None, None) => (content_type.and_then(charset_of_content_type)
+            .or_else(|| meta_charset(bytes))
+            .unwrap_or(UTF_8), 0)
+    };
+    encoding.decode_without_bom_handling(&bytes[bom_len..]).0.into_owned()
+}
+
+/// The `charset` parameter of a `Content-Type` header, like `text/html; charset="Shift_JIS"`.
+pub fn charset_of_content_type(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("charset") {
+            return None;
+        }
+        Encoding::for_label(value.trim().trim_matches(|c| c == '"' || c == '\'').as_bytes())
+    })
+}
+
+// `<meta charset="...">` or `<meta http-equiv="Content-Type" content="...; charset=...">` in the
+// start of the document. A document that says it is UTF-16 while being read as bytes is not, so
+// it is taken as UTF-8 like browsers do.
+fn meta_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    static META: OnceLock<Regex> = OnceLock::new();
+    let meta = META.get_or_init(|| Regex::new(r#"(?i)<meta\s[^>]*?charset\s*=\s*["']?\s*([a-z0-9_:.+-]+)"#).unwrap());
+    let head = &bytes[..bytes.len().min(META_PRESCAN_LEN)];
+    let label = meta.captures(head)?.get(1)?.as_bytes();
+    match Encoding::for_label(label)? {
+        encoding if encoding == UTF_16LE || encoding == UTF_16BE => Some(UTF_8),
+        encoding if encoding == X_USER_DEFINED => Some(encoding_rs::WINDOWS_1252),
+        encoding => Some(encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, SHIFT_JIS, WINDOWS_1252};
+
+    #[test]
+    fn decode_by_priority() {
+        let (sjis, _, _) = SHIFT_JIS.encode("<p>日本語</p>");
+        let meta = [b"<html><head><meta charset=\"shift_jis\"></head>".as_ref(), &sjis].concat();
+        assert_eq!(decode(&meta, None, None), "<html><head><meta charset=\"shift_jis\"></head><p>日本語</p>");
+        assert_eq!(decode(&sjis, None, Some("text/html; charset=Shift_JIS")), "<p>日本語</p>");
+        assert_eq!(decode(&sjis, Some(SHIFT_JIS), Some("text/html; charset=utf-8")), "<p>日本語</p>");
+        // the header wins over the meta
+        let (gbk, _, _) = GBK.encode("<meta charset=\"utf-8\"><p>中文</p>");
+        assert_eq!(decode(&gbk, None, Some("text/html;charset=\"gbk\"")), "<meta charset=\"utf-8\"><p>中文</p>");
+        // the BOM wins over the header, and is removed
+        let bom = [b"\xEF\xBB\xBF".as_ref(), "<p>é</p>".as_bytes()].concat();
+        assert_eq!(decode(&bom, None, Some("text/html; charset=windows-1252")), "<p>é</p>");
+        assert_eq!(decode(&bom, Some(UTF_8), None), "<p>é</p>");
+    }
+
+    #[test]
+    fn decode_meta_http_equiv() {
+        let (latin, _, _) = WINDOWS_1252.encode("<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=iso-8859-1\"><p>café</p>");
+        assert_eq!(decode(&latin, None, None), "<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=iso-8859-1\"><p>café</p>");
+        assert_eq!(decode(b"<meta charset='utf-16'><p>x</p>", None, None), "<meta charset='utf-16'><p>x</p>");
+        // without any hint, invalid UTF-8 is replaced
+        assert_eq!(decode(b"<p>caf\xE9</p>", None, None), "<p>caf\u{FFFD}</p>");
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(for_label("Shift_JIS").unwrap(), SHIFT_JIS);
+        assert_eq!(for_label("latin1").unwrap(), WINDOWS_1252);
+        assert!(matches!(for_label("klingon"), Err(ParamError::InvalidValue { .. })));
+        assert_eq!(charset_of_content_type("text/html"), None);
+        assert_eq!(charset_of_content_type("text/html; Charset = 'GBK'"), Some(GBK));
+    }
+}
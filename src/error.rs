@@ -0,0 +1,121 @@
+use crate::extract::ExtractError;
+use crate::params::ParamError;
+
+use std::fmt;
+use std::io;
+
+/// The error of a run. Every category has its own exit code, so that scripts can tell "the page
+/// had no matches" from "the network failed" from "the template is invalid":
+///
+/// | code | category                                                                         |
+/// |------|----------------------------------------------------------------------------------|
+/// | 1    | `NoMatches`: the template matched nothing in the document                        |
+/// | 2    | `Args`: invalid command line arguments                                           |
+/// | 3    | `Template`: the template cannot be parsed or validated                           |
+/// | 4    | `Io`: reading the template file or the input, or writing the output, failed      |
+/// | 5    | `Http`: fetching the URL failed                                                  |
+/// | 6    | `Extract`: the extraction itself failed                                          |
+/// | 7    | `Batch`: some inputs of a batch failed, the others are output                    |
+/// | 8    | `RobotsBlocked`: the robots.txt of the site disallows the URL                    |
+#[derive(Debug)]
+pub enum Error {
+    NoMatches,
+    Args(ParamError),
+    Template(String),
+    Io(String),
+    Http(String),
+    Extract(ExtractError),
+    Batch { failed: usize, total: usize },
+    RobotsBlocked(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::NoMatches => 1,
+            Self::Args(_) => 2,
+            Self::Template(_) => 3,
+            Self::Io(_) => 4,
+            Self::Http(_) => 5,
+            Self::Extract(_) => 6,
+            Self::Batch { .. } => 7,
+            Self::RobotsBlocked(_) => 8,
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Self::NoMatches => write!(f, "the template matched nothing in the document"),
+            Self::Args(err) => write!(f, "{}\n\nTry `dessert2 --help` for more information.", err),
+            Self::Template(reason) => write!(f, "invalid template: {}", reason),
+            Self::Io(reason) => write!(f, "{}", reason),
+            Self::Http(reason) => write!(f, "cannot fetch the page: {}", reason),
+            Self::Extract(err) => write!(f, "extraction failed: {}", err),
+            Self::Batch { failed, total } => write!(f, "{} of {} inputs failed", failed, total),
+            Self::RobotsBlocked(reason) => write!(f, "blocked by robots.txt: {}", reason),
+        }
+    }
+}
+
+impl From<ParamError> for Error {
+    fn from(item: ParamError) -> Self {
+        Error::Args(item)
+    }
+}
+
+/// XPath errors are errors of the template.
+impl From<ExtractError> for Error {
+    fn from(item: ExtractError) -> Self {
+        match item {
+            ExtractError::InvalidTemplate(errors) => Error::Template(errors.join("\n  ")),
+            ExtractError::XPath(reason) => Error::Template(reason),
+            ExtractError::Io(reason) => Error::Io(reason),
+            _ => Error::Extract(item)
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(item: io::Error) -> Self {
+        Error::Io(format!("{}", item))
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(item: reqwest::Error) -> Self {
+        Error::Http(format!("{}", item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            Error::NoMatches,
+            Error::Args(ParamError::MissingTemplate),
+            Error::Template(String::new()),
+            Error::Io(String::new()),
+            Error::Http(String::new()),
+            Error::Extract(ExtractError::Unsupported(String::new())),
+            Error::Batch { failed: 1, total: 2 },
+            Error::RobotsBlocked(String::new()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        codes.dedup();
+        assert_eq!(codes, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn extract_error_category() {
+        assert_eq!(Error::from(ExtractError::XPath("expected a node-set".to_string())).exit_code(), 3);
+        assert_eq!(Error::from(ExtractError::Io("broken pipe".to_string())).exit_code(), 4);
+        assert_eq!(Error::from(ExtractError::SerdeJson("oops".to_string())).exit_code(), 6);
+    }
+}
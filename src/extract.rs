@@ -1,65 +1,337 @@
 use crate::output::Output;
-use crate::idents::{IdentObj, IdentityValueType, ValueFrom};
+use crate::table::{self, Delimiter, TableLayout};
+use crate::idents::{Cardinality, CaptureGroup, IdentObj, IdentityValueType, ValueFrom, Whitespace};
+use crate::template::{CompiledObject, CompiledProperty, CompiledSelector, CompiledTemplate, CompiledTransform};
+use crate::xpath::{DocumentOrder, Value, XNode, XPathError};
+use indexmap::IndexMap;
 use scraper::{ElementRef, Html, Selector};
+use url::Url;
+use serde::de::{self, DeserializeOwned, Deserializer, MapAccess, SeqAccess, Visitor};
 use serde::{Deserialize, Serialize, Serializer};
 
 use std::error::Error;
+use std::io::{self, Write};
 use std::fmt;
-use std::collections::HashMap;
 use std::num::{ParseFloatError, ParseIntError};
+use std::convert::TryFrom;
 use std::str::ParseBoolError;
+use std::sync::OnceLock;
 
-/// Extract the text from the source(Html), return it as Output
-///
-pub struct Extractor<'a> {
-    text: &'a str,
-    output_format: OutputFormat
+/// Extract the text from the source(Html), either as a structured result with `extract`, or
+/// serialized as Output with `abstract_objs`. The source is parsed once, when the extractor is
+/// created, and every extraction reads the same document.
+pub struct Extractor {
+    document: Html,
+    // the document order of its nodes, for the XPath expressions
+    order: DocumentOrder,
+    output_format: OutputFormat,
+    // the URL the links are resolved against, see `document_base`
+    base_url: Option<Url>
 }
 
-impl<'a> Extractor<'a> {
-    pub fn new(source: &'a str, output_format: OutputFormat) -> Self {
+impl Extractor {
+    pub fn new(source: &str, output_format: OutputFormat) -> Self {
+        let document = Html::parse_document(source);
+        let base_url = document_base(&document, None);
         Self {
-            text: source,
-            output_format
+            document,
+            order: DocumentOrder::default(),
+            output_format,
+            base_url
         }
     }
-    pub fn abstract_objs(&self, idents: &'a Vec<IdentObj>) -> Result<Output, ExtractError> {
-        let intermediate_result = self.abstract_objs_intermediate(idents)?;
-        intermediate_to_output(&intermediate_result, &self.output_format)
-    }
-
-    fn abstract_objs_intermediate(&self, idents: &'a Vec<IdentObj>) -> Result<IntermediateResult, ExtractError> {
-        let mut result = IntermediateResult::new();
-        let parsed = Html::parse_document(self.text);
-        for ident in idents {
-            let mut result_obj = IntermediateObject{ object_id: ident.object_id.clone(), records: Vec::new() };
-            //locate the object by css selector
-            let selector = Selector::parse(&*ident.css_selector)?;
-            let mut dom_obj = parsed.select(&selector);
-            while let Some(obj) = dom_obj.next() {
-                let mut result_props = IntermediateProperty::new();
-                for prop in &ident.properties {
-                    let prop_selector = Selector::parse(&*prop.css_selector)?;
-                    let mut dom_prop = obj.select(&prop_selector);
-                    if let Some(v) = dom_prop.next() {
-                        let mut selected_value = "".to_string();
-                        let val = get_value_from_dom(&v, &prop.value_from);
-                        if let Some(prop_value) = val { selected_value = prop_value };
-                        result_props.insert(prop.id.clone(), convert_string_to_property_value(selected_value, &prop.value_type));
-                    }
-                }
-                result_obj.records.push(result_props);
-                
-            }
-            result.push(result_obj);
+
+    /// The URL of the page, that the `Url` values are resolved against, unless the document has a
+    /// `<base href>`. Without any, relative URLs are kept as they are.
+    pub fn base_url(mut self, base_url: Option<&Url>) -> Self {
+        self.base_url = document_base(&self.document, base_url);
+        self
+    }
+    pub fn abstract_objs(&self, template: &CompiledTemplate) -> Result<Output, ExtractError> {
+        if let OutputFormat::Ndjson = self.output_format {
+            let mut buffer = Vec::new();
+            self.stream_objs(template, &mut buffer)?;
+            return Ok(Output::Ndjson(String::from_utf8_lossy(&buffer).into_owned()));
         }
+        let intermediate_result = self.extract(template)?;
+        intermediate_to_output(&intermediate_result, template.idents(), &self.output_format)
+    }
+
+    /// Extract the records and serialize them with any `serde` serializer, in the same shape as the
+    /// `json` and `yaml` outputs.
+    pub fn serialize_objs<S: Serializer>(&self, template: &CompiledTemplate, serializer: S) -> Result<S::Ok, ExtractError> {
+        let intermediate_result = self.extract(template)?;
+        intermediate_result.serialize(serializer).map_err(|err| ExtractError::Serialize(err.to_string()))
+    }
+
+    /// Write the records as newline-delimited JSON, one `{"object_id": ..., "record": {...}}` line
+    /// per record. Every line is written as soon as its match is processed, without building the
+    /// whole result first. Return the number of records written.
+    pub fn stream_objs<W: Write>(&self, template: &CompiledTemplate, writer: &mut W) -> Result<usize, ExtractError> {
+        let mut count = 0;
+        self.for_each_record(template, |_, object, record| {
+            serde_json::to_writer(&mut *writer, &NdjsonLine { source: None, object_id: &object.object_id, record: &record })?;
+            writer.write_all(b"\n")?;
+            count += 1;
+            Ok(())
+        })?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Extract the records of every object of the template, as a structured result. The objects
+    /// follow the order of the template, and every record holds the properties of its object in
+    /// their declared order.
+    ///
+    /// ```
+    /// use dessert2::{CompiledTemplate, Extractor, IdentObj, Identity, IdentityValueType, OutputFormat, PropertyValue, ValueFrom};
+    ///
+    /// let idents = vec![IdentObj {
+    ///     object_id: "users".to_string(),
+    ///     css_selector: "li".to_string(),
+    ///     properties: vec![Identity {
+    ///         id: "name".to_string(),
+    ///         css_selector: "span.name".to_string(),
+    ///         value_type: IdentityValueType::Str,
+    ///         value_from: ValueFrom::TextContent,
+    ///         ..Default::default()
+    ///     }],
+    ///     ..Default::default()
+    /// }];
+    /// let html = r#"<ul><li><span class="name">Ada</span></li><li><span class="name">Alan</span></li></ul>"#;
+    ///
+    /// let template = CompiledTemplate::compile(&idents)?;
+    /// let result = Extractor::new(html, OutputFormat::Json).extract(&template)?;
+    /// assert_eq!(result[0].object_id, "users");
+    /// assert_eq!(result[0].records[1]["name"], PropertyValue::Str("Alan".to_string()));
+    /// # Ok::<(), dessert2::ExtractError>(())
+    /// ```
+    pub fn extract(&self, template: &CompiledTemplate) -> Result<IntermediateResult, ExtractError> {
+        let mut result: IntermediateResult = template.objects.iter()
+            .map(|object| IntermediateObject{ object_id: object.object_id.clone(), ..Default::default() })
+            .collect();
+        self.for_each_record(template, |index, _, record| {
+            result[index].records.push(record);
+            Ok(())
+        })?;
         Ok(result)
     }
+
+    /// The first link matched by the selector: the `href` of an element, or the value of an
+    /// attribute or of an XPath expression, resolved like the `Url` values.
+    pub(crate) fn link(&self, selector: &CompiledSelector) -> Result<Option<Url>, ExtractError> {
+        let document = Matched::Node(XNode::Node(self.document.tree.root()));
+        let first = match select_matches(&document, selector, &self.page())?.into_iter().next() {
+            Some(first) => first,
+            None => return Ok(None)
+        };
+        let value_from = match &first {
+            Matched::Node(XNode::Node(node)) if ElementRef::wrap(*node).is_some() => ValueFrom::Property("href".to_string()),
+            _ => ValueFrom::TextContent
+        };
+        let href = get_value_from_dom(&first, &value_from).unwrap_or_default();
+        let href = href.trim();
+        if href.is_empty() {
+            return Ok(None);
+        }
+        Ok(match &self.base_url {
+            Some(base) => base.join(href).ok(),
+            None => Url::parse(href).ok()
+        })
+    }
+
+    /// The record of the first element matched by the object, for the detail page of a link.
+    pub(crate) fn first_record(&self, object: &CompiledObject) -> Result<Option<Record>, ExtractError> {
+        let document = Matched::Node(XNode::Node(self.document.tree.root()));
+        let page = self.page();
+        match select_matches(&document, &object.selector, &page)?.into_iter().next() {
+            Some(first) => Ok(Some(abstract_record(&first, &object.properties, &page)?)),
+            None => Ok(None)
+        }
+    }
+
+    fn page(&self) -> Page<'_> {
+        Page { base: self.base_url.as_ref(), order: &self.order }
+    }
+
+    /// Call `f` with the position of the object in the template and every record of it, in the
+    /// order of the objects in the template and of the matches in the document.
+    fn for_each_record<F>(&self, template: &CompiledTemplate, mut f: F) -> Result<(), ExtractError>
+        where F: FnMut(usize, &CompiledObject, Record) -> Result<(), ExtractError> {
+        let document = Matched::Node(XNode::Node(self.document.tree.root()));
+        let page = self.page();
+        for (index, object) in template.objects.iter().enumerate() {
+            //locate the object by its selector
+            for obj in select_matches(&document, &object.selector, &page)? {
+                f(index, object, abstract_record(&obj, &object.properties, &page)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct NdjsonLine<'r> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'r str>,
+    object_id: &'r str,
+    record: &'r Record
+}
+
+/// Write an extracted result as newline-delimited JSON, in the format of `Extractor::stream_objs`,
+/// with the `source` of the objects when they have one. Return the number of records written.
+pub fn write_ndjson<W: Write>(intermediate: &IntermediateResult, writer: &mut W) -> Result<usize, ExtractError> {
+    let mut count = 0;
+    for object in intermediate {
+        for record in &object.records {
+            serde_json::to_writer(&mut *writer, &NdjsonLine { source: object.source.as_deref(), object_id: &object.object_id, record })?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// A match of a selector: a node of the document, or the value of an XPath expression that does
+/// not result in a node-set.
+enum Matched<'a> {
+    Node(XNode<'a>),
+    Value(String)
+}
+
+/// What the matches of a document share: the URL its links are resolved against, and the document
+/// order of its nodes.
+struct Page<'p> {
+    base: Option<&'p Url>,
+    order: &'p DocumentOrder
+}
+
+/// Locate the matches of a selector relative to `context`, in document order.
+fn select_matches<'a>(context: &Matched<'a>, selector: &CompiledSelector, page: &Page) -> Result<Vec<Matched<'a>>, ExtractError> {
+    let context = match context {
+        Matched::Node(node) => *node,
+        Matched::Value(_) => return Ok(Vec::new())
+    };
+    match selector {
+        CompiledSelector::Css(selector) => {
+            Ok(match context {
+                XNode::Node(node) => match ElementRef::wrap(node) {
+                    Some(elm_ref) => elm_ref.select(selector).map(|v| Matched::Node(XNode::Node(*v))).collect(),
+                    None => node.descendants().filter_map(ElementRef::wrap).filter(|v| selector.matches(v)).map(|v| Matched::Node(XNode::Node(*v))).collect()
+                },
+                XNode::Attribute(..) => Vec::new()
+            })
+        },
+        CompiledSelector::XPath(xpath) => {
+            Ok(match xpath.evaluate_in(context, page.order)? {
+                Value::NodeSet(nodes) => nodes.into_iter().map(Matched::Node).collect(),
+                other => vec![Matched::Value(other.to_xpath_string())]
+            })
+        }
+    }
+}
+
+/// Build one record from the properties located under `matched`. `Object` and `Array` properties
+/// recurse into their own child properties, so records can be nested to any depth.
+fn abstract_record(matched: &Matched, props: &[CompiledProperty], page: &Page) -> Result<Record, ExtractError> {
+    let mut result_props = Record::new();
+    for prop in props {
+        let mut dom_prop = select_matches(matched, &prop.selector, page)?.into_iter();
+        if prop.value_type == IdentityValueType::Array {
+            let items = dom_prop.map(|v| abstract_record(&v, &prop.properties, page)).collect::<Result<Vec<_>, _>>()?;
+            result_props.insert(prop.id.clone(), PropertyValue::Array(items));
+            continue;
+        }
+        let selected = match &prop.cardinality {
+            Cardinality::All => {
+                let items = dom_prop.map(|v| abstract_value(&v, prop, page)).collect::<Result<Vec<_>, _>>()?;
+                result_props.insert(prop.id.clone(), PropertyValue::List(items));
+                continue;
+            },
+            Cardinality::First => dom_prop.next(),
+            Cardinality::Last => dom_prop.last(),
+            Cardinality::Nth(n) => dom_prop.nth(*n)
+        };
+        if let Some(v) = selected {
+            result_props.insert(prop.id.clone(), abstract_value(&v, prop, page)?);
+        }
+    }
+    Ok(result_props)
+}
+
+/// The value of a single match, either a nested record or a typed scalar.
+fn abstract_value(matched: &Matched, prop: &CompiledProperty, page: &Page) -> Result<PropertyValue, ExtractError> {
+    if prop.value_type == IdentityValueType::Object {
+        return Ok(PropertyValue::Object(abstract_record(matched, &prop.properties, page)?));
+    }
+    let selected_value = get_value_from_dom(matched, &prop.value_from).unwrap_or_default();
+    let selected_value = apply_whitespace(selected_value, &prop.whitespace);
+    match apply_transforms(selected_value, &prop.transforms) {
+        Some(value) if prop.value_type == IdentityValueType::Url => Ok(PropertyValue::Str(resolve_url(&value, &prop.value_from, page.base))),
+        Some(value) => Ok(convert_string_to_property_value(value, &prop.value_type)),
+        None => Ok(PropertyValue::NA)
+    }
+}
+
+/// The URL relative links are resolved against: the first `<base href>` of the document, itself
+/// resolved against the URL of the page, or the URL of the page.
+fn document_base(document: &Html, page_url: Option<&Url>) -> Option<Url> {
+    static BASE: OnceLock<Selector> = OnceLock::new();
+    let selector = BASE.get_or_init(|| Selector::parse("base[href]").unwrap());
+    let href = document.select(selector).next().and_then(|base| base.value().attr("href"));
+    match (href.map(str::trim), page_url) {
+        (Some(href), Some(page_url)) => page_url.join(href).ok().or_else(|| Some(page_url.clone())),
+        (Some(href), None) => Url::parse(href).ok(),
+        (None, page_url) => page_url.cloned()
+    }
+}
+
+/// Resolve a link against the base. Every candidate of a `srcset`, like `a.jpg 1x, b.jpg 2x`,
+/// is resolved, with its descriptor kept. Without a base, or when it cannot be resolved, the link
+/// is kept as it is.
+fn resolve_url(value: &str, value_from: &ValueFrom, base: Option<&Url>) -> String {
+    let resolve = |link: &str| match base.and_then(|base| base.join(link).ok()) {
+        Some(url) => url.to_string(),
+        None => link.to_string()
+    };
+    match value_from {
+        ValueFrom::Property(name) if name.eq_ignore_ascii_case("srcset") || name.eq_ignore_ascii_case("imagesrcset") => value.split(',')
+            .map(str::trim)
+            .filter(|candidate| !candidate.is_empty())
+            .map(|candidate| match candidate.split_once(char::is_whitespace) {
+                Some((link, descriptor)) => format!("{} {}", resolve(link), descriptor.trim()),
+                None => resolve(candidate)
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => resolve(value.trim())
+    }
+}
+
+/// Run the value through the transforms of a property. `None` means a `Capture` did not match.
+fn apply_transforms(value: String, transforms: &[CompiledTransform]) -> Option<String> {
+    let mut value = value;
+    for transform in transforms {
+        value = match transform {
+            CompiledTransform::Trim => value.trim().to_string(),
+            CompiledTransform::Replace { regex, with } => regex.replace_all(&value, with.as_str()).into_owned(),
+            CompiledTransform::Capture { regex, group } => {
+                let captured = regex.captures(&value).and_then(|caps| match group {
+                    None => caps.get(0),
+                    Some(CaptureGroup::Index(index)) => caps.get(*index),
+                    Some(CaptureGroup::Name(name)) => caps.name(name)
+                });
+                captured?.as_str().to_string()
+            }
+        }
+    }
+    Some(value)
 }
 
 fn convert_string_to_property_value(value: String, prop_type: &IdentityValueType) -> PropertyValue {
     match prop_type {
-        IdentityValueType::Str => PropertyValue::Str(value),
+        IdentityValueType::Str | IdentityValueType::Url => PropertyValue::Str(value),
         IdentityValueType::Int => {
             let value: Result<i64, ParseIntError> = value.parse();
             match value {
@@ -80,25 +352,59 @@ fn convert_string_to_property_value(value: String, prop_type: &IdentityValueType
                 Ok(bool_value) => PropertyValue::Bool(bool_value),
                 Err(_)=> PropertyValue::NA,
             }
-        }
+        },
+        IdentityValueType::Object | IdentityValueType::Array => PropertyValue::NA
     }
 }
 
-fn get_value_from_dom(elm_ref: &ElementRef, value_from: &ValueFrom) -> Option<String> {
+fn get_value_from_dom(matched: &Matched, value_from: &ValueFrom) -> Option<String> {
+    let node = match matched {
+        Matched::Value(value) => return Some(value.clone()),
+        Matched::Node(node) => node
+    };
+    let elm_ref = match node {
+        XNode::Node(elm) => ElementRef::wrap(*elm),
+        XNode::Attribute(..) => None
+    };
+    // text, comment and attribute nodes only have their string-value
+    let elm_ref = match elm_ref {
+        Some(elm_ref) => elm_ref,
+        None => return match value_from {
+            ValueFrom::Property(_) => None,
+            _ => Some(node.string_value())
+        }
+    };
     match value_from {
-        ValueFrom::InnerText => Some(elm_ref.inner_html()),
-        ValueFrom::Property(prop) => elm_ref.value().attr(&*prop).map(|prop_val|String::from(prop_val))
+        ValueFrom::InnerText | ValueFrom::InnerHtml => Some(elm_ref.inner_html()),
+        ValueFrom::OuterHtml => Some(elm_ref.html()),
+        ValueFrom::TextContent => Some(elm_ref.text().collect()),
+        ValueFrom::OwnText => Some(elm_ref.children().filter_map(|child| child.value().as_text()).map(|text| &**text).collect()),
+        ValueFrom::Property(prop) => elm_ref.value().attr(prop).map(String::from)
     } 
 }
 
-fn intermediate_to_output(intermediate: &IntermediateResult, output_format: &OutputFormat) -> Result<Output, ExtractError> {
+fn apply_whitespace(value: String, whitespace: &Whitespace) -> String {
+    match whitespace {
+        Whitespace::Preserve => value,
+        Whitespace::Trim => value.trim().to_string(),
+        Whitespace::Collapse => value.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Serialize the result. The objects follow the order of the template, and the fields of every
+/// record follow the order of the properties in their `IdentObj`, so the output of a template is
+/// stable from run to run. `Text` is the value of the first property of the first record.
+pub fn intermediate_to_output(intermediate: &IntermediateResult, idents: &[IdentObj], output_format: &OutputFormat) -> Result<Output, ExtractError> {
     match output_format {
         OutputFormat::Json => Ok(Output::Json(serde_json::to_string(intermediate)?)),
         OutputFormat::Yaml => Ok(Output::Yaml(serde_yaml::to_string(intermediate)?)),
+        OutputFormat::Ndjson => Err(ExtractError::Unsupported("NDJSON is written by `Extractor::stream_objs`".to_string())),
+        OutputFormat::Csv(layout) => Ok(intermediate_to_tables(intermediate, idents, *layout, Delimiter::Comma)),
+        OutputFormat::Tsv(layout) => Ok(intermediate_to_tables(intermediate, idents, *layout, Delimiter::Tab)),
         OutputFormat::Text => {
-            if intermediate.len() > 0 {
+            if !intermediate.is_empty() {
                 let records = &intermediate[0].records;
-                if records.len() > 0 {
+                if !records.is_empty() {
                     let record = &records[0];
                     let value = record.values().next();
                     if let Some(v) = value {
@@ -116,19 +422,41 @@ fn intermediate_to_output(intermediate: &IntermediateResult, output_format: &Out
     }
 }
 
-#[allow(dead_code)]
+fn intermediate_to_tables(intermediate: &IntermediateResult, idents: &[IdentObj], layout: TableLayout, delimiter: Delimiter) -> Output {
+    match (layout, delimiter) {
+        (TableLayout::Stream, Delimiter::Comma) => Output::Csv(table::objects_to_table(intermediate, idents, delimiter)),
+        (TableLayout::Stream, Delimiter::Tab) => Output::Tsv(table::objects_to_table(intermediate, idents, delimiter)),
+        // in a batch the objects of the template repeat for every input, they are grouped by id
+        (TableLayout::PerObject, _) => Output::Files(idents.iter()
+            .map(|ident| {
+                let objects: Vec<&IntermediateObject> = intermediate.iter().filter(|object| object.object_id == ident.object_id).collect();
+                (format!("{}.{}", ident.object_id, delimiter.extension()), table::object_to_table(&objects, ident, delimiter))
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OutputFormat {
     Json,
     Yaml,
-    Text
+    Text,
+    Csv(TableLayout),
+    Tsv(TableLayout),
+    Ndjson
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
-enum PropertyValue {
+/// The value of a property in a record. It serializes to the plain value, `NA` to null, and
+/// deserializes back from it: a list of records is an `Array`, any other list a `List`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
     Int(i64),
     Float(f64),
     Str(String),
     Bool(bool),
+    Object(Record),
+    Array(Vec<Record>),
+    List(Vec<PropertyValue>),
     NA
 }
 
@@ -139,18 +467,95 @@ impl Serialize for PropertyValue {
             Self::Float(val) => serializer.serialize_f64(*val),
             Self::Str(val) => serializer.serialize_str(val),
             Self::Bool(val) => serializer.serialize_bool(*val),
+            Self::Object(val) => val.serialize(serializer),
+            Self::Array(val) => val.serialize(serializer),
+            Self::List(val) => val.serialize(serializer),
             Self::NA => serializer.serialize_none() 
         }
     }
 }
 
+impl<'de> Deserialize<'de> for PropertyValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(PropertyValueVisitor)
+    }
+}
+
+struct PropertyValueVisitor;
+
+impl<'de> Visitor<'de> for PropertyValueVisitor {
+    type Value = PropertyValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a property value")
+    }
+
+    fn visit_bool<E: de::Error>(self, val: bool) -> Result<PropertyValue, E> {
+        Ok(PropertyValue::Bool(val))
+    }
+
+    fn visit_i64<E: de::Error>(self, val: i64) -> Result<PropertyValue, E> {
+        Ok(PropertyValue::Int(val))
+    }
+
+    fn visit_u64<E: de::Error>(self, val: u64) -> Result<PropertyValue, E> {
+        i64::try_from(val).map(PropertyValue::Int).map_err(|_| E::custom(format!("{} is out of the range of Int", val)))
+    }
+
+    fn visit_f64<E: de::Error>(self, val: f64) -> Result<PropertyValue, E> {
+        Ok(PropertyValue::Float(val))
+    }
+
+    fn visit_str<E: de::Error>(self, val: &str) -> Result<PropertyValue, E> {
+        Ok(PropertyValue::Str(val.to_string()))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<PropertyValue, E> {
+        Ok(PropertyValue::NA)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<PropertyValue, E> {
+        Ok(PropertyValue::NA)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<PropertyValue, D::Error> {
+        PropertyValue::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<PropertyValue, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        if !items.is_empty() && items.iter().all(|item| matches!(item, PropertyValue::Object(_))) {
+            Ok(PropertyValue::Array(items.into_iter().filter_map(|item| match item {
+                PropertyValue::Object(record) => Some(record),
+                _ => None
+            }).collect()))
+        } else {
+            Ok(PropertyValue::List(items))
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<PropertyValue, A::Error> {
+        let mut record = Record::new();
+        while let Some((key, value)) = map.next_entry()? {
+            record.insert(key, value);
+        }
+        Ok(PropertyValue::Object(record))
+    }
+}
+
 impl fmt::Display for PropertyValue {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.write_str(&*match &self {
+        fmt.write_str(&match &self {
             Self::Int(val) => val.to_string(),
             Self::Float(val) => val.to_string(),
             Self::Str(val) => val.clone(),
             Self::Bool(val) => val.to_string(),
+            Self::Object(val) => serde_json::to_string(val).unwrap_or_default(),
+            Self::Array(val) => serde_json::to_string(val).unwrap_or_default(),
+            Self::List(val) => serde_json::to_string(val).unwrap_or_default(),
             Self::NA => "".to_string()
         })?;
         Ok(())
@@ -159,9 +564,15 @@ impl fmt::Display for PropertyValue {
 
 #[derive(Debug)]
 pub enum ExtractError {
-    ParseSelector(String),
     SerdeJson(String),
     SerdeYaml(String),
+    /// An XPath expression of the template that fails to evaluate, like `count(1)`.
+    XPath(String),
+    Io(String),
+    Unsupported(String),
+    Serialize(String),
+    /// Every error found while compiling a template, prefixed by its path in the template.
+    InvalidTemplate(Vec<String>),
 }
 
 impl Error for ExtractError {}
@@ -169,16 +580,26 @@ impl Error for ExtractError {}
 impl fmt::Display for ExtractError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self {
-            Self::ParseSelector(selector_error) => write!(f, "[Selector Error]: {}", selector_error),
             Self::SerdeJson(json_error) => write!(f, "SerdeJson Error: {}", json_error),
             Self::SerdeYaml(yaml_error) => write!(f, "SerdeYaml Error: {}", yaml_error),
+            Self::XPath(xpath_error) => write!(f, "{}", xpath_error),
+            Self::Io(io_error) => write!(f, "[IO Error]: {}", io_error),
+            Self::Unsupported(reason) => write!(f, "[Unsupported]: {}", reason),
+            Self::Serialize(reason) => write!(f, "[Serialize Error]: {}", reason),
+            Self::InvalidTemplate(errors) => write!(f, "{}", errors.join("\n")),
         }
     }
 }
 
-impl From<cssparser::ParseError<'_, selectors::parser::SelectorParseErrorKind<'_>>> for ExtractError {
-    fn from(item: cssparser::ParseError<'_, selectors::parser::SelectorParseErrorKind<'_>>) -> Self {
-        ExtractError::ParseSelector(format!("[cssparser::ParseError]{:?}", item))
+impl From<XPathError> for ExtractError {
+    fn from(item: XPathError) -> Self {
+        ExtractError::XPath(format!("{}", item))
+    }
+}
+
+impl From<io::Error> for ExtractError {
+    fn from(item: io::Error) -> Self {
+        ExtractError::Io(format!("{}", item))
     }
 }
 
@@ -193,19 +614,69 @@ impl From<serde_yaml::Error> for ExtractError {
         ExtractError::SerdeYaml(format!("[Yaml Error]: {}", item))
     }
 }
-type IntermediateProperty = HashMap<String, PropertyValue>;
-type IntermediateResult = Vec<IntermediateObject>;
+/// A record keeps its properties in the order they are declared in the template.
+pub type Record = IndexMap<String, PropertyValue>;
+/// The objects of a template, in its order.
+pub type IntermediateResult = Vec<IntermediateObject>;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
-struct IntermediateObject {
-    object_id: String,
-    records: Vec<IntermediateProperty>
+/// The records extracted for one `IdentObj`, one for every element its selector matched.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IntermediateObject {
+    /// The input the records were extracted from, set in batch mode only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub object_id: String,
+    pub records: Vec<Record>
+}
+
+impl IntermediateObject {
+    /// Map the records onto a type of the caller, by its `Deserialize` implementation. Fields
+    /// are matched by property id, and `NA` values deserialize as `None`.
+    ///
+    /// ```
+    /// use dessert2::{idents, CompiledTemplate, Extractor, OutputFormat};
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct City {
+    ///     name: String,
+    ///     population: Option<i64>,
+    /// }
+    ///
+    /// let idents = idents::from_json_str(r#"[{"object_id": "cities", "css_selector": "li", "properties": [
+    ///     {"id": "name", "css_selector": "b", "value_type": "Str"},
+    ///     {"id": "population", "css_selector": "i", "value_type": "Int"}
+    /// ]}]"#).unwrap();
+    /// let html = "<ul><li><b>Toronto</b><i>2794356</i></li><li><b>Tiny</b><i>?</i></li></ul>";
+    ///
+    /// let template = CompiledTemplate::compile(&idents)?;
+    /// let result = Extractor::new(html, OutputFormat::Json).extract(&template)?;
+    /// let cities: Vec<City> = result[0].records_as()?;
+    /// assert_eq!(cities[0].name, "Toronto");
+    /// assert_eq!(cities[0].population, Some(2794356));
+    /// assert_eq!(cities[1].population, None);
+    /// # Ok::<(), dessert2::ExtractError>(())
+    /// ```
+    pub fn records_as<T: DeserializeOwned>(&self) -> Result<Vec<T>, ExtractError> {
+        self.records.iter()
+            .map(|record| Ok(serde_json::from_value(serde_json::to_value(record)?)?))
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::idents::Identity;
+    use crate::idents::{CaptureGroup, Identity, SelectorType, Transform};
+    use crate::template::compile_transform;
+
+    fn compile(ids: &[IdentObj]) -> CompiledTemplate {
+        CompiledTemplate::compile(ids).unwrap()
+    }
+
+    fn compile_transforms(transforms: &[Transform]) -> Vec<CompiledTransform> {
+        transforms.iter().map(|transform| compile_transform(transform, "", &mut Vec::new()).unwrap()).collect()
+    }
     
     fn construct_ident_obj() -> IdentObj {
         IdentObj {
@@ -215,13 +686,16 @@ mod tests {
                     id: "email".to_owned(),
                     css_selector: "div#email".to_owned(),
                     value_type: IdentityValueType::Str,
-                    value_from: ValueFrom::InnerText
+                    value_from: ValueFrom::InnerText,
+                    ..Default::default()
             }, Identity {
                     id: "phone-number".to_owned(),
                     css_selector: "div#phone_number".to_owned(),
                     value_type: IdentityValueType::Str,
-                    value_from: ValueFrom::InnerText
-            }]
+                    value_from: ValueFrom::InnerText,
+                    ..Default::default()
+            }],
+            ..Default::default()
         }
     }
     fn construct_ident_obj_single_prop() -> IdentObj {
@@ -232,8 +706,10 @@ mod tests {
                     id: "email".to_owned(),
                     css_selector: "div#email".to_owned(),
                     value_type: IdentityValueType::Str,
-                    value_from: ValueFrom::InnerText
-            }]
+                    value_from: ValueFrom::InnerText,
+                    ..Default::default()
+            }],
+            ..Default::default()
         }
     }
     fn construct_multiple_ident_obj() -> Vec<IdentObj> {
@@ -244,8 +720,10 @@ mod tests {
                     id: "email".to_owned(),
                     css_selector: "div#email".to_owned(),
                     value_type: IdentityValueType::Str,
-                    value_from: ValueFrom::InnerText
-            }]
+                    value_from: ValueFrom::InnerText,
+                    ..Default::default()
+            }],
+            ..Default::default()
         },
         IdentObj {
             object_id: "book-info".to_owned(),
@@ -254,8 +732,10 @@ mod tests {
                     id: "isn".to_owned(),
                     css_selector: "div#isn".to_owned(),
                     value_type: IdentityValueType::Str,
-                    value_from: ValueFrom::InnerText
-            }]
+                    value_from: ValueFrom::InnerText,
+                    ..Default::default()
+            }],
+            ..Default::default()
         }]
     }
 
@@ -298,44 +778,44 @@ mod tests {
     #[test]
     fn abstract_intermediate() {
         let ids = vec![construct_ident_obj()];
-        let extractor = Extractor { text: "<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", output_format: OutputFormat::Yaml};
-        let result = extractor.abstract_objs_intermediate(&ids).unwrap();
+        let extractor = Extractor::new("<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", OutputFormat::Yaml);
+        let result = extractor.extract(&compile(&ids)).unwrap();
 
-        let mut expected_props = HashMap::new();
+        let mut expected_props = IndexMap::new();
         expected_props.insert("email".to_string(), PropertyValue::Str("abc@abc.com".to_string()));
         expected_props.insert("phone-number".to_string(), PropertyValue::Str("13344445555".to_string()));
-        let expected_output = vec![ IntermediateObject {object_id: "user-info".to_string(), records: vec![expected_props]}];
+        let expected_output = vec![ IntermediateObject {object_id: "user-info".to_string(), records: vec![expected_props], ..Default::default()}];
         assert_eq!(result, expected_output);
     }
 
     #[test]
     fn abstract_intermediate_multiple_records() {
         let ids = vec![construct_ident_obj()];
-        let extractor = Extractor { text: "<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div><div id=\"user_info\"><div id=\"email\">def@abc.com</div><div id=\"phone_number\">23344445555</div></div></body></html>", output_format: OutputFormat::Yaml};
-        let result = extractor.abstract_objs_intermediate(&ids).unwrap();
+        let extractor = Extractor::new("<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div><div id=\"user_info\"><div id=\"email\">def@abc.com</div><div id=\"phone_number\">23344445555</div></div></body></html>", OutputFormat::Yaml);
+        let result = extractor.extract(&compile(&ids)).unwrap();
 
-        let mut expected_props_group1 = HashMap::new();
+        let mut expected_props_group1 = IndexMap::new();
         expected_props_group1.insert("email".to_string(), PropertyValue::Str("abc@abc.com".to_string()));
         expected_props_group1.insert("phone-number".to_string(), PropertyValue::Str("13344445555".to_string()));
-        let mut expected_props_group2 = HashMap::new();
+        let mut expected_props_group2 = IndexMap::new();
         expected_props_group2.insert("email".to_string(), PropertyValue::Str("def@abc.com".to_string()));
         expected_props_group2.insert("phone-number".to_string(), PropertyValue::Str("23344445555".to_string()));
-        let expected_output = vec![ IntermediateObject {object_id: "user-info".to_string(), records: vec![expected_props_group1, expected_props_group2]}];
+        let expected_output = vec![ IntermediateObject {object_id: "user-info".to_string(), records: vec![expected_props_group1, expected_props_group2], ..Default::default()}];
         assert_eq!(result, expected_output);
     }
 
     #[test]
     fn abstrct_intermediate_multiple_objects() {
         let ids = construct_multiple_ident_obj();
-        let extractor = Extractor { text: "<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div><div id=\"book_info\"><div id=\"isn\">123456</div><div id=\"price\">178.55</div></div></body></html>", output_format: OutputFormat::Yaml};
-        let result = extractor.abstract_objs_intermediate(&ids).unwrap();
+        let extractor = Extractor::new("<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div><div id=\"book_info\"><div id=\"isn\">123456</div><div id=\"price\">178.55</div></div></body></html>", OutputFormat::Yaml);
+        let result = extractor.extract(&compile(&ids)).unwrap();
 
-        let mut expected_props_for_obj1 = HashMap::new();
+        let mut expected_props_for_obj1 = IndexMap::new();
         expected_props_for_obj1.insert("email".to_string(), PropertyValue::Str("abc@abc.com".to_string()));
-        let mut expected_props_for_obj2 = HashMap::new();
+        let mut expected_props_for_obj2 = IndexMap::new();
         expected_props_for_obj2.insert("isn".to_string(), PropertyValue::Str("123456".to_string()));
-        let expected_output = vec![ IntermediateObject {object_id: "user-info".to_string(), records: vec![expected_props_for_obj1]},
-                IntermediateObject {object_id: "book-info".to_string(), records: vec![expected_props_for_obj2]}];
+        let expected_output = vec![ IntermediateObject {object_id: "user-info".to_string(), records: vec![expected_props_for_obj1], ..Default::default()},
+                IntermediateObject {object_id: "book-info".to_string(), records: vec![expected_props_for_obj2], ..Default::default()}];
         assert_eq!(result, expected_output);
     }
 
@@ -348,11 +828,13 @@ mod tests {
                     id: "link".to_owned(),
                     css_selector: "a".to_owned(),
                     value_type: IdentityValueType::Str,
-                    value_from: ValueFrom::Property("href".to_string())
-            }]
+                    value_from: ValueFrom::Property("href".to_string()),
+                    ..Default::default()
+            }],
+            ..Default::default()
         }];
-        let extractor = Extractor { text: "<html><head></head><body><div id=\"user_info\"><a href=\"mail_to:abc@abc.com\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", output_format: OutputFormat::Yaml};
-        let result = extractor.abstract_objs(&ids).unwrap();
+        let extractor = Extractor::new("<html><head></head><body><div id=\"user_info\"><a href=\"mail_to:abc@abc.com\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", OutputFormat::Yaml);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
         assert_eq!(result, Output::Yaml(r#"---
 - object_id: user-info
   records:
@@ -360,11 +842,34 @@ mod tests {
 "#.to_string()));
     }
 
+    #[test]
+    fn abstract_inner_text_yaml() {
+        let ids = vec![ IdentObj {
+            object_id: "user-info".to_owned(),
+            css_selector: "div#user_info".to_owned(),
+            properties: vec![Identity {
+                    id: "address".to_owned(),
+                    css_selector: "div#address".to_owned(),
+                    value_type: IdentityValueType::Str,
+                    value_from: ValueFrom::InnerText,
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        let extractor = Extractor::new(r#"<html><head></head><body><div id="user_info"><div id="address">Ontario, Canada</div><div id="email">abc@abc.com</div></div></body></html>"#, OutputFormat::Yaml);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Yaml(r#"---
+- object_id: user-info
+  records:
+    - address: "Ontario, Canada"
+"#.to_string()));
+    }
+
     #[test]
     fn abstract_single_yaml() {
         let ids = vec![construct_ident_obj_single_prop()];
-        let extractor = Extractor { text: "<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", output_format: OutputFormat::Yaml};
-        let result = extractor.abstract_objs(&ids).unwrap();
+        let extractor = Extractor::new("<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", OutputFormat::Yaml);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
         assert_eq!(result, Output::Yaml(r#"---
 - object_id: user-info
   records:
@@ -375,15 +880,15 @@ mod tests {
     #[test]
     fn abstract_single_json() {
         let ids = vec![construct_ident_obj_single_prop()];
-        let extractor = Extractor { text: "<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", output_format: OutputFormat::Json};
-        let result = extractor.abstract_objs(&ids).unwrap();
+        let extractor = Extractor::new("<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", OutputFormat::Json);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
         assert_eq!(result, Output::Json("[{\"object_id\":\"user-info\",\"records\":[{\"email\":\"abc@abc.com\"}]}]".to_string()));
     }
     #[test]
     fn abstract_multiple_records() {
         let ids = construct_multiple_ident_obj();
-        let extractor = Extractor { text: "<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div><div id=\"book_info\"><div id=\"isn\">123456</div><div id=\"price\">178.55</div></div></body></html>", output_format: OutputFormat::Yaml};
-        let result = extractor.abstract_objs(&ids).unwrap();
+        let extractor = Extractor::new("<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div><div id=\"book_info\"><div id=\"isn\">123456</div><div id=\"price\">178.55</div></div></body></html>", OutputFormat::Yaml);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
         assert_eq!(result, Output::Yaml(r#"---
 - object_id: user-info
   records:
@@ -397,10 +902,550 @@ mod tests {
     #[test]
     fn abstract_single_text() {
         let ids = vec![construct_ident_obj_single_prop()];
-        let extractor = Extractor { text: "<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", output_format: OutputFormat::Text};
-        let result = extractor.abstract_objs(&ids).unwrap();
+        let extractor = Extractor::new("<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div></body></html>", OutputFormat::Text);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
         assert_eq!(result, Output::Text("abc@abc.com".to_string()));
 
     }
 
+    fn construct_nested_ident_obj() -> Vec<IdentObj> {
+        vec![IdentObj {
+            object_id: "product".to_owned(),
+            css_selector: "div.product".to_owned(),
+            properties: vec![Identity {
+                    id: "reviews".to_owned(),
+                    css_selector: "div.review".to_owned(),
+                    value_type: IdentityValueType::Array,
+                    properties: vec![Identity {
+                            id: "author".to_owned(),
+                            css_selector: "div.author".to_owned(),
+                            value_type: IdentityValueType::Object,
+                            properties: vec![Identity {
+                                    id: "name".to_owned(),
+                                    css_selector: "span.name".to_owned(),
+                                    value_type: IdentityValueType::Str,
+                                    ..Default::default()
+                            }],
+                            ..Default::default()
+                    }],
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }]
+    }
+
+    const NESTED_HTML: &str = "<html><head></head><body><div class=\"product\"><div class=\"review\"><div class=\"author\"><span class=\"name\">Alice</span></div></div><div class=\"review\"><div class=\"author\"><span class=\"name\">Bob</span></div></div></div></body></html>";
+
+    #[test]
+    fn abstract_intermediate_nested() {
+        let ids = construct_nested_ident_obj();
+        let extractor = Extractor::new(NESTED_HTML, OutputFormat::Yaml);
+        let result = extractor.extract(&compile(&ids)).unwrap();
+
+        let review = |name: &str| {
+            let mut author = IndexMap::new();
+            author.insert("name".to_string(), PropertyValue::Str(name.to_string()));
+            let mut review = IndexMap::new();
+            review.insert("author".to_string(), PropertyValue::Object(author));
+            review
+        };
+        let mut expected_props = IndexMap::new();
+        expected_props.insert("reviews".to_string(), PropertyValue::Array(vec![review("Alice"), review("Bob")]));
+        let expected_output = vec![ IntermediateObject {object_id: "product".to_string(), records: vec![expected_props], ..Default::default()}];
+        assert_eq!(result, expected_output);
+    }
+
+    #[test]
+    fn abstract_nested_yaml() {
+        let ids = construct_nested_ident_obj();
+        let extractor = Extractor::new(NESTED_HTML, OutputFormat::Yaml);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Yaml(r#"---
+- object_id: product
+  records:
+    - reviews:
+        - author:
+            name: Alice
+        - author:
+            name: Bob
+"#.to_string()));
+    }
+
+    #[test]
+    fn abstract_nested_json() {
+        let ids = construct_nested_ident_obj();
+        let extractor = Extractor::new(NESTED_HTML, OutputFormat::Json);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Json(r#"[{"object_id":"product","records":[{"reviews":[{"author":{"name":"Alice"}},{"author":{"name":"Bob"}}]}]}]"#.to_string()));
+    }
+
+    #[test]
+    fn abstract_nested_array_without_match() {
+        let ids = construct_nested_ident_obj();
+        let extractor = Extractor::new("<html><body><div class=\"product\"></div></body></html>", OutputFormat::Json);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Json(r#"[{"object_id":"product","records":[{"reviews":[]}]}]"#.to_string()));
+    }
+
+    fn construct_tags_ident_obj(cardinality: Cardinality, value_type: IdentityValueType) -> Vec<IdentObj> {
+        vec![IdentObj {
+            object_id: "article".to_owned(),
+            css_selector: "div.article".to_owned(),
+            properties: vec![Identity {
+                    id: "tags".to_owned(),
+                    css_selector: "a.tag".to_owned(),
+                    value_type,
+                    cardinality,
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }]
+    }
+
+    const TAGS_HTML: &str = "<html><body><div class=\"article\"><a class=\"tag\">1</a><a class=\"tag\">2</a><a class=\"tag\">3</a></div></body></html>";
+
+    #[test]
+    fn abstract_cardinality_all() {
+        let ids = construct_tags_ident_obj(Cardinality::All, IdentityValueType::Int);
+        let extractor = Extractor::new(TAGS_HTML, OutputFormat::Json);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Json(r#"[{"object_id":"article","records":[{"tags":[1,2,3]}]}]"#.to_string()));
+    }
+
+    #[test]
+    fn abstract_cardinality_all_yaml() {
+        let ids = construct_tags_ident_obj(Cardinality::All, IdentityValueType::Str);
+        let extractor = Extractor::new(TAGS_HTML, OutputFormat::Yaml);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Yaml(r#"---
+- object_id: article
+  records:
+    - tags:
+        - "1"
+        - "2"
+        - "3"
+"#.to_string()));
+    }
+
+    #[test]
+    fn abstract_cardinality_all_without_match() {
+        let ids = construct_tags_ident_obj(Cardinality::All, IdentityValueType::Int);
+        let extractor = Extractor::new("<html><body><div class=\"article\"></div></body></html>", OutputFormat::Json);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Json(r#"[{"object_id":"article","records":[{"tags":[]}]}]"#.to_string()));
+    }
+
+    #[test]
+    fn abstract_cardinality_first_last_nth() {
+        let cases = vec![(Cardinality::First, Some(1)), (Cardinality::Last, Some(3)), (Cardinality::Nth(1), Some(2)), (Cardinality::Nth(5), None)];
+        for (cardinality, expected) in cases {
+            let ids = construct_tags_ident_obj(cardinality, IdentityValueType::Int);
+            let extractor = Extractor::new(TAGS_HTML, OutputFormat::Json);
+            let result = extractor.extract(&compile(&ids)).unwrap();
+            assert_eq!(result[0].records[0].get("tags"), expected.map(PropertyValue::Int).as_ref());
+        }
+    }
+
+    #[test]
+    fn apply_transforms_capture_positional() {
+        let transforms = vec![Transform::Capture { pattern: r"\$([0-9.]+)".to_string(), group: Some(CaptureGroup::Index(1)) }];
+        let v = apply_transforms("Price: $42.00 CAD".to_string(), &compile_transforms(&transforms));
+        assert_eq!(v, Some("42.00".to_string()));
+    }
+
+    #[test]
+    fn apply_transforms_capture_named() {
+        let transforms = vec![Transform::Capture { pattern: r"(?P<amount>\d+)\.\d+".to_string(), group: Some(CaptureGroup::Name("amount".to_string())) }];
+        let v = apply_transforms("Price: $42.00 CAD".to_string(), &compile_transforms(&transforms));
+        assert_eq!(v, Some("42".to_string()));
+    }
+
+    #[test]
+    fn apply_transforms_capture_no_match() {
+        let transforms = vec![Transform::Capture { pattern: r"\d+".to_string(), group: None }];
+        let v = apply_transforms("free".to_string(), &compile_transforms(&transforms));
+        assert_eq!(v, None);
+    }
+
+    #[test]
+    fn apply_transforms_replace_and_trim() {
+        let transforms = vec![Transform::Replace { pattern: r"(\d),(\d)".to_string(), with: "$1$2".to_string() }, Transform::Trim];
+        let v = apply_transforms("  1,234,567 ".to_string(), &compile_transforms(&transforms));
+        assert_eq!(v, Some("1234567".to_string()));
+    }
+
+    #[test]
+    fn abstract_transformed_float() {
+        let ids = vec![IdentObj {
+            object_id: "book-info".to_owned(),
+            css_selector: "div#book_info".to_owned(),
+            properties: vec![Identity {
+                    id: "price".to_owned(),
+                    css_selector: "div#price".to_owned(),
+                    value_type: IdentityValueType::Float,
+                    transforms: vec![Transform::Capture { pattern: r"\$([0-9.]+)".to_string(), group: Some(CaptureGroup::Index(1)) }],
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        let extractor = Extractor::new("<html><body><div id=\"book_info\"><div id=\"price\">Price: $42.50 CAD</div></div></body></html>", OutputFormat::Json);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Json(r#"[{"object_id":"book-info","records":[{"price":42.5}]}]"#.to_string()));
+    }
+
+    const MARKUP_HTML: &str = "<html><body><div id=\"user_info\"><p id=\"bio\">  Hello <b>big</b>\n   <i>wide</i> world  </p></div></body></html>";
+
+    fn abstract_bio(value_from: ValueFrom, whitespace: Whitespace) -> Option<PropertyValue> {
+        let ids = vec![IdentObj {
+            object_id: "user-info".to_owned(),
+            css_selector: "div#user_info".to_owned(),
+            properties: vec![Identity {
+                    id: "bio".to_owned(),
+                    css_selector: "p#bio".to_owned(),
+                    value_from,
+                    whitespace,
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        let extractor = Extractor::new(MARKUP_HTML, OutputFormat::Json);
+        let mut result = extractor.extract(&compile(&ids)).unwrap();
+        result.remove(0).records.remove(0).shift_remove("bio")
+    }
+
+    #[test]
+    fn value_from_inner_text_keeps_markup() {
+        let v = abstract_bio(ValueFrom::InnerText, Whitespace::Preserve);
+        assert_eq!(v, Some(PropertyValue::Str("  Hello <b>big</b>\n   <i>wide</i> world  ".to_string())));
+        assert_eq!(v, abstract_bio(ValueFrom::InnerHtml, Whitespace::Preserve));
+    }
+
+    #[test]
+    fn value_from_outer_html() {
+        let v = abstract_bio(ValueFrom::OuterHtml, Whitespace::Trim);
+        assert_eq!(v, Some(PropertyValue::Str("<p id=\"bio\">  Hello <b>big</b>\n   <i>wide</i> world  </p>".to_string())));
+    }
+
+    #[test]
+    fn value_from_text_content() {
+        let v = abstract_bio(ValueFrom::TextContent, Whitespace::Preserve);
+        assert_eq!(v, Some(PropertyValue::Str("  Hello big\n   wide world  ".to_string())));
+        let v = abstract_bio(ValueFrom::TextContent, Whitespace::Collapse);
+        assert_eq!(v, Some(PropertyValue::Str("Hello big wide world".to_string())));
+    }
+
+    #[test]
+    fn value_from_own_text() {
+        let v = abstract_bio(ValueFrom::OwnText, Whitespace::Trim);
+        assert_eq!(v, Some(PropertyValue::Str("Hello \n    world".to_string())));
+        let v = abstract_bio(ValueFrom::OwnText, Whitespace::Collapse);
+        assert_eq!(v, Some(PropertyValue::Str("Hello world".to_string())));
+    }
+
+    const SPECS_HTML: &str = "<html><body><table class=\"specs\"><caption>Book</caption><tr><th>Author</th><td>Jane Doe</td></tr><tr><th>Price</th><td>42.5</td></tr></table><table class=\"specs\"><caption>Pen</caption><tr><th>Price</th><td>1.25</td></tr></table></body></html>";
+
+    #[test]
+    fn abstract_mixed_css_and_xpath() {
+        let ids = vec![IdentObj {
+            object_id: "specs".to_owned(),
+            css_selector: "//table[@class='specs']".to_owned(),
+            selector_type: SelectorType::XPath,
+            properties: vec![Identity {
+                    id: "name".to_owned(),
+                    css_selector: "caption".to_owned(),
+                    value_type: IdentityValueType::Str,
+                    ..Default::default()
+            }, Identity {
+                    id: "price".to_owned(),
+                    css_selector: ".//th[text()='Price']/following-sibling::td".to_owned(),
+                    selector_type: SelectorType::XPath,
+                    value_type: IdentityValueType::Float,
+                    ..Default::default()
+            }, Identity {
+                    id: "author".to_owned(),
+                    css_selector: "normalize-space(.//th[. = 'Author']/../td)".to_owned(),
+                    selector_type: SelectorType::XPath,
+                    value_type: IdentityValueType::Str,
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        let extractor = Extractor::new(SPECS_HTML, OutputFormat::Yaml);
+        let result = extractor.extract(&compile(&ids)).unwrap();
+        assert_eq!(result[0].records.len(), 2);
+        assert_eq!(result[0].records[0].get("name"), Some(&PropertyValue::Str("Book".to_string())));
+        assert_eq!(result[0].records[0].get("price"), Some(&PropertyValue::Float(42.5)));
+        assert_eq!(result[0].records[0].get("author"), Some(&PropertyValue::Str("Jane Doe".to_string())));
+        assert_eq!(result[0].records[1].get("price"), Some(&PropertyValue::Float(1.25)));
+        assert_eq!(result[0].records[1].get("author"), Some(&PropertyValue::Str("".to_string())));
+    }
+
+    #[test]
+    fn abstract_xpath_attribute_and_text_nodes() {
+        let ids = vec![IdentObj {
+            object_id: "user-info".to_owned(),
+            css_selector: "div#user_info".to_owned(),
+            properties: vec![Identity {
+                    id: "link".to_owned(),
+                    css_selector: "a/@href".to_owned(),
+                    selector_type: SelectorType::XPath,
+                    ..Default::default()
+            }, Identity {
+                    id: "text".to_owned(),
+                    css_selector: "a/text()".to_owned(),
+                    selector_type: SelectorType::XPath,
+                    cardinality: Cardinality::All,
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        let extractor = Extractor::new("<html><body><div id=\"user_info\"><a href=\"mailto:abc@abc.com\">abc<b>@</b>abc.com</a></div></body></html>", OutputFormat::Json);
+        let result = extractor.extract(&compile(&ids)).unwrap();
+        assert_eq!(result[0].records[0].get("link"), Some(&PropertyValue::Str("mailto:abc@abc.com".to_string())));
+        assert_eq!(result[0].records[0].get("text"), Some(&PropertyValue::List(vec![PropertyValue::Str("abc".to_string()), PropertyValue::Str("abc.com".to_string())])));
+    }
+
+    #[test]
+    fn xpath_evaluation_error() {
+        let ids = vec![IdentObj {
+            object_id: "user-info".to_owned(),
+            css_selector: "div#user_info".to_owned(),
+            properties: vec![Identity {
+                    id: "count".to_owned(),
+                    css_selector: "count(1)".to_owned(),
+                    selector_type: SelectorType::XPath,
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        let extractor = Extractor::new("<html><body><div id=\"user_info\"></div></body></html>", OutputFormat::Json);
+        let err = extractor.extract(&compile(&ids)).unwrap_err();
+        assert!(matches!(err, ExtractError::XPath(_)), "{:?}", err);
+        assert_eq!(crate::error::Error::from(err).exit_code(), 3);
+    }
+
+    #[test]
+    fn abstract_invalid_xpath() {
+        let ids = vec![IdentObj {
+            object_id: "user-info".to_owned(),
+            css_selector: "//div[".to_owned(),
+            selector_type: SelectorType::XPath,
+            ..Default::default()
+        }];
+        assert!(matches!(CompiledTemplate::compile(&ids), Err(ExtractError::InvalidTemplate(_))));
+    }
+
+    const TWO_USERS_HTML: &str = "<html><head></head><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div><div id=\"phone_number\">13344445555</div></div><div id=\"user_info\"><div id=\"email\">def@abc.com</div><div id=\"phone_number\">23344445555</div></div></body></html>";
+
+    #[test]
+    fn abstract_multiple_props_yaml_in_template_order() {
+        let ids = vec![construct_ident_obj()];
+        let extractor = Extractor::new(TWO_USERS_HTML, OutputFormat::Yaml);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Yaml(r#"---
+- object_id: user-info
+  records:
+    - email: abc@abc.com
+      phone-number: "13344445555"
+    - email: def@abc.com
+      phone-number: "23344445555"
+"#.to_string()));
+    }
+
+    #[test]
+    fn abstract_multiple_props_json_in_template_order() {
+        let mut ids = vec![construct_ident_obj()];
+        let extractor = Extractor::new(TWO_USERS_HTML, OutputFormat::Json);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Json(r#"[{"object_id":"user-info","records":[{"email":"abc@abc.com","phone-number":"13344445555"},{"email":"def@abc.com","phone-number":"23344445555"}]}]"#.to_string()));
+
+        ids[0].properties.reverse();
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Json(r#"[{"object_id":"user-info","records":[{"phone-number":"13344445555","email":"abc@abc.com"},{"phone-number":"23344445555","email":"def@abc.com"}]}]"#.to_string()));
+    }
+
+    #[test]
+    fn abstract_text_is_first_declared_property() {
+        let mut ids = vec![construct_ident_obj()];
+        ids[0].properties.reverse();
+        let extractor = Extractor::new(TWO_USERS_HTML, OutputFormat::Text);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Text("13344445555".to_string()));
+    }
+
+    #[test]
+    fn abstract_csv_stream() {
+        let ids = construct_multiple_ident_obj();
+        let extractor = Extractor::new("<html><body><div id=\"user_info\"><div id=\"email\">abc, \"def\"@abc.com</div></div><div id=\"book_info\"><div id=\"isn\">123456</div></div></body></html>", OutputFormat::Csv(TableLayout::Stream));
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Csv("object_id,email,isn\nuser-info,\"abc, \"\"def\"\"@abc.com\",\nbook-info,,123456\n".to_string()));
+    }
+
+    #[test]
+    fn abstract_tsv_per_object() {
+        let ids = vec![construct_ident_obj()];
+        let extractor = Extractor::new(TWO_USERS_HTML, OutputFormat::Tsv(TableLayout::PerObject));
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Files(vec![("user-info.tsv".to_string(), "email\tphone-number\nabc@abc.com\t13344445555\ndef@abc.com\t23344445555\n".to_string())]));
+    }
+
+    #[test]
+    fn per_object_tables_group_by_object_id() {
+        let object = |source: &str, object_id: &str, field: &str, value: &str| IntermediateObject {
+            source: Some(source.to_string()),
+            object_id: object_id.to_string(),
+            records: vec![vec![(field.to_string(), PropertyValue::Str(value.to_string()))].into_iter().collect()]
+        };
+        // the inputs do not all have every object, nor in the order of the template
+        let intermediate = vec![
+            object("a.html", "user-info", "email", "a@a.com"),
+            object("a.html", "book-info", "isn", "1"),
+            object("b.html", "book-info", "isn", "2"),
+            object("c.html", "user-info", "email", "c@c.com")
+        ];
+        let result = intermediate_to_output(&intermediate, &construct_multiple_ident_obj(), &OutputFormat::Csv(TableLayout::PerObject)).unwrap();
+        assert_eq!(result, Output::Files(vec![
+            ("user-info.csv".to_string(), "source,email\na.html,a@a.com\nc.html,c@c.com\n".to_string()),
+            ("book-info.csv".to_string(), "source,isn\na.html,1\nb.html,2\n".to_string())
+        ]));
+    }
+
+    #[test]
+    fn abstract_csv_nested_values_as_json() {
+        let ids = construct_tags_ident_obj(Cardinality::All, IdentityValueType::Int);
+        let extractor = Extractor::new(TAGS_HTML, OutputFormat::Csv(TableLayout::PerObject));
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Files(vec![("article.csv".to_string(), "tags\n\"[1,2,3]\"\n".to_string())]));
+    }
+
+    #[test]
+    fn abstract_ndjson() {
+        let ids = construct_multiple_ident_obj();
+        let extractor = Extractor::new("<html><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div></div><div id=\"user_info\"><div id=\"email\">def@abc.com</div></div><div id=\"book_info\"><div id=\"isn\">123456</div></div></body></html>", OutputFormat::Ndjson);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Ndjson(r#"{"object_id":"user-info","record":{"email":"abc@abc.com"}}
+{"object_id":"user-info","record":{"email":"def@abc.com"}}
+{"object_id":"book-info","record":{"isn":"123456"}}
+"#.to_string()));
+    }
+
+    #[test]
+    fn stream_writes_each_record() {
+        // a writer that records the size of every write, to see the lines arrive one by one
+        struct Recorder(Vec<String>);
+        impl Write for Recorder {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.push(String::from_utf8_lossy(buf).into_owned());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let ids = vec![construct_ident_obj()];
+        let extractor = Extractor::new(TWO_USERS_HTML, OutputFormat::Ndjson);
+        let mut recorder = Recorder(Vec::new());
+        extractor.stream_objs(&compile(&ids), &mut recorder).unwrap();
+        let lines: Vec<&String> = recorder.0.iter().filter(|w| *w == "\n").collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(recorder.0.concat(), r#"{"object_id":"user-info","record":{"email":"abc@abc.com","phone-number":"13344445555"}}
+{"object_id":"user-info","record":{"email":"def@abc.com","phone-number":"23344445555"}}
+"#);
+    }
+
+    #[test]
+    fn abstract_intermediate_same_object_id_twice() {
+        let mut ids = construct_multiple_ident_obj();
+        ids[1].object_id = "user-info".to_string();
+        let extractor = Extractor::new("<html><body><div id=\"user_info\"><div id=\"email\">abc@abc.com</div></div><div id=\"book_info\"><div id=\"isn\">123456</div></div></body></html>", OutputFormat::Json);
+        let result = extractor.abstract_objs(&compile(&ids)).unwrap();
+        assert_eq!(result, Output::Json(r#"[{"object_id":"user-info","records":[{"email":"abc@abc.com"}]},{"object_id":"user-info","records":[{"isn":"123456"}]}]"#.to_string()));
+    }
+
+    #[test]
+    fn intermediate_round_trip() {
+        let ids = construct_nested_ident_obj();
+        let extractor = Extractor::new(NESTED_HTML, OutputFormat::Json);
+        let result = extractor.extract(&compile(&ids)).unwrap();
+        let json = serde_json::to_string(&result).unwrap();
+        assert_eq!(serde_json::from_str::<IntermediateResult>(&json).unwrap(), result);
+        let yaml = serde_yaml::to_string(&result).unwrap();
+        assert_eq!(serde_yaml::from_str::<IntermediateResult>(&yaml).unwrap(), result);
+    }
+
+    #[test]
+    fn deserialize_property_values() {
+        let record: Record = serde_json::from_str(r#"{"i": 1, "f": 1.5, "s": "a", "b": true, "n": null, "l": [1, "a"], "e": []}"#).unwrap();
+        assert_eq!(record["i"], PropertyValue::Int(1));
+        assert_eq!(record["f"], PropertyValue::Float(1.5));
+        assert_eq!(record["s"], PropertyValue::Str("a".to_string()));
+        assert_eq!(record["b"], PropertyValue::Bool(true));
+        assert_eq!(record["n"], PropertyValue::NA);
+        assert_eq!(record["l"], PropertyValue::List(vec![PropertyValue::Int(1), PropertyValue::Str("a".to_string())]));
+        assert_eq!(record["e"], PropertyValue::List(vec![]));
+    }
+
+    #[test]
+    fn records_as_user_type() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Author {
+            name: String
+        }
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Review {
+            author: Author
+        }
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Product {
+            reviews: Vec<Review>
+        }
+        let ids = construct_nested_ident_obj();
+        let extractor = Extractor::new(NESTED_HTML, OutputFormat::Json);
+        let result = extractor.extract(&compile(&ids)).unwrap();
+        let products: Vec<Product> = result[0].records_as().unwrap();
+        assert_eq!(products, vec![Product { reviews: vec![
+            Review { author: Author { name: "Alice".to_string() } },
+            Review { author: Author { name: "Bob".to_string() } },
+        ]}]);
+        assert!(matches!(result[0].records_as::<Author>(), Err(ExtractError::SerdeJson(_))));
+    }
+
+    #[test]
+    fn serialize_objs_to_serializer() {
+        let ids = vec![construct_ident_obj()];
+        let extractor = Extractor::new(TWO_USERS_HTML, OutputFormat::Json);
+        let mut buffer = Vec::new();
+        extractor.serialize_objs(&compile(&ids), &mut serde_json::Serializer::new(&mut buffer)).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), r#"[{"object_id":"user-info","records":[{"email":"abc@abc.com","phone-number":"13344445555"},{"email":"def@abc.com","phone-number":"23344445555"}]}]"#);
+        let value = extractor.serialize_objs(&compile(&ids), serde_json::value::Serializer).unwrap();
+        assert_eq!(value[0]["records"][1]["email"], "def@abc.com");
+    }
+
+    #[test]
+    fn resolve_url_values() {
+        let ids = crate::idents::from_json_str(r#"[{"object_id": "links", "css_selector": "body", "properties": [
+            {"id": "page", "css_selector": "a", "value_type": "Url", "value_from": {"Property": "href"}},
+            {"id": "image", "css_selector": "img", "value_type": "Url", "value_from": {"Property": "src"}},
+            {"id": "sizes", "css_selector": "img", "value_type": "Url", "value_from": {"Property": "srcset"}},
+            {"id": "raw", "css_selector": "a", "value_type": "Str", "value_from": {"Property": "href"}}
+        ]}]"#).unwrap();
+        let html = "<a href=' ../about?q=1#team '>about</a><img src='/i/a.png' srcset='a-1x.png 1x, //cdn.com/a-2x.png  2x,'>";
+        let page = Url::parse("https://a.com/blog/post/").unwrap();
+        let record = |html: &str, base: Option<&Url>| Extractor::new(html, OutputFormat::Json).base_url(base).extract(&compile(&ids)).unwrap().remove(0).records.remove(0);
+        let resolved = record(html, Some(&page));
+        assert_eq!(resolved["page"], PropertyValue::Str("https://a.com/blog/about?q=1#team".to_string()));
+        assert_eq!(resolved["image"], PropertyValue::Str("https://a.com/i/a.png".to_string()));
+        assert_eq!(resolved["sizes"], PropertyValue::Str("https://a.com/blog/post/a-1x.png 1x, https://cdn.com/a-2x.png 2x".to_string()));
+        assert_eq!(resolved["raw"], PropertyValue::Str(" ../about?q=1#team ".to_string()));
+        // the `<base href>` of the document wins over the URL of the page
+        let based = record(&format!("<head><base href='/static/'></head>{}", html), Some(&page));
+        assert_eq!(based["page"], PropertyValue::Str("https://a.com/about?q=1#team".to_string()));
+        assert_eq!(based["image"], PropertyValue::Str("https://a.com/i/a.png".to_string()));
+        let absolute_base = record(&format!("<base href='https://b.org/x/'>{}", html), None);
+        assert_eq!(absolute_base["sizes"], PropertyValue::Str("https://b.org/x/a-1x.png 1x, https://cdn.com/a-2x.png 2x".to_string()));
+        // without a base, the links are kept as they are
+        let unresolved = record(html, None);
+        assert_eq!(unresolved["page"], PropertyValue::Str("../about?q=1#team".to_string()));
+        assert_eq!(unresolved["sizes"], PropertyValue::Str("a-1x.png 1x, //cdn.com/a-2x.png 2x".to_string()));
+    }
 }
@@ -0,0 +1,202 @@
+use crate::batch::{self, Input, Source};
+use crate::error::Error;
+use crate::extract::{Extractor, IntermediateResult, OutputFormat, PropertyValue, Record};
+use crate::http::Fetcher;
+use crate::template::{CompiledObject, CompiledTemplate};
+
+use url::Url;
+
+use std::collections::HashMap;
+use std::slice;
+
+/// Follow the links of the records of an input to their detail pages, see `idents::Follow`. The
+/// detail pages are fetched with the HTTP options of the input, and every one of them only once:
+/// the records that link to the same page share its detail record. The detail pages that cannot
+/// be read are kept with their errors, see `take_failed`.
+pub struct Follower<'a> {
+    input: &'a Input,
+    fetcher: &'a Fetcher,
+    max_depth: usize,
+    details: HashMap<(String, Url), Option<Record>>,
+    failed: Vec<(Url, Error)>
+}
+
+impl<'a> Follower<'a> {
+    pub fn new(input: &'a Input, fetcher: &'a Fetcher, template: &CompiledTemplate) -> Self {
+        Self { input, fetcher, max_depth: template.max_depth, details: HashMap::new(), failed: Vec::new() }
+    }
+
+    /// The detail pages that could not be read since the last call, with their errors, for the
+    /// caller to report them.
+    pub fn take_failed(&mut self) -> Vec<(Url, Error)> {
+        std::mem::take(&mut self.failed)
+    }
+
+    /// Nest the detail records in the records extracted from a page of the input, whose links are
+    /// resolved against `page_url`. A link that is not followed, whose page cannot be read, or has
+    /// no match, gets an `NA` detail.
+    pub fn follow(&mut self, result: &mut IntermediateResult, template: &CompiledTemplate, page_url: Option<&Url>) -> Result<(), Error> {
+        for (object, compiled) in result.iter_mut().zip(&template.objects) {
+            self.follow_records(&mut object.records, compiled, page_url, 1)?;
+        }
+        Ok(())
+    }
+
+    fn follow_records(&mut self, records: &mut [Record], object: &CompiledObject, page_url: Option<&Url>, depth: usize) -> Result<(), Error> {
+        let follow = match &object.follow {
+            Some(follow) => follow,
+            None => return Ok(())
+        };
+        for record in records {
+            let detail = match link(record, &follow.property, page_url, follow.any_domain) {
+                Some(url) if depth <= self.max_depth => self.detail(url, &follow.object, depth)?,
+                _ => None
+            };
+            record.insert(follow.object.object_id.clone(), detail.map_or(PropertyValue::NA, PropertyValue::Object));
+        }
+        Ok(())
+    }
+
+    fn detail(&mut self, url: Url, object: &CompiledObject, depth: usize) -> Result<Option<Record>, Error> {
+        let key = (object.object_id.clone(), url);
+        if let Some(detail) = self.details.get(&key) {
+            return Ok(detail.clone());
+        }
+        let url = &key.1;
+        let input = Input { source: Source::Url(url.to_string()), http: self.input.http.clone(), base_url: None };
+        // a detail page that fails does not fail the records of the other links
+        let html = match batch::read_html(&input, self.fetcher) {
+            Ok(html) => html,
+            Err(err) => {
+                self.failed.push((url.clone(), err));
+                self.details.insert(key, None);
+                return Ok(None);
+            }
+        };
+        let mut detail = Extractor::new(&html, OutputFormat::Json).base_url(Some(url)).first_record(object)?;
+        if let Some(detail) = &mut detail {
+            self.follow_records(slice::from_mut(detail), object, Some(url), depth + 1)?;
+        }
+        self.details.insert(key, detail.clone());
+        Ok(detail)
+    }
+}
+
+// The link of a record, without its fragment. A relative link is resolved against the page, and
+// a link to another host than the one of the page is only followed with `any_domain`.
+fn link(record: &Record, property: &str, page_url: Option<&Url>, any_domain: bool) -> Option<Url> {
+    let value = match record.get(property)? {
+        PropertyValue::Str(value) => value.trim(),
+        _ => return None
+    };
+    let mut url = match page_url {
+        Some(page_url) => page_url.join(value).ok()?,
+        None => Url::parse(value).ok()?
+    };
+    url.set_fragment(None);
+    let same_domain = page_url.is_none_or(|page_url| page_url.host_str() == url.host_str());
+    if !matches!(url.scheme(), "http" | "https") || !(same_domain || any_domain) {
+        return None;
+    }
+    Some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch;
+    use crate::http::HttpOptions;
+    use crate::idents;
+    use crate::test_server::{Response, TestServer};
+
+    const TEMPLATE: &str = r#"[{"object_id": "products", "css_selector": "li", "properties": [
+        {"id": "name", "css_selector": "a", "value_type": "Str", "value_from": "TextContent"},
+        {"id": "link", "css_selector": "a", "value_type": "Url", "value_from": {"Property": "href"}}
+    ], "follow": {"property": "link", "object": {"object_id": "detail", "css_selector": "main", "properties": [
+        {"id": "title", "css_selector": "h1", "value_type": "Str", "value_from": "TextContent"},
+        {"id": "maker", "css_selector": "a.maker", "value_type": "Url", "value_from": {"Property": "href"}}
+    ], "follow": {"property": "maker", "object": {"object_id": "company", "css_selector": "div.maker", "properties": [
+        {"id": "name", "css_selector": "b", "value_type": "Str", "value_from": "TextContent"}
+    ]}}}}}]"#;
+
+    fn shop() -> TestServer {
+        TestServer::start(|request| match request.path.as_str() {
+            "/list" => Response::html("<li><a href='/p/1'>One</a></li><li><a href='p/2#reviews'>Two</a></li><li><a href='/p/1'>Again</a></li>\
+                <li><a href='http://localhost:1/p/3'>Elsewhere</a></li><li><a href='mailto:shop@example.com'>Mail</a></li>"),
+            "/p/1" => Response::html("<main><h1>First</h1><a class='maker' href='/m/acme'>Acme</a></main>"),
+            "/p/2" => Response::html("<main><h1>Second</h1><a class='maker' href='/m/acme#about'>Acme</a></main>"),
+            "/m/acme" => Response::html("<div class='maker'><b>Acme Inc</b></div>"),
+            "/broken" => Response::html("<li><a href='/p/1'>One</a></li><li><a href='/p/gone'>Gone</a></li><li><a href='/p/2'>Two</a></li>"),
+            _ => Response::status(404, "not found")
+        })
+    }
+
+    fn extract(server: &TestServer, max_depth: usize) -> Vec<String> {
+        extract_page(server, "/list", max_depth)
+    }
+
+    fn extract_page(server: &TestServer, path: &str, max_depth: usize) -> Vec<String> {
+        let template = CompiledTemplate::compile(&idents::from_json_str(TEMPLATE).unwrap()).unwrap().max_depth(max_depth);
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        let result = batch::extract_input(&Source::Url(server.url(path)).into(), &fetcher, &template, OutputFormat::Json, |_, _| {}).unwrap();
+        result[0].records.iter().map(|record| serde_json::to_string(&record).unwrap().replace(&server.url(""), "")).collect()
+    }
+
+    #[test]
+    fn follow_links_to_details() {
+        let server = shop();
+        assert_eq!(extract(&server, 3), vec![
+            r#"{"name":"One","link":"/p/1","detail":{"title":"First","maker":"/m/acme","company":{"name":"Acme Inc"}}}"#,
+            r#"{"name":"Two","link":"/p/2#reviews","detail":{"title":"Second","maker":"/m/acme#about","company":{"name":"Acme Inc"}}}"#,
+            r#"{"name":"Again","link":"/p/1","detail":{"title":"First","maker":"/m/acme","company":{"name":"Acme Inc"}}}"#,
+            r#"{"name":"Elsewhere","link":"http://localhost:1/p/3","detail":null}"#,
+            r#"{"name":"Mail","link":"mailto:shop@example.com","detail":null}"#
+        ]);
+        // every page is fetched once, and the other hosts are not
+        let paths: Vec<String> = server.requests().iter().map(|request| request.path.clone()).collect();
+        assert_eq!(paths, vec!["/list", "/p/1", "/m/acme", "/p/2"]);
+    }
+
+    #[test]
+    fn follow_down_to_the_max_depth() {
+        let server = shop();
+        assert_eq!(extract(&server, 1)[0], r#"{"name":"One","link":"/p/1","detail":{"title":"First","maker":"/m/acme","company":null}}"#);
+        assert_eq!(extract(&server, 0)[1], r#"{"name":"Two","link":"/p/2#reviews","detail":null}"#);
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[test]
+    fn failed_details_are_na() {
+        let server = shop();
+        assert_eq!(extract_page(&server, "/broken", 3), vec![
+            r#"{"name":"One","link":"/p/1","detail":{"title":"First","maker":"/m/acme","company":{"name":"Acme Inc"}}}"#,
+            r#"{"name":"Gone","link":"/p/gone","detail":null}"#,
+            r#"{"name":"Two","link":"/p/2","detail":{"title":"Second","maker":"/m/acme#about","company":{"name":"Acme Inc"}}}"#
+        ]);
+    }
+
+    #[test]
+    fn failed_details_are_reported() {
+        let server = shop();
+        let template = CompiledTemplate::compile(&idents::from_json_str(TEMPLATE).unwrap()).unwrap();
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        let mut failed = Vec::new();
+        batch::extract_input(&Source::Url(server.url("/broken")).into(), &fetcher, &template, OutputFormat::Json, |url, err| failed.push((url, err))).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0.as_str(), server.url("/p/gone"));
+        assert_eq!(failed[0].1.exit_code(), 5);
+    }
+
+    #[test]
+    fn same_domain_is_by_host() {
+        let page = Url::parse("https://a.com/list").unwrap();
+        let record = |link: &str| vec![("link".to_string(), PropertyValue::Str(link.to_string()))].into_iter().collect::<Record>();
+        assert_eq!(link(&record(" /p/1#top "), "link", Some(&page), false), Some(Url::parse("https://a.com/p/1").unwrap()));
+        assert_eq!(link(&record("https://b.com/p/1"), "link", Some(&page), false), None);
+        assert_eq!(link(&record("https://b.com/p/1"), "link", Some(&page), true), Some(Url::parse("https://b.com/p/1").unwrap()));
+        // without the URL of the page, only the absolute links are followed
+        assert_eq!(link(&record("/p/1"), "link", None, false), None);
+        assert_eq!(link(&record("https://b.com/p/1"), "link", None, false), Some(Url::parse("https://b.com/p/1").unwrap()));
+        assert_eq!(link(&record("/p/1"), "name", Some(&page), false), None);
+    }
+}
@@ -0,0 +1,753 @@
+use crate::cache::{Cache, CachedResponse};
+use crate::encoding;
+use crate::error::Error;
+use crate::pool::{self, HostLimits, PoolOptions};
+use crate::robots::{Robots, RobotsTxt};
+
+use encoding_rs::Encoding;
+use indexmap::IndexMap;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::redirect::Policy;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER};
+use reqwest::{Proxy, StatusCode, Url};
+use serde::Deserialize;
+
+use std::collections::hash_map::RandomState;
+use std::fs::read_to_string;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The user agent sent when none is configured.
+pub const DEFAULT_USER_AGENT: &str = concat!("dessert2/", env!("CARGO_PKG_VERSION"));
+
+/// The number of retries of a failed request when none is configured.
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// The statuses retried when none are configured: timeouts, rate limits and server errors.
+pub const DEFAULT_RETRY_STATUSES: &[u16] = &[408, 429, 500, 502, 503, 504];
+
+/// The delay before the first retry when none is configured, in seconds.
+pub const DEFAULT_RETRY_DELAY: f64 = 1.0;
+
+/// The longest wait asked by a `Retry-After` header that is honored.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// How pages are fetched. The options of a run can be overridden for a single URL by a JSON line
+/// of the URL list, like `{"url": "https://a.com/", "headers": {"Accept-Language": "fr"}}`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpOptions {
+    /// Sent with every request, in this order.
+    pub headers: IndexMap<String, String>,
+    pub user_agent: Option<String>,
+    /// A cookie jar file in the Netscape format, as written by curl and browser extensions.
+    pub cookie_jar: Option<PathBuf>,
+    /// In seconds.
+    pub connect_timeout: Option<f64>,
+    /// The time allowed for a whole request, in seconds.
+    pub timeout: Option<f64>,
+    /// An `http://` or `https://` proxy for all the requests.
+    pub proxy: Option<String>,
+    /// The number of redirects followed, `0` to follow none. 10 by default.
+    pub max_redirects: Option<usize>,
+    pub auth: Option<Auth>,
+    /// The number of times a request is retried after a network error or a status of
+    /// `retry_statuses`. 2 by default.
+    pub retries: Option<u32>,
+    pub retry_statuses: Option<Vec<u16>>,
+    /// The delay before the first retry in seconds, doubled for every other one, with a random
+    /// jitter. A `Retry-After` header of the response takes precedence.
+    pub retry_delay: Option<f64>,
+    /// The statuses other than 2xx whose page is extracted, like `[404]`. The others are errors.
+    pub allow_statuses: Vec<u16>,
+    /// The encoding of the pages, like `shift_jis`, instead of the detected one. It applies to
+    /// the files and stdin as well.
+    pub encoding: Option<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Auth {
+    Basic { user: String, password: Option<String> },
+    Bearer(String)
+}
+
+impl HttpOptions {
+    /// These options, with the ones set in `overrides` taking precedence. Headers are merged, a
+    /// header of `overrides` replaces the one of the same name.
+    pub fn merge(&self, overrides: &HttpOptions) -> HttpOptions {
+        let mut headers = self.headers.clone();
+        for (name, value) in &overrides.headers {
+            headers.retain(|n, _| !n.eq_ignore_ascii_case(name));
+            headers.insert(name.clone(), value.clone());
+        }
+        HttpOptions {
+            headers,
+            user_agent: overrides.user_agent.clone().or_else(|| self.user_agent.clone()),
+            cookie_jar: overrides.cookie_jar.clone().or_else(|| self.cookie_jar.clone()),
+            connect_timeout: overrides.connect_timeout.or(self.connect_timeout),
+            timeout: overrides.timeout.or(self.timeout),
+            proxy: overrides.proxy.clone().or_else(|| self.proxy.clone()),
+            max_redirects: overrides.max_redirects.or(self.max_redirects),
+            auth: overrides.auth.clone().or_else(|| self.auth.clone()),
+            retries: overrides.retries.or(self.retries),
+            retry_statuses: overrides.retry_statuses.clone().or_else(|| self.retry_statuses.clone()),
+            retry_delay: overrides.retry_delay.or(self.retry_delay),
+            allow_statuses: if overrides.allow_statuses.is_empty() { self.allow_statuses.clone() } else { overrides.allow_statuses.clone() },
+            encoding: overrides.encoding.clone().or_else(|| self.encoding.clone())
+        }
+    }
+
+    fn is_retried(&self, status: StatusCode) -> bool {
+        self.retry_statuses.as_deref().unwrap_or(DEFAULT_RETRY_STATUSES).contains(&status.as_u16())
+    }
+
+    // the wait before the `retry`th retry, from 1: the delay doubled for every retry, between half
+    // of it and all of it so that clients failing together do not retry together
+    fn backoff(&self, retry: u32) -> Duration {
+        let delay = self.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY) * 2f64.powi(retry as i32 - 1);
+        Duration::from_secs_f64(delay * (0.5 + jitter() / 2.0))
+    }
+
+    // whether the options need a client of their own, the others are set on every request
+    fn has_client_options(&self) -> bool {
+        self.user_agent.is_some() || self.connect_timeout.is_some() || self.timeout.is_some()
+            || self.proxy.is_some() || self.max_redirects.is_some()
+    }
+
+    fn client(&self) -> Result<Client, Error> {
+        let mut builder = Client::builder()
+            .user_agent(self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+            .redirect(match self.max_redirects {
+                Some(0) => Policy::none(),
+                Some(max) => Policy::limited(max),
+                None => Policy::default()
+            });
+        if let Some(secs) = self.connect_timeout {
+            builder = builder.connect_timeout(Duration::from_secs_f64(secs));
+        }
+        if let Some(secs) = self.timeout {
+            builder = builder.timeout(Duration::from_secs_f64(secs));
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy).map_err(|err| Error::Http(format!("invalid proxy `{}`: {}", proxy, err)))?);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// A cookie of a Netscape cookie jar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub domain: String,
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    /// Seconds since the epoch, `0` for a session cookie.
+    pub expires: u64,
+    pub name: String,
+    pub value: String
+}
+
+impl Cookie {
+    fn matches(&self, url: &Url, now: u64) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return false
+        };
+        let domain = self.domain.trim_start_matches('.').to_ascii_lowercase();
+        let domain_matches = host == domain || (self.include_subdomains && host.ends_with(&format!(".{}", domain)));
+        domain_matches
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && (self.expires == 0 || self.expires > now)
+    }
+}
+
+// the path-match of RFC 6265 §5.1.4: `/account` matches `/account` and `/account/me`, not
+// `/accounting`
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    match request_path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false
+    }
+}
+
+/// Parse a cookie jar in the Netscape format: one cookie per line, with seven tab-separated
+/// fields. Lines starting with `#` are comments, except the `#HttpOnly_` prefix of a domain.
+pub fn parse_cookie_jar(text: &str) -> Vec<Cookie> {
+    text.lines().filter_map(|line| {
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.starts_with('#') || line.trim().is_empty() {
+            return None;
+        }
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        if fields.len() != 7 {
+            return None;
+        }
+        Some(Cookie {
+            domain: fields[0].to_string(),
+            include_subdomains: fields[1].eq_ignore_ascii_case("TRUE"),
+            path: fields[2].to_string(),
+            secure: fields[3].eq_ignore_ascii_case("TRUE"),
+            expires: fields[4].parse().unwrap_or(0),
+            name: fields[5].to_string(),
+            value: fields[6].to_string()
+        })
+    }).collect()
+}
+
+fn read_cookie_jar(path: &Path) -> Result<Vec<Cookie>, Error> {
+    let text = read_to_string(path).map_err(|err| Error::Io(format!("cannot read the cookie jar `{}`: {}", path.display(), err)))?;
+    Ok(parse_cookie_jar(&text))
+}
+
+/// Fetch pages with the HTTP options of a run. The client and the cookie jar are set up once,
+/// and shared by all the requests that do not override them.
+#[derive(Debug)]
+pub struct Fetcher {
+    options: HttpOptions,
+    client: Client,
+    cookies: Vec<Cookie>,
+    verbose: bool,
+    cache: Option<Cache>,
+    robots: Option<Robots>,
+    host_limits: Option<HostLimits>
+}
+
+impl Fetcher {
+    pub fn new(options: HttpOptions) -> Result<Self, Error> {
+        let client = options.client()?;
+        let cookies = match &options.cookie_jar {
+            Some(path) => read_cookie_jar(path)?,
+            None => Vec::new()
+        };
+        Ok(Self { options, client, cookies, verbose: false, cache: None, robots: None, host_limits: None })
+    }
+
+    /// Report the retries and the final status of every request on stderr.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Keep the pages in the cache, and revalidate them instead of downloading them again.
+    pub fn cache(mut self, cache: Option<Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Obey the robots.txt of the sites: a disallowed URL is an `Error::RobotsBlocked`, and the
+    /// requests to a site are spaced by its `Crawl-delay`. The robots.txt of every site is
+    /// fetched once, with the retries of the pages: a missing one allows everything, and one that is
+    /// still unreachable, because of a network or server error, disallows everything.
+    pub fn robots(mut self, obey: bool) -> Self {
+        self.robots = if obey { Some(Robots::default()) } else { None };
+        self
+    }
+
+    /// Hold every request to the per-host limits of the pool, `per_host` requests to a host at a
+    /// time and `host_delay` apart, including the next pages and the detail pages of an input.
+    pub fn host_limits(mut self, options: &PoolOptions) -> Self {
+        self.host_limits = Some(HostLimits::new(options));
+        self
+    }
+
+    pub fn options(&self) -> &HttpOptions {
+        &self.options
+    }
+
+    /// Fetch the page at `url`, with the options of the run, or merged with `overrides`. Network
+    /// errors and the statuses of `retry_statuses` are retried, a status other than 2xx and the
+    /// `allow_statuses` is an error. The page is decoded with `encoding::decode`.
+    pub fn fetch(&self, url: &str, overrides: Option<&HttpOptions>) -> Result<String, Error> {
+        let (page, forced) = self.fetch_response(url, overrides)?;
+        Ok(encoding::decode(&page.body, forced, page.content_type.as_deref()))
+    }
+
+    /// Fetch the body at `url` like `fetch`, as bytes, for the files that are not pages, like a
+    /// compressed sitemap.
+    pub fn fetch_bytes(&self, url: &str, overrides: Option<&HttpOptions>) -> Result<Vec<u8>, Error> {
+        Ok(self.fetch_response(url, overrides)?.0.body)
+    }
+
+    // the response, and the encoding it is forced to
+    fn fetch_response(&self, url: &str, overrides: Option<&HttpOptions>) -> Result<(CachedResponse, Option<&'static Encoding>), Error> {
+        let parsed = Url::parse(url).map_err(|err| Error::Http(format!("invalid URL `{}`: {}", url, err)))?;
+        let merged;
+        let (options, client, cookies) = match overrides {
+            None => (&self.options, self.client.clone(), self.cookies.clone()),
+            Some(overrides) => {
+                merged = self.options.merge(overrides);
+                let client = if overrides.has_client_options() { merged.client()? } else { self.client.clone() };
+                let cookies = match &overrides.cookie_jar {
+                    Some(path) => read_cookie_jar(path)?,
+                    None => self.cookies.clone()
+                };
+                (&merged, client, cookies)
+            }
+        };
+        let forced = options.encoding.as_deref().map(encoding::for_label).transpose()?;
+        let key = Cache::key(url, options, cookie_header(&cookies, &parsed).as_deref());
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(&key));
+        if self.cache.as_ref().is_some_and(Cache::is_offline) {
+            let cached = cached.ok_or_else(|| Error::Http(format!("{} is not in the cache, and the run is offline", url)))?;
+            self.diagnose(url, "from the cache, offline");
+            return Ok((cached, forced));
+        }
+        if let Some(robots) = &self.robots {
+            self.obey_robots(robots, url, &parsed, options, &client, &cookies)?;
+        }
+
+        let _permit = match (&self.host_limits, pool::host_key(&parsed)) {
+            (Some(limits), Some(host)) => Some(limits.acquire(&host)),
+            _ => None
+        };
+        let response = self.send(url, &parsed, options, &client, &cookies, cached.as_ref())?;
+        let status = response.status();
+        let header = |name| response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+        let page = match cached {
+            Some(cached) if status == StatusCode::NOT_MODIFIED => cached,
+            _ => CachedResponse {
+                url: url.to_string(),
+                status: status.as_u16(),
+                content_type: header(CONTENT_TYPE),
+                etag: header(ETAG),
+                last_modified: header(LAST_MODIFIED),
+                body: response.bytes()?.to_vec()
+            }
+        };
+        if let Some(cache) = &self.cache {
+            if status != StatusCode::NOT_MODIFIED {
+                cache.put(&key, &page)?;
+            }
+        }
+        Ok((page, forced))
+    }
+
+    // send the request until it succeeds or cannot be retried anymore. A cached page is
+    // revalidated, and then `304 Not Modified` is a success as well.
+    fn send(&self, url: &str, parsed: &Url, options: &HttpOptions, client: &Client, cookies: &[Cookie], cached: Option<&CachedResponse>) -> Result<Response, Error> {
+        let retries = options.retries.unwrap_or(DEFAULT_RETRIES);
+        let mut retry = 0;
+        loop {
+            let mut request = prepare(client.get(parsed.clone()), options, cookies, parsed);
+            if let Some(cached) = cached {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(IF_NONE_MATCH, etag.as_str());
+                }
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+                }
+            }
+            let (reason, retry_after) = match request.send() {
+                Ok(response) => {
+                    let status = response.status();
+                    if status == StatusCode::NOT_MODIFIED && cached.is_some() {
+                        self.diagnose(url, &format!("{}{}, from the cache", status, retried(retry)));
+                        return Ok(response);
+                    }
+                    if status.is_success() || options.allow_statuses.contains(&status.as_u16()) {
+                        self.diagnose(url, &format!("{}{}", status, retried(retry)));
+                        return Ok(response);
+                    }
+                    if retry == retries || !options.is_retried(status) {
+                        self.diagnose(url, &format!("{}{}", status, retried(retry)));
+                        return Err(Error::Http(format!("{} returned {}{}", url, status, retried(retry))));
+                    }
+                    (status.to_string(), parse_retry_after(response.headers()))
+                },
+                Err(err) if retry == retries || err.is_builder() => {
+                    self.diagnose(url, &format!("{}{}", err, retried(retry)));
+                    return Err(Error::Http(format!("{}{}", err, retried(retry))));
+                },
+                Err(err) => (err.to_string(), None)
+            };
+            retry += 1;
+            let wait = retry_after.map(|wait| wait.min(MAX_RETRY_AFTER)).unwrap_or_else(|| options.backoff(retry));
+            self.diagnose(url, &format!("{}, retry {} of {} in {:.2}s", reason, retry, retries, wait.as_secs_f64()));
+            thread::sleep(wait);
+        }
+    }
+
+    // check the URL against the robots.txt of its site, and wait for its crawl delay
+    fn obey_robots(&self, robots: &Robots, url: &str, parsed: &Url, options: &HttpOptions, client: &Client, cookies: &[Cookie]) -> Result<(), Error> {
+        let origin = parsed.origin().ascii_serialization();
+        let robots_txt = robots.get(&origin, || self.fetch_robots(&origin, options, client, cookies));
+        let user_agent = options.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        let path = match parsed.query() {
+            Some(query) => format!("{}?{}", parsed.path(), query),
+            None => parsed.path().to_string()
+        };
+        if !robots_txt.is_allowed(user_agent, &path) {
+            self.diagnose(url, "disallowed by robots.txt");
+            return Err(Error::RobotsBlocked(format!("{} is disallowed by {}/robots.txt", url, origin)));
+        }
+        if let Some(delay) = robots_txt.crawl_delay(user_agent) {
+            robots.wait(&origin, delay);
+        }
+        Ok(())
+    }
+
+    // a robots.txt that is not found allows everything, and one that is unreachable because of a
+    // network or server error disallows everything, as of RFC 9309. It is retried like the pages.
+    fn fetch_robots(&self, origin: &str, options: &HttpOptions, client: &Client, cookies: &[Cookie]) -> RobotsTxt {
+        let url = format!("{}/robots.txt", origin);
+        let parsed = match Url::parse(&url) {
+            Ok(parsed) => parsed,
+            Err(_) => return RobotsTxt::disallow_all()
+        };
+        // the client errors that are not retried are answers, the others are errors of `send`
+        let options = HttpOptions {
+            allow_statuses: (400..500).filter(|status| !options.is_retried(StatusCode::from_u16(*status).unwrap())).collect(),
+            ..options.clone()
+        };
+        let response = match self.send(&url, &parsed, &options, client, cookies, None) {
+            Ok(response) => response,
+            Err(err) => {
+                self.diagnose(&url, &format!("unreachable, every URL of the site is disallowed: {}", err));
+                return RobotsTxt::disallow_all();
+            }
+        };
+        if response.status().is_client_error() {
+            return RobotsTxt::default();
+        }
+        match response.bytes() {
+            Ok(bytes) => RobotsTxt::parse(&String::from_utf8_lossy(&bytes)),
+            Err(err) => {
+                self.diagnose(&url, &format!("unreadable, every URL of the site is disallowed: {}", err));
+                RobotsTxt::disallow_all()
+            }
+        }
+    }
+
+    fn diagnose(&self, url: &str, message: &str) {
+        if self.verbose {
+            eprintln!("GET {}: {}", url, message);
+        }
+    }
+}
+
+fn retried(retry: u32) -> String {
+    match retry {
+        0 => String::new(),
+        1 => " after 1 retry".to_string(),
+        n => format!(" after {} retries", n)
+    }
+}
+
+// a number of seconds or an HTTP date
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => httpdate::parse_http_date(value).ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+}
+
+// a random number in [0, 1)
+fn jitter() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// the `Cookie` header of the cookies of the jar sent to the URL
+fn cookie_header(cookies: &[Cookie], url: &Url) -> Option<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let cookie: Vec<String> = cookies.iter()
+        .filter(|cookie| cookie.matches(url, now))
+        .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+        .collect();
+    if cookie.is_empty() { None } else { Some(cookie.join("; ")) }
+}
+
+fn prepare(mut request: RequestBuilder, options: &HttpOptions, cookies: &[Cookie], url: &Url) -> RequestBuilder {
+    for (name, value) in &options.headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    if let Some(cookie) = cookie_header(cookies, url) {
+        request = request.header("Cookie", cookie);
+    }
+    match &options.auth {
+        Some(Auth::Basic { user, password }) => request.basic_auth(user, password.as_ref()),
+        Some(Auth::Bearer(token)) => request.bearer_auth(token),
+        None => request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Request, Response, TestServer};
+
+    const JAR: &str = "# Netscape HTTP Cookie File\n\
+        127.0.0.1\tFALSE\t/\tFALSE\t0\tsession\tabc\n\
+        #HttpOnly_127.0.0.1\tFALSE\t/account\tFALSE\t0\taccount\tdef\n\
+        .example.com\tTRUE\t/\tTRUE\t4102444800\tsecure\tghi\n\
+        127.0.0.1\tFALSE\t/\tFALSE\t1\texpired\tjkl\n";
+
+    fn echo_server() -> TestServer {
+        TestServer::start(|request: &Request| match request.path.as_str() {
+            "/redirect" => Response::status(302, "").header("Location", "/page"),
+            "/slow" => Response::html("slow").delay(Duration::from_millis(500)),
+            _ => Response::html("page")
+        })
+    }
+
+    #[test]
+    fn cookie_jar_matching() {
+        let cookies = parse_cookie_jar(JAR);
+        assert_eq!(cookies.len(), 4);
+        assert_eq!(cookies[1].name, "account");
+        let names = |url: &str| -> Vec<String> {
+            let url = Url::parse(url).unwrap();
+            cookies.iter().filter(|c| c.matches(&url, 1_700_000_000)).map(|c| c.name.clone()).collect()
+        };
+        assert_eq!(names("http://127.0.0.1/account/me"), vec!["session", "account"]);
+        assert_eq!(names("http://127.0.0.1/"), vec!["session"]);
+        assert_eq!(names("http://127.0.0.1/account"), vec!["session", "account"]);
+        assert_eq!(names("http://127.0.0.1/accounting"), vec!["session"]);
+        assert_eq!(names("https://www.example.com/"), vec!["secure"]);
+        assert!(names("http://www.example.com/").is_empty());
+        assert!(names("https://notexample.com/").is_empty());
+    }
+
+    #[test]
+    fn cookie_path_matching() {
+        assert!(path_matches("/account", "/account"));
+        assert!(path_matches("/account/me", "/account"));
+        assert!(path_matches("/account/me", "/account/"));
+        assert!(path_matches("/anything", "/"));
+        assert!(!path_matches("/accounting", "/account"));
+        assert!(!path_matches("/account", "/account/"));
+        assert!(!path_matches("/", "/account"));
+    }
+
+    #[test]
+    fn merge_overrides() {
+        let mut base = HttpOptions { user_agent: Some("base".to_string()), timeout: Some(5.0), ..Default::default() };
+        base.headers.insert("Accept-Language".to_string(), "en".to_string());
+        base.headers.insert("X-Run".to_string(), "1".to_string());
+        let overrides: HttpOptions = serde_json::from_str(r#"{"headers": {"accept-language": "fr"}, "auth": {"bearer": "t0k"}, "max_redirects": 0}"#).unwrap();
+        let merged = base.merge(&overrides);
+        assert_eq!(merged.headers.iter().collect::<Vec<_>>(), vec![(&"X-Run".to_string(), &"1".to_string()), (&"accept-language".to_string(), &"fr".to_string())]);
+        assert_eq!(merged.user_agent, Some("base".to_string()));
+        assert_eq!(merged.timeout, Some(5.0));
+        assert_eq!(merged.max_redirects, Some(0));
+        assert_eq!(merged.auth, Some(Auth::Bearer("t0k".to_string())));
+        assert!(serde_json::from_str::<HttpOptions>(r#"{"user-agent": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn fetch_sends_headers_cookies_and_auth() {
+        let server = echo_server();
+        let jar = std::env::temp_dir().join(format!("dessert2-jar-{}.txt", std::process::id()));
+        std::fs::write(&jar, JAR).unwrap();
+        let mut options = HttpOptions { cookie_jar: Some(jar.clone()), auth: Some(Auth::Basic { user: "ann".to_string(), password: Some("pw".to_string()) }), ..Default::default() };
+        options.headers.insert("Accept-Language".to_string(), "en-CA".to_string());
+        let fetcher = Fetcher::new(options).unwrap();
+        assert_eq!(fetcher.fetch(&server.url("/account/me"), None).unwrap(), "page");
+        let overrides = HttpOptions { user_agent: Some("custom/1.0".to_string()), auth: Some(Auth::Bearer("t0k".to_string())), ..Default::default() };
+        fetcher.fetch(&server.url("/"), Some(&overrides)).unwrap();
+        std::fs::remove_file(&jar).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("Accept-Language"), Some("en-CA"));
+        assert_eq!(requests[0].header("Cookie"), Some("session=abc; account=def"));
+        assert_eq!(requests[0].header("Authorization"), Some("Basic YW5uOnB3"));
+        assert_eq!(requests[0].header("User-Agent"), Some(DEFAULT_USER_AGENT));
+        assert_eq!(requests[1].header("Cookie"), Some("session=abc"));
+        assert_eq!(requests[1].header("Authorization"), Some("Bearer t0k"));
+        assert_eq!(requests[1].header("User-Agent"), Some("custom/1.0"));
+    }
+
+    #[test]
+    fn fetch_redirects_and_timeouts() {
+        let server = echo_server();
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        assert_eq!(fetcher.fetch(&server.url("/redirect"), None).unwrap(), "page");
+        let no_redirects = HttpOptions { max_redirects: Some(0), ..Default::default() };
+        assert!(matches!(fetcher.fetch(&server.url("/redirect"), Some(&no_redirects)), Err(Error::Http(_))));
+        let allow_redirects = HttpOptions { max_redirects: Some(0), allow_statuses: vec![302], ..Default::default() };
+        assert_eq!(fetcher.fetch(&server.url("/redirect"), Some(&allow_redirects)).unwrap(), "");
+        let short = HttpOptions { timeout: Some(0.1), retries: Some(0), ..Default::default() };
+        assert!(matches!(fetcher.fetch(&server.url("/slow"), Some(&short)), Err(Error::Http(_))));
+    }
+
+    #[test]
+    fn fetch_through_proxy() {
+        let proxy = TestServer::start(|_| Response::html("proxied"));
+        let fetcher = Fetcher::new(HttpOptions { proxy: Some(proxy.url("")), ..Default::default() }).unwrap();
+        assert_eq!(fetcher.fetch("http://example.invalid/page", None).unwrap(), "proxied");
+        assert_eq!(proxy.requests()[0].path, "http://example.invalid/page");
+    }
+
+    fn flaky_server(failures: usize, failure: Response) -> TestServer {
+        let count = std::sync::atomic::AtomicUsize::new(0);
+        TestServer::start(move |_| match count.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+            n if n < failures => failure.clone(),
+            _ => Response::html("page")
+        })
+    }
+
+    #[test]
+    fn fetch_retries_with_backoff() {
+        let server = flaky_server(2, Response::status(503, "busy"));
+        let fetcher = Fetcher::new(HttpOptions { retry_delay: Some(0.05), ..Default::default() }).unwrap();
+        let start = std::time::Instant::now();
+        assert_eq!(fetcher.fetch(&server.url("/"), None).unwrap(), "page");
+        // 0.05s then 0.1s, each at least halved by the jitter
+        assert!(start.elapsed() >= Duration::from_millis(75));
+        assert_eq!(server.requests().len(), 3);
+
+        let server = flaky_server(3, Response::status(503, "busy"));
+        let error = fetcher.fetch(&server.url("/"), None).unwrap_err().to_string();
+        assert!(error.ends_with("returned 503 Service Unavailable after 2 retries"), "{}", error);
+        let server = flaky_server(1, Response::status(404, "gone"));
+        assert!(fetcher.fetch(&server.url("/"), None).unwrap_err().to_string().ends_with("returned 404 Not Found"));
+        assert_eq!(server.requests().len(), 1);
+        let only_404 = HttpOptions { retry_statuses: Some(vec![404]), ..Default::default() };
+        assert_eq!(fetcher.fetch(&server.url("/"), Some(&only_404)).unwrap(), "page");
+    }
+
+    #[test]
+    fn fetch_honors_retry_after() {
+        let server = flaky_server(1, Response::status(429, "").header("Retry-After", "0"));
+        let fetcher = Fetcher::new(HttpOptions { retry_delay: Some(30.0), ..Default::default() }).unwrap();
+        let start = std::time::Instant::now();
+        assert_eq!(fetcher.fetch(&server.url("/"), None).unwrap(), "page");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "7".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(7)));
+        headers.insert(RETRY_AFTER, "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(0)));
+        headers.insert(RETRY_AFTER, httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(60)).parse().unwrap());
+        assert!(parse_retry_after(&headers).unwrap() > Duration::from_secs(55));
+    }
+
+    #[test]
+    fn fetch_retries_network_errors() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let fetcher = Fetcher::new(HttpOptions { retries: Some(1), retry_delay: Some(0.01), ..Default::default() }).unwrap();
+        let error = fetcher.fetch(&format!("http://127.0.0.1:{}/", port), None).unwrap_err();
+        assert!(matches!(&error, Error::Http(reason) if reason.ends_with(" after 1 retry")), "{}", error);
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter() {
+        let options = HttpOptions { retry_delay: Some(1.0), ..Default::default() };
+        for retry in 1..5 {
+            let full = 2f64.powi(retry as i32 - 1);
+            let wait = options.backoff(retry).as_secs_f64();
+            assert!(wait >= full / 2.0 && wait <= full, "{} {}", retry, wait);
+        }
+        assert_ne!(jitter(), jitter());
+    }
+
+    #[test]
+    fn fetch_decodes_pages() {
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("<p>日本語</p>");
+        let body = sjis.into_owned();
+        let server = TestServer::start(move |request| {
+            let mut response = Response::status(200, "");
+            response.body = body.clone();
+            match request.path.as_str() {
+                "/header" => response.header("Content-Type", "text/html; charset=Shift_JIS"),
+                _ => response.header("Content-Type", "text/html")
+            }
+        });
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        assert_eq!(fetcher.fetch(&server.url("/header"), None).unwrap(), "<p>日本語</p>");
+        assert!(fetcher.fetch(&server.url("/"), None).unwrap().contains('\u{FFFD}'));
+        let forced = HttpOptions { encoding: Some("sjis".to_string()), ..Default::default() };
+        assert_eq!(fetcher.fetch(&server.url("/"), Some(&forced)).unwrap(), "<p>日本語</p>");
+    }
+
+    #[test]
+    fn fetch_with_cache() {
+        let server = TestServer::start(|request| match (request.path.as_str(), request.header("If-None-Match"), request.header("If-Modified-Since")) {
+            ("/etag", Some("\"v1\""), _) | ("/date", _, Some("Wed, 21 Oct 2015 07:28:00 GMT")) => Response::status(304, ""),
+            ("/etag", _, _) => Response::html("<p>etag</p>").header("ETag", "\"v1\""),
+            ("/date", _, _) => Response::html("<p>date</p>").header("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT"),
+            _ => Response::html("<p>plain</p>")
+        });
+        let dir = std::env::temp_dir().join(format!("dessert2-fetch-cache-{}", std::process::id()));
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap().cache(Some(Cache::new(&dir, false).unwrap()));
+        for _ in 0..2 {
+            assert_eq!(fetcher.fetch(&server.url("/etag"), None).unwrap(), "<p>etag</p>");
+            assert_eq!(fetcher.fetch(&server.url("/date"), None).unwrap(), "<p>date</p>");
+            assert_eq!(fetcher.fetch(&server.url("/plain"), None).unwrap(), "<p>plain</p>");
+        }
+        let requests = server.requests();
+        assert_eq!(requests.len(), 6);
+        assert_eq!(requests[0].header("If-None-Match"), None);
+        assert_eq!(requests[3].header("If-None-Match"), Some("\"v1\""));
+        assert_eq!(requests[4].header("If-Modified-Since"), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(requests[5].header("If-None-Match"), None);
+
+        let offline = Fetcher::new(HttpOptions::default()).unwrap().cache(Some(Cache::new(&dir, true).unwrap()));
+        assert_eq!(offline.fetch(&server.url("/etag"), None).unwrap(), "<p>etag</p>");
+        assert_eq!(offline.fetch(&server.url("/plain"), None).unwrap(), "<p>plain</p>");
+        let error = offline.fetch(&server.url("/other"), None).unwrap_err();
+        assert!(matches!(&error, Error::Http(reason) if reason.ends_with("is not in the cache, and the run is offline")), "{}", error);
+        let french = HttpOptions { headers: vec![("Accept-Language".to_string(), "fr".to_string())].into_iter().collect(), ..Default::default() };
+        assert!(offline.fetch(&server.url("/etag"), Some(&french)).is_err());
+        assert_eq!(server.requests().len(), 6);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fetch_obeys_robots() {
+        let server = TestServer::start(|request| match request.path.as_str() {
+            "/robots.txt" => Response::html("User-agent: *\nDisallow: /\n\nUser-agent: dessert2\nDisallow: /private\nAllow: /private/ok\nCrawl-delay: 0.2\n"),
+            _ => Response::html("<p>page</p>")
+        });
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap().robots(true);
+        let start = std::time::Instant::now();
+        assert_eq!(fetcher.fetch(&server.url("/public"), None).unwrap(), "<p>page</p>");
+        assert_eq!(fetcher.fetch(&server.url("/private/ok?page=2"), None).unwrap(), "<p>page</p>");
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        let error = fetcher.fetch(&server.url("/private/secret"), None).unwrap_err();
+        assert!(matches!(&error, Error::RobotsBlocked(reason) if *reason == format!("{} is disallowed by {}", server.url("/private/secret"), server.url("/robots.txt"))), "{}", error);
+        // another user agent falls under `*`
+        let bot = HttpOptions { user_agent: Some("Mozilla/5.0".to_string()), ..Default::default() };
+        assert!(matches!(fetcher.fetch(&server.url("/public"), Some(&bot)), Err(Error::RobotsBlocked(_))));
+        let paths: Vec<String> = server.requests().iter().map(|request| request.path.clone()).collect();
+        assert_eq!(paths, vec!["/robots.txt", "/public", "/private/ok?page=2"]);
+        // without robots, everything is fetched
+        let ignoring = Fetcher::new(HttpOptions::default()).unwrap();
+        assert!(ignoring.fetch(&server.url("/private/secret"), None).is_ok());
+    }
+
+    #[test]
+    fn fetch_without_robots_txt() {
+        let missing = TestServer::start(|request| match request.path.as_str() {
+            "/robots.txt" => Response::status(404, "not found"),
+            _ => Response::html("<p>page</p>")
+        });
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap().robots(true);
+        assert!(fetcher.fetch(&missing.url("/page"), None).is_ok());
+        let failing = TestServer::start(|request| match request.path.as_str() {
+            "/robots.txt" => Response::status(503, "down"),
+            _ => Response::html("<p>page</p>")
+        });
+        let fetcher = Fetcher::new(HttpOptions { retries: Some(1), retry_delay: Some(0.01), ..Default::default() }).unwrap().robots(true);
+        assert!(matches!(fetcher.fetch(&failing.url("/page"), None), Err(Error::RobotsBlocked(_))));
+        assert_eq!(failing.requests().len(), 2);
+    }
+
+    #[test]
+    fn fetch_with_unreachable_robots_txt() {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let fetcher = Fetcher::new(HttpOptions { retries: Some(1), retry_delay: Some(0.01), ..Default::default() }).unwrap().robots(true);
+        let url = format!("http://{}/page", closed);
+        let error = fetcher.fetch(&url, None).unwrap_err();
+        assert!(matches!(&error, Error::RobotsBlocked(reason) if *reason == format!("{} is disallowed by http://{}/robots.txt", url, closed)), "{}", error);
+        assert_eq!(error.exit_code(), 8);
+    }
+}
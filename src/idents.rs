@@ -1,36 +1,226 @@
 use serde::{Serialize, Deserialize};
 
-pub trait Concentrating {
-
-}
-
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Identity {
     pub id: String,
+    #[serde(alias = "selector")]
     pub css_selector: String,
+    #[serde(default, skip_serializing_if = "SelectorType::is_css")]
+    pub selector_type: SelectorType,
     pub value_type: IdentityValueType,
-    pub value_from: ValueFrom
+    #[serde(default)]
+    pub value_from: ValueFrom,
+    #[serde(default, skip_serializing_if = "Whitespace::is_preserve")]
+    pub whitespace: Whitespace,
+    #[serde(default, skip_serializing_if = "Cardinality::is_first")]
+    pub cardinality: Cardinality,
+    /// Post-processing applied in order to the text taken from the element, before it is converted
+    /// to `value_type`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<Transform>,
+    /// The child properties of an `Object` or `Array` identity. They are located relative to the
+    /// element matched by `css_selector`, and may contain further `Object` or `Array` identities.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<Identity>,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub enum IdentityValueType {
     Int,
     Float,
+    #[default]
     Str,
-    Bool
+    Bool,
+    /// A link, like the `href` or `src` of the element, resolved against the URL of the page, or
+    /// its `<base href>`. Every URL of a `srcset` is resolved, with its descriptor kept.
+    Url,
+    /// A nested record built from the first matched element and the child `properties`.
+    Object,
+    /// A list of nested records, one for every matched element.
+    Array
+}
+
+/// The language of the selector in `css_selector`. CSS and XPath selectors can be mixed in the
+/// same template, a property is always located relative to the element matched by its parent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum SelectorType {
+    #[default]
+    Css,
+    /// XPath 1.0. An expression that does not result in a node-set, like `normalize-space(td)`,
+    /// gives the value of the property directly.
+    XPath
+}
+
+impl SelectorType {
+    pub fn is_css(&self) -> bool {
+        *self == SelectorType::Css
+    }
+}
+
+/// Which of the elements matched by an identity's `css_selector` contribute to the value.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum Cardinality {
+    /// The first matched element. This is the default.
+    #[default]
+    First,
+    /// The last matched element.
+    Last,
+    /// Every matched element, the value becomes a list.
+    All,
+    /// The matched element at the given zero-based position.
+    Nth(usize)
+}
+
+impl Cardinality {
+    pub fn is_first(&self) -> bool {
+        *self == Cardinality::First
+    }
+}
+
+/// A step of the post-processing pipeline of a property value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Transform {
+    /// Keep only the text captured by `pattern`, either the whole match or the given `group`. The
+    /// value becomes `NA` if the pattern does not match.
+    Capture {
+        pattern: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<CaptureGroup>
+    },
+    /// Replace every match of `pattern` by `with`, which may refer to groups as `$1` or `$name`.
+    Replace {
+        pattern: String,
+        with: String
+    },
+    /// Remove the leading and trailing whitespaces.
+    Trim
+}
+
+/// A capture group, either by its position or by its name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CaptureGroup {
+    Index(usize),
+    Name(String)
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// Where the value of a property is taken from on the matched element.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub enum ValueFrom {
+    /// The inner HTML of the element, markup included. It is kept for the templates written
+    /// before `TextContent` and `InnerHtml` existed, and behaves exactly like `InnerHtml`.
+    #[default]
     InnerText,
+    /// The text of all the descendant text nodes, concatenated.
+    TextContent,
+    /// The text of the direct text children only, concatenated.
+    OwnText,
+    /// The inner HTML of the element, markup included.
+    InnerHtml,
+    /// The HTML of the element itself, markup included.
+    OuterHtml,
+    /// The value of the given attribute.
     Property(String)
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// How the whitespaces in the value taken from the element are handled.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum Whitespace {
+    /// Keep the value as it is. This is the default.
+    #[default]
+    Preserve,
+    /// Remove the leading and trailing whitespaces.
+    Trim,
+    /// Trim, and replace every run of whitespaces inside the value by a single space.
+    Collapse
+}
+
+impl Whitespace {
+    pub fn is_preserve(&self) -> bool {
+        *self == Whitespace::Preserve
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct IdentObj {
     pub object_id: String,
+    #[serde(alias = "selector")]
     pub css_selector: String,
+    #[serde(default, skip_serializing_if = "SelectorType::is_css")]
+    pub selector_type: SelectorType,
     pub properties: Vec<Identity>,
+    /// The link of every record to a detail page, and what to extract there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub follow: Option<Follow>,
+}
+
+/// Follow a link of every record to the page it leads to, and extract a detail record there. The
+/// detail record is nested in the record of the link, under the `object_id` of `object`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Follow {
+    /// The id of the property with the link, usually a `Url` one.
+    pub property: String,
+    /// The detail record: the first element matched by its selector on the linked page, with its
+    /// properties. It may follow links of its own, down to the depth limit of the run.
+    pub object: Box<IdentObj>,
+    /// Follow the links to other hosts than the one of the page too. By default, they are not.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub any_domain: bool,
+}
+
+/// A template in its document form: the objects, and the rules that go with them. A list of
+/// objects alone is a template as well, see `template_from_json_str`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub objects: Vec<IdentObj>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<Pagination>,
+}
+
+/// How to go from a page of a listing to the next one: either by following the link matched by
+/// `next_selector`, or by counting pages in `url_pattern`. The records of all the pages are
+/// appended to the ones of their object.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pagination {
+    /// The link to the next page. Its `href` is followed, or the value of an XPath expression
+    /// like `//a[@rel='next']/@href`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_selector: Option<String>,
+    #[serde(default, skip_serializing_if = "SelectorType::is_css")]
+    pub selector_type: SelectorType,
+    /// The URL of the pages, with `{page}` in place of the page number, like
+    /// `https://example.com/cities?page={page}`. The listing ends at the first page without any
+    /// record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_pattern: Option<String>,
+    /// The number of the first page, the one given as input, in `url_pattern`, when its URL does not
+    /// tell it. 1 by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<u64>,
+    /// The number of pages read at most, the first one included. 100 by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pages: Option<usize>,
+}
+
+/// Parse a template in either form: a list of objects, or a `Template` document.
+pub fn template_from_json_str(json_str: &str) -> Result<Template, serde_json::Error> {
+    if json_str.trim_start().starts_with('{') {
+        serde_json::from_str(json_str)
+    } else {
+        Ok(Template { objects: from_json_str(json_str)?, pagination: None })
+    }
+}
+
+/// Parse a YAML template in either form, like `template_from_json_str`.
+pub fn template_from_yaml_str(yaml_str: &str) -> Result<Template, serde_yaml::Error> {
+    if let serde_yaml::Value::Mapping(_) = serde_yaml::from_str(yaml_str)? {
+        serde_yaml::from_str(yaml_str)
+    } else {
+        Ok(Template { objects: from_yaml_str(yaml_str)?, pagination: None })
+    }
 }
 
 pub fn from_json_str(json_str: &str) -> Result<Vec<IdentObj>, serde_json::Error> {
@@ -41,12 +231,10 @@ pub fn from_yaml_str(yaml_str: &str) -> Result<Vec<IdentObj>, serde_yaml::Error>
     serde_yaml::from_str(yaml_str)
 }
 
-#[allow(dead_code)]
 pub fn to_json_str(idents: &Vec<IdentObj>) -> Result<String, serde_json::Error> {
     serde_json::to_string(idents)
 }
 
-#[allow(dead_code)]
 pub fn to_yaml_str(idents: &Vec<IdentObj>) -> Result<String, serde_yaml::Error> {
     serde_yaml::to_string(idents)
 }
@@ -64,13 +252,16 @@ mod tests {
                         id: "email".to_owned(),
                         css_selector: "div#email".to_owned(),
                         value_type: IdentityValueType::Str,
-                        value_from: ValueFrom::InnerText
+                        value_from: ValueFrom::InnerText,
+                        ..Default::default()
                 }, Identity {
                         id: "address".to_owned(),
                         css_selector: "div#address".to_owned(),
                         value_type: IdentityValueType::Str,
-                        value_from: ValueFrom::InnerText
-                }]
+                        value_from: ValueFrom::InnerText,
+                        ..Default::default()
+                }],
+                ..Default::default()
             },
             IdentObj {
                     object_id: "book-info".to_owned(),
@@ -79,13 +270,16 @@ mod tests {
                             id: "isn".to_owned(),
                             css_selector: "div#isn".to_owned(),
                             value_type: IdentityValueType::Str,
-                            value_from: ValueFrom::InnerText
+                            value_from: ValueFrom::InnerText,
+                            ..Default::default()
                     }, Identity {
                             id: "price".to_owned(),
                             css_selector: "div#price".to_owned(),
                             value_type: IdentityValueType::Str,
-                            value_from: ValueFrom::InnerText
-            }]
+                            value_from: ValueFrom::InnerText,
+                            ..Default::default()
+            }],
+            ..Default::default()
         }]
     }
 
@@ -142,4 +336,147 @@ mod tests {
 
         assert_eq!(ident, deserialized);
     }
+
+    #[test]
+    fn deserialize_nested_from_yaml() {
+        let idents = from_yaml_str(r#"---
+- object_id: product
+  css_selector: "div.product"
+  properties:
+    - id: reviews
+      css_selector: "div.review"
+      value_type: Array
+      properties:
+        - id: author
+          css_selector: "span.author"
+          value_type: Str
+"#).unwrap();
+        assert_eq!(idents, vec![IdentObj {
+            object_id: "product".to_owned(),
+            css_selector: "div.product".to_owned(),
+            properties: vec![Identity {
+                    id: "reviews".to_owned(),
+                    css_selector: "div.review".to_owned(),
+                    value_type: IdentityValueType::Array,
+                    value_from: ValueFrom::InnerText,
+                    properties: vec![Identity {
+                            id: "author".to_owned(),
+                            css_selector: "span.author".to_owned(),
+                            value_type: IdentityValueType::Str,
+                            value_from: ValueFrom::InnerText,
+                            ..Default::default()
+                    }],
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }]);
+    }
+
+    #[test]
+    fn deserialize_cardinality_from_yaml() {
+        let idents = from_yaml_str(r#"---
+- object_id: article
+  css_selector: "div.article"
+  properties:
+    - id: tags
+      css_selector: "a.tag"
+      value_type: Str
+      cardinality: All
+    - id: second-author
+      css_selector: "span.author"
+      value_type: Str
+      cardinality:
+        Nth: 1
+"#).unwrap();
+        assert_eq!(idents[0].properties[0].cardinality, Cardinality::All);
+        assert_eq!(idents[0].properties[1].cardinality, Cardinality::Nth(1));
+    }
+
+    #[test]
+    fn serialize_default_cardinality_is_omitted() {
+        let idents = construct_ident_obj();
+        assert!(!to_yaml_str(&idents).unwrap().contains("cardinality"));
+    }
+
+    #[test]
+    fn deserialize_transforms_from_yaml() {
+        let idents = from_yaml_str(r#"---
+- object_id: product
+  css_selector: "div.product"
+  properties:
+    - id: price
+      css_selector: "span.price"
+      value_type: Float
+      transforms:
+        - Capture:
+            pattern: '\$(?P<amount>[0-9.]+)'
+            group: amount
+        - Replace:
+            pattern: ","
+            with: ""
+        - Capture:
+            pattern: "[0-9]+"
+            group: 0
+        - Trim
+"#).unwrap();
+        assert_eq!(idents[0].properties[0].transforms, vec![
+            Transform::Capture { pattern: "\\$(?P<amount>[0-9.]+)".to_owned(), group: Some(CaptureGroup::Name("amount".to_owned())) },
+            Transform::Replace { pattern: ",".to_owned(), with: "".to_owned() },
+            Transform::Capture { pattern: "[0-9]+".to_owned(), group: Some(CaptureGroup::Index(0)) },
+            Transform::Trim
+        ]);
+    }
+
+    #[test]
+    fn deserialize_value_from_and_whitespace() {
+        let idents = from_json_str(r#"[{"object_id":"a","css_selector":"div","properties":[{"id":"text","css_selector":"p","value_type":"Str","value_from":"TextContent","whitespace":"Collapse"},{"id":"html","css_selector":"p","value_type":"Str","value_from":"OuterHtml"}]}]"#).unwrap();
+        assert_eq!(idents[0].properties[0].value_from, ValueFrom::TextContent);
+        assert_eq!(idents[0].properties[0].whitespace, Whitespace::Collapse);
+        assert_eq!(idents[0].properties[1].value_from, ValueFrom::OuterHtml);
+        assert_eq!(idents[0].properties[1].whitespace, Whitespace::Preserve);
+    }
+
+    #[test]
+    fn deserialize_xpath_selector() {
+        let idents = from_yaml_str(r#"---
+- object_id: specs
+  selector: "//table[@id='specs']"
+  selector_type: XPath
+  properties:
+    - id: price
+      selector: "tr/th[text()='Price']/following-sibling::td"
+      selector_type: XPath
+      value_type: Str
+    - id: title
+      css_selector: "caption"
+      value_type: Str
+"#);
+        let idents = idents.unwrap();
+        assert_eq!(idents[0].selector_type, SelectorType::XPath);
+        assert_eq!(idents[0].css_selector, "//table[@id='specs']");
+        assert_eq!(idents[0].properties[0].selector_type, SelectorType::XPath);
+        assert_eq!(idents[0].properties[1].selector_type, SelectorType::Css);
+    }
+
+    #[test]
+    fn deserialize_template_forms() {
+        let list = template_from_json_str(r#"[{"object_id": "a", "css_selector": "li", "properties": []}]"#).unwrap();
+        assert_eq!(list.objects[0].object_id, "a");
+        assert_eq!(list.pagination, None);
+        let document = template_from_yaml_str(r#"---
+objects:
+  - object_id: cities
+    css_selector: "li.city"
+    properties: []
+pagination:
+  next_selector: "a[rel=next]"
+  max_pages: 5
+"#).unwrap();
+        assert_eq!(document.objects[0].object_id, "cities");
+        assert_eq!(document.pagination, Some(Pagination { next_selector: Some("a[rel=next]".to_owned()), max_pages: Some(5), ..Default::default() }));
+        let document = template_from_json_str(r#"{"objects": [], "pagination": {"url_pattern": "https://a.com/?p={page}", "start": 0}}"#).unwrap();
+        assert_eq!(document.pagination.unwrap().start, Some(0));
+        assert_eq!(template_from_yaml_str("- object_id: a\n  css_selector: li\n  properties: []\n").unwrap().objects.len(), 1);
+        assert!(template_from_json_str(r#"{"objects": [], "paging": {}}"#).unwrap_err().to_string().contains("unknown field `paging`"));
+    }
 }
@@ -0,0 +1,96 @@
+//! `dessert2` extracts values from an HTML document with a template of CSS or XPath selectors.
+//!
+//! A template is a list of `IdentObj`, compiled once into a `CompiledTemplate`. Every element matched by the selector of an object becomes
+//! a record, whose fields are the values of its `properties`. Templates are usually read from
+//! JSON or YAML:
+//!
+//! ```
+//! use dessert2::{idents, CompiledTemplate, Extractor, OutputFormat, PropertyValue};
+//!
+//! let idents = idents::from_yaml_str(r#"---
+//! - object_id: prices
+//!   css_selector: tr
+//!   properties:
+//!     - id: item
+//!       css_selector: th
+//!       value_type: Str
+//!     - id: price
+//!       css_selector: td
+//!       value_type: Float
+//! "#).unwrap();
+//! let html = "<table><tr><th>Tea</th><td>3.5</td></tr><tr><th>Cake</th><td>4</td></tr></table>";
+//!
+//! let template = CompiledTemplate::compile(&idents)?;
+//! let result = Extractor::new(html, OutputFormat::Json).extract(&template)?;
+//! assert_eq!(result[0].records.len(), 2);
+//! assert_eq!(result[0].records[0]["price"], PropertyValue::Float(3.5));
+//! # Ok::<(), dessert2::ExtractError>(())
+//! ```
+//!
+//! They can be built in code as well, and the result serialized in any of the output formats:
+//!
+//! ```
+//! use dessert2::{Cardinality, CompiledTemplate, Extractor, IdentObj, Identity, IdentityValueType, Output, OutputFormat, SelectorType};
+//!
+//! let idents = vec![IdentObj {
+//!     object_id: "article".to_string(),
+//!     css_selector: "article".to_string(),
+//!     properties: vec![
+//!         Identity {
+//!             id: "title".to_string(),
+//!             css_selector: "h1".to_string(),
+//!             value_type: IdentityValueType::Str,
+//!             ..Default::default()
+//!         },
+//!         Identity {
+//!             id: "tags".to_string(),
+//!             css_selector: ".//a[@rel='tag']".to_string(),
+//!             selector_type: SelectorType::XPath,
+//!             value_type: IdentityValueType::Str,
+//!             cardinality: Cardinality::All,
+//!             ..Default::default()
+//!         },
+//!     ],
+//!     ..Default::default()
+//! }];
+//! let html = r#"<article><h1>Hello</h1><a rel="tag">rust</a><a rel="tag">html</a></article>"#;
+//!
+//! let template = CompiledTemplate::compile(&idents)?;
+//! let output = Extractor::new(html, OutputFormat::Json).abstract_objs(&template)?;
+//! assert_eq!(output, Output::Json(r#"[{"object_id":"article","records":[{"title":"Hello","tags":["rust","html"]}]}]"#.to_string()));
+//! # Ok::<(), dessert2::ExtractError>(())
+//! ```
+
+pub mod batch;
+pub mod cache;
+pub mod encoding;
+pub mod error;
+pub mod extract;
+pub mod follow;
+pub mod http;
+pub mod idents;
+pub mod output;
+pub mod pagination;
+/// The command line of the `dessert2` binary, not a part of the API of the library.
+#[doc(hidden)]
+pub mod params;
+pub mod pool;
+pub mod robots;
+pub mod sitemap;
+pub mod table;
+pub mod template;
+pub mod xpath;
+
+#[cfg(test)]
+mod test_server;
+
+pub use batch::{Batch, BatchSummary, Input, Source};
+pub use cache::Cache;
+pub use error::Error;
+pub use http::{Auth, Fetcher, HttpOptions};
+pub use extract::{ExtractError, Extractor, IntermediateObject, IntermediateResult, OutputFormat, PropertyValue, Record};
+pub use follow::Follower;
+pub use idents::{Cardinality, CaptureGroup, Follow, IdentObj, Identity, IdentityValueType, Pagination, SelectorType, Template, Transform, ValueFrom, Whitespace};
+pub use output::Output;
+pub use robots::RobotsTxt;
+pub use template::CompiledTemplate;
@@ -1,16 +1,40 @@
-use extract::OutputFormat;
-use idents::IdentObj;
-use std::env;
+use dessert2::{batch, extract, pagination, params, Cache, CompiledTemplate, Error, Fetcher, Follower, Input, Output, OutputFormat};
+use dessert2::params::{Command, Config};
+use std::{env, io, process};
 
-mod idents;
-mod output;
-mod extract;
-mod params;
-
-/// Main entry. Those parameters are acceptable:
-/// `--url String`: Read html text from the given url. If this parameter is absent, the propgram
+/// Main entry. Those parameters are acceptable, run `dessert2 --help` for the full list:
+/// `--url`, `-u String`: Read html text from the given url. If this parameter is absent, the propgram
 /// will read the HTML from stdin.
-/// `--template String`: The template that the propgram uses to extract content from the HTML. The
+/// `--input`, `-i String`: Read the HTML from the file, or from every `.html`, `.htm` and `.xhtml`
+/// file under the directory. Files ending with `.gz` or `.br` are decompressed.
+/// `--url-list`, `-l String` and `--glob`, `-g String`: Together with a repeated `--url`, run the
+/// template over a batch of URLs and files. Every result is tagged with its source, a failed input
+/// is reported on stderr without stopping the batch, and a summary is printed at the end.
+/// `--sitemap String`: Add the URLs of the sitemap, or of every sitemap of the sitemap index, a
+/// file or a URL, compressed or not, to the batch. `--sitemap-filter REGEX` and
+/// `--sitemap-since DATE` keep the URLs that match and the ones modified since the day.
+/// `--concurrency`, `-j N`: Fetch and extract up to N inputs of a batch at the same time, with at
+/// most `--per-host N` requests to the same host at a time, `--host-delay MS` apart. The results
+/// are output as soon as they are done, or in the order of the inputs with `--ordered`.
+/// `--header`, `-H String`, `--cookie-jar`, `-b String`, `--user-agent`, `-A String`,
+/// `--connect-timeout SECS`, `--timeout SECS`, `--proxy String`, `--max-redirects N`,
+/// `--basic-auth USER:PASS` and `--bearer TOKEN`: How the URLs are fetched. A line of the
+/// URL list can also be a JSON object with the `url` and the options of this URL only.
+/// `--retries N`, `--retry-on CODES`, `--retry-delay SECS` and `--allow-status CODES`: Network
+/// errors and the `--retry-on` statuses are retried with an exponential backoff, or after the
+/// `Retry-After` of the response. Any other status than 2xx and `--allow-status` is an error.
+/// `--cache-dir String` and `--offline`: Keep the fetched pages in the directory, and revalidate
+/// them with their `ETag` and `Last-Modified`. Offline, only the cached pages are read.
+/// `--encoding`, `-e String`: Decode the inputs with this encoding. By default, the encoding is
+/// taken from the byte order mark, the `Content-Type` header or the `<meta>` charset, else UTF-8.
+/// `--ignore-robots`: Fetch the URLs that the robots.txt of their site disallows, and do not wait
+/// for its `Crawl-delay`. By default, a disallowed URL is an error.
+/// `--max-depth N`: Follow the links of the `follow` rules of the template down to N pages from the
+/// one of the input, 3 by default.
+/// `--base-url String`: Resolve the `Url` values of the files and of stdin against this URL, as the
+/// ones of a page are resolved against its URL.
+/// `--verbose`, `-v`: Report the retries and the final status of every request on stderr.
+/// `--template`, `-t String`: The template that the propgram uses to extract content from the HTML. The
 /// program treats it as yaml if the text start with `---`, or else it is `json`. The
 /// example of the template format:
 /// ```
@@ -27,54 +51,115 @@ mod params;
 ///     value_type: Str
 ///     value_from: InnerText
 /// ```
-/// `--template-file String`: The template file that the program uses to extract content form the
+/// The template can also be a document with the objects under `objects`, and a `pagination` rule
+/// to read the next pages of a listing, by a `next_selector` link or a `url_pattern`. An object can
+/// `follow` a link of its records to a detail page, whose record is nested in theirs.
+/// `--template-file`, `-f String`: The template file that the program uses to extract content form the
 /// HTML.
-/// `--output-format String`: This could either be `json`, `yaml`, or `text`. The default value is
-/// `yaml`. If `text` is given, it will print out only the first property value from the result.
+/// `--output-format`, `-o String`: This could either be `json`, `yaml`, `text`, `csv`, `tsv` or `ndjson`. The
+/// default value is `yaml`. If `text` is given, it will print out only the first property value from
+/// the result.
+/// `ndjson` writes one `{"object_id": ..., "record": {...}}` line per record, as soon as it is
+/// extracted.
+/// `--table-layout String`: For `csv` and `tsv`, either `stream` (the default), a single table with
+/// an `object_id` column, or `per-object`, one `<object_id>.csv` file per object.
+/// `--output-dir`, `-d String`: The directory of the `per-object` files. The default is the current one.
+/// `--help`, `-h` and `--version`, `-V` print the usage and the version.
+///
+/// Errors are reported on stderr, and the program exits with the code of their category, see
+/// `error::Error`: 1 when the template matched nothing, 2 for invalid arguments, 3 for an invalid
+/// template, 4 for IO errors, 5 for network errors, 6 for extraction errors, 7 when some inputs
+/// of a batch failed and 8 when the robots.txt of the site disallows the URL.
 fn main() {
-    let params = params::parse_params(env::args());
-    let html = params::read_html(&params);
-    let template = params::read_template(&params);
-    let output_format = params::parse_output_format(&params);
-
-    let output = parse(&*html, &template, output_format);
-    println!("{}", output);
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        process::exit(err.exit_code());
+    }
 }
 
-#[allow(dead_code)]
-fn parse(src: &str, abstract_template: &Vec<IdentObj>, output_format: OutputFormat) -> String {
-    let extractor = extract::Extractor::new(src, output_format);
-    match extractor.abstract_objs(abstract_template).unwrap() {
-        output::Output::Json(text) => text,
-        output::Output::Yaml(text) => text,
-        output::Output::Text(text) => text,
-        _ => "".to_owned()
+fn run() -> Result<(), Error> {
+    let config = match params::parse_params(env::args().skip(1))? {
+        Command::Run(config) => *config,
+        Command::Help => {
+            print!("{}", params::usage());
+            return Ok(());
+        },
+        Command::Version => {
+            println!("dessert2 {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+    };
+    let template = params::read_template(&config)?;
+    let mut inputs = params::collect_inputs(&config)?;
+    let cache = config.cache_dir.as_ref().map(|dir| Cache::new(dir, config.offline)).transpose()?;
+    let fetcher = Fetcher::new(config.http.clone())?.verbose(config.verbose).cache(cache).robots(!config.ignore_robots)
+        .host_limits(&config.pool);
+    inputs.extend(params::sitemap_inputs(&config, &fetcher)?);
+    if config.is_batch() {
+        return run_batch(&config, &template, &inputs, &fetcher);
     }
+    let output_format = config.output_format;
+
+    let matched = if let OutputFormat::Ndjson = output_format {
+        let mut count = 0;
+        let mut follower = Follower::new(&inputs[0], &fetcher, &template);
+        pagination::for_each_page(&inputs[0], &fetcher, &template, output_format, |extractor, base_url| {
+            // the records wait for their detail pages, the others are written as they are extracted
+            let written = if template.has_follow() {
+                let mut page = extractor.extract(&template)?;
+                follower.follow(&mut page, &template, base_url)?;
+                for (url, err) in follower.take_failed() {
+                    eprintln!("error: {}: {}", url, err);
+                }
+                extract::write_ndjson(&page, &mut io::stdout().lock())?
+            } else {
+                extractor.stream_objs(&template, &mut io::stdout().lock())?
+            };
+            count += written;
+            Ok(written > 0)
+        })?;
+        count > 0
+    } else {
+        let intermediate = batch::extract_input(&inputs[0], &fetcher, &template, output_format, |url, err| eprintln!("error: {}: {}", url, err))?;
+        print_output(&config, extract::intermediate_to_output(&intermediate, template.idents(), &output_format)?)?;
+        intermediate.iter().any(|object| !object.records.is_empty())
+    };
+    if matched { Ok(()) } else { Err(Error::NoMatches) }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use idents::{Identity, IdentityValueType, ValueFrom};
+fn run_batch(config: &Config, template: &CompiledTemplate, inputs: &[Input], fetcher: &Fetcher) -> Result<(), Error> {
+    let (output, summary) = batch::run_inputs(inputs, fetcher, template, config.output_format, &config.pool, &mut io::stdout(),
+        |source, err| eprintln!("error: {}: {}", source, err))?;
+    print_output(config, output)?;
+    eprintln!("{}", summary);
+    if summary.failed > 0 {
+        Err(Error::Batch { failed: summary.failed, total: summary.total })
+    } else if summary.matched == 0 {
+        Err(Error::NoMatches)
+    } else {
+        Ok(())
+    }
+}
 
-    #[test]
-    fn test_parse() {
-        let source_html = r#"<html><head></head><body><div id="user_info"><div id="address">Ontario, Canada</div><div id="email">abc@abc.com</div></div></body></html>"#;
-        let text = parse(source_html, &vec![ IdentObj {
-            object_id: "user-info".to_owned(),
-            css_selector: "div#user_info".to_owned(),
-            properties: vec![Identity {
-                    id: "address".to_owned(),
-                    css_selector: "div#address".to_owned(),
-                    value_type: IdentityValueType::Str,
-                    value_from: ValueFrom::InnerText
-            }]
-        }], OutputFormat::Yaml);
+fn print_output(config: &Config, output: Output) -> Result<(), Error> {
+    match output {
+        Output::Files(files) => params::write_output_files(config, &files)?,
+        Output::Csv(table) | Output::Tsv(table) | Output::Ndjson(table) => print!("{}", table),
+        Output::Empty => {},
+        output => println!("{}", output_text(output))
+    }
+    Ok(())
+}
 
-        assert_eq!(text, r#"---
-- object_id: user-info
-  records:
-    - address: "Ontario, Canada"
-"#)
+fn output_text(output: Output) -> String {
+    match output {
+        Output::Json(text) => text,
+        Output::Yaml(text) => text,
+        Output::Text(text) => text,
+        Output::Csv(text) => text,
+        Output::Tsv(text) => text,
+        Output::Ndjson(text) => text,
+        _ => "".to_owned()
     }
 }
+
@@ -1,12 +1,17 @@
 /// The output represents the text after the abstraction from the source.
-/// It could be either Json format, or Yaml format, or plain text. Or Nothing if nothing could be
-/// extracted from the source.
-#[allow(dead_code)]
+/// It could be either Json format, or Yaml format, or plain text, or a CSV or TSV table, or NDJSON. Or a set of
+/// named files, one table per object. Or Nothing if nothing could be extracted from the source.
 #[derive(Debug, PartialEq)]
 pub enum Output {
     Json(String),
     Yaml(String),
     Text(String),
+    Csv(String),
+    Tsv(String),
+    /// One JSON record per line
+    Ndjson(String),
+    /// (file name, content)
+    Files(Vec<(String, String)>),
     Empty
 }
 
@@ -22,29 +27,24 @@ impl<T: Into<String> + Sized> std::convert::From<T> for Output {
 }
 
 impl Output {
-    #[allow(dead_code)]
     #[inline]
     pub fn from_json<T: Into<String>>(json: T) -> Self {
         Self::Json(json.into())
     }
-    #[allow(dead_code)]
     #[inline]
     pub fn from_yaml<T: Into<String>>(yaml: T) -> Self {
         Self::Yaml(yaml.into())
     }
-    #[allow(dead_code)]
     #[inline]
     pub fn json<C: Into<String>>(val: C) -> Self {
         Self::Json(val.into()) 
     }
 
-    #[allow(dead_code)]
     #[inline]
     pub fn yaml<C: Into<String>>(val: C) -> Self {
         Self::Yaml(val.into())
     }
 
-    #[allow(dead_code)]
     #[inline]
     pub fn text<C: Into<String>>(val: C) -> Self {
         Self::Text(val.into())
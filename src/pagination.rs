@@ -0,0 +1,166 @@
+use crate::batch::{self, Input, Source};
+use crate::error::Error;
+use crate::extract::{Extractor, OutputFormat};
+use crate::http::Fetcher;
+use crate::template::{CompiledTemplate, NextPage};
+
+use regex::Regex;
+use url::Url;
+
+use std::collections::HashSet;
+
+/// Read an input, then the next pages of its listing when the template has a pagination, and call
+/// `page` with the extractor and the URL of every page, in order. Every page is parsed once, and
+/// its extractor resolves the links against its URL. `page` returns whether the page had any
+/// record, a listing by `url_pattern` ends at the first page without one. Only URLs are
+/// paginated, the next pages are fetched like the first one, with the same HTTP options.
+pub fn for_each_page<F>(input: &Input, fetcher: &Fetcher, template: &CompiledTemplate, output_format: OutputFormat, mut page: F) -> Result<(), Error>
+    where F: FnMut(&Extractor, Option<&Url>) -> Result<bool, Error> {
+    let html = batch::read_html(input, fetcher)?;
+    let current = match &input.source {
+        Source::Url(url) => Url::parse(url).map_err(|err| Error::Http(format!("invalid URL `{}`: {}", url, err)))?,
+        _ => return page(&Extractor::new(&html, output_format).base_url(input.base_url.as_ref()), input.base_url.as_ref()).map(|_| ())
+    };
+    let mut extractor = Extractor::new(&html, output_format).base_url(Some(&current));
+    let mut matched = page(&extractor, Some(&current))?;
+    let pagination = match &template.pagination {
+        Some(pagination) => pagination,
+        None => return Ok(())
+    };
+    let mut visited = HashSet::new();
+    visited.insert(without_fragment(current.clone()));
+    // the number of the input in the pattern, when its URL is one of the pattern, else `start`
+    let first = match &pagination.next {
+        NextPage::Pattern(pattern, start) => {
+            visited.insert(without_fragment(page_url(pattern, *start).unwrap_or_else(|| current.clone())));
+            let url = match &input.source {
+                Source::Url(url) => url.as_str(),
+                _ => current.as_str()
+            };
+            page_number(pattern, url).or_else(|| page_number(pattern, current.as_str())).unwrap_or(*start)
+        },
+        NextPage::Link(_) => 0
+    };
+    for index in 1..pagination.max_pages {
+        let next = match &pagination.next {
+            NextPage::Link(selector) => extractor.link(selector)?,
+            NextPage::Pattern(_, _) if !matched => None,
+            NextPage::Pattern(pattern, _) => page_url(pattern, first + index as u64)
+        };
+        // the end of the listing, a link out of the web, or back to a page already read
+        let next = match next.map(without_fragment) {
+            Some(next) if matches!(next.scheme(), "http" | "https") && visited.insert(next.clone()) => next,
+            _ => break
+        };
+        let html = batch::read_html(&Input { source: Source::Url(next.to_string()), http: input.http.clone(), base_url: None }, fetcher)?;
+        extractor = Extractor::new(&html, output_format).base_url(Some(&next));
+        matched = page(&extractor, Some(&next))?;
+    }
+    Ok(())
+}
+
+fn page_url(pattern: &str, number: u64) -> Option<Url> {
+    Url::parse(&pattern.replace("{page}", &number.to_string())).ok()
+}
+
+// the number of the page at `url` in the pattern, like 3 for `?page=3` in `?page={page}`
+fn page_number(pattern: &str, url: &str) -> Option<u64> {
+    let pattern = format!("^{}$", regex::escape(pattern).replace(r"\{page\}", "([0-9]+)"));
+    Regex::new(&pattern).ok()?.captures(url)?.get(1)?.as_str().parse().ok()
+}
+
+fn without_fragment(mut url: Url) -> Url {
+    url.set_fragment(None);
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch;
+    use crate::http::HttpOptions;
+    use crate::idents::{self, Pagination, Template};
+    use crate::test_server::{Response, TestServer};
+
+    const OBJECTS: &str = r#"[{"object_id": "cities", "css_selector": "li", "properties": [{"id": "name", "css_selector": "b", "value_type": "Str"}]}]"#;
+
+    fn template(pagination: Pagination) -> CompiledTemplate {
+        CompiledTemplate::compile_template(&Template { objects: idents::from_json_str(OBJECTS).unwrap(), pagination: Some(pagination) }).unwrap()
+    }
+
+    fn listing() -> TestServer {
+        TestServer::start(|request| match request.path.as_str() {
+            "/list" | "/list?page=1" => Response::html("<li><b>Oslo</b></li><a class='next' href='?page=2'>next</a>"),
+            "/list?page=2" => Response::html("<li><b>Rome</b></li><a class='next' href='/list?page=3#top'>next</a>"),
+            "/list?page=3" => Response::html("<li><b>Lima</b></li><a class='next' href='http://127.0.0.1:1/list'>next</a><a rel='loop' href='/list'>first</a>"),
+            _ => Response::html("<p>no more cities</p>")
+        })
+    }
+
+    fn names(template: &CompiledTemplate, url: &str) -> Vec<String> {
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        let result = batch::extract_input(&Source::Url(url.to_string()).into(), &fetcher, template, OutputFormat::Json, |_, _| {}).unwrap();
+        assert_eq!(result.len(), 1);
+        result[0].records.iter().map(|record| record["name"].to_string()).collect()
+    }
+
+    #[test]
+    fn follow_next_links() {
+        let server = listing();
+        let next = template(Pagination { next_selector: Some("a.next".to_string()), max_pages: Some(3), ..Default::default() });
+        assert_eq!(names(&next, &server.url("/list")), vec!["Oslo", "Rome", "Lima"]);
+        let paths: Vec<String> = server.requests().iter().map(|request| request.path.clone()).collect();
+        assert_eq!(paths, vec!["/list", "/list?page=2", "/list?page=3"]);
+
+        let limited = template(Pagination { next_selector: Some("a.next".to_string()), max_pages: Some(2), ..Default::default() });
+        assert_eq!(names(&limited, &server.url("/list")), vec!["Oslo", "Rome"]);
+        // a link back to a page already read ends the listing
+        let xpath = template(Pagination { next_selector: Some("//a[@rel='loop' or @class='next'][last()]/@href".to_string()), selector_type: idents::SelectorType::XPath, ..Default::default() });
+        assert_eq!(names(&xpath, &server.url("/list")), vec!["Oslo", "Rome", "Lima"]);
+    }
+
+    #[test]
+    fn count_pages_in_a_pattern() {
+        let server = listing();
+        let pattern = template(Pagination { url_pattern: Some(server.url("/list?page={page}")), ..Default::default() });
+        assert_eq!(names(&pattern, &server.url("/list?page=1")), vec!["Oslo", "Rome", "Lima"]);
+        assert_eq!(server.requests().last().unwrap().path, "/list?page=4");
+        // the number of the input is the one of its URL in the pattern, else `start`
+        let from_zero = template(Pagination { url_pattern: Some(server.url("/list?page={page}")), start: Some(0), max_pages: Some(2), ..Default::default() });
+        assert_eq!(names(&from_zero, &server.url("/list?page=1")), vec!["Oslo", "Rome"]);
+        let from_two = template(Pagination { url_pattern: Some(server.url("/list?page={page}")), max_pages: Some(2), ..Default::default() });
+        assert_eq!(names(&from_two, &server.url("/list?page=2")), vec!["Rome", "Lima"]);
+        assert_eq!(names(&from_two, &server.url("/list")), vec!["Oslo", "Rome"]);
+        let paths: Vec<String> = server.requests().iter().skip(4).map(|request| request.path.clone()).collect();
+        assert_eq!(paths, vec!["/list?page=1", "/list?page=2", "/list?page=2", "/list?page=3", "/list", "/list?page=2"]);
+        assert_eq!(page_number("https://a.com/p/{page}.html", "https://a.com/p/12.html"), Some(12));
+        assert_eq!(page_number("https://a.com/p/{page}.html", "https://a.com/p/x.html"), None);
+    }
+
+    #[test]
+    fn links_resolve_against_every_page() {
+        let server = listing();
+        let pagination = Pagination { next_selector: Some("a.next".to_string()), max_pages: Some(2), ..Default::default() };
+        let objects = r#"[{"object_id": "next", "css_selector": "body", "properties": [{"id": "url", "css_selector": "a.next", "value_type": "Url", "value_from": {"Property": "href"}}]}]"#;
+        let template = CompiledTemplate::compile_template(&Template { objects: idents::from_json_str(objects).unwrap(), pagination: Some(pagination) }).unwrap();
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        let result = batch::extract_input(&Source::Url(server.url("/list")).into(), &fetcher, &template, OutputFormat::Json, |_, _| {}).unwrap();
+        let urls: Vec<String> = result[0].records.iter().map(|record| record["url"].to_string()).collect();
+        assert_eq!(urls, vec![server.url("/list?page=2"), server.url("/list?page=3#top")]);
+    }
+
+    #[test]
+    fn files_are_not_paginated() {
+        let path = std::env::temp_dir().join(format!("dessert2-page-{}.html", std::process::id()));
+        std::fs::write(&path, "<li><b>Oslo</b></li><a class='next' href='https://example.invalid/'>next</a>").unwrap();
+        let next = template(Pagination { next_selector: Some("a.next".to_string()), ..Default::default() });
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        let mut pages = 0;
+        for_each_page(&Source::File(path.clone()).into(), &fetcher, &next, OutputFormat::Json, |_, _| {
+            pages += 1;
+            Ok(true)
+        }).unwrap();
+        assert_eq!(pages, 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,121 +1,846 @@
-use std::{collections::HashMap, fs::read_to_string, io};
+use std::{collections::HashMap, fmt, fs::{self, read_to_string}, io::{self, Read}, path::{Path, PathBuf}, time::Duration};
 
-use crate::{extract::OutputFormat, idents::{self, IdentObj}};
+use indexmap::IndexMap;
+use regex::Regex;
+use reqwest::header::{HeaderName, HeaderValue};
+use url::Url;
+
+use crate::{batch::{Input, Source}, encoding, error::Error, http::{Auth, Fetcher, HttpOptions}, pool::{PoolOptions, DEFAULT_PER_HOST}, sitemap::{self, Day, SitemapFilter}, extract::OutputFormat, idents::{self, Template}, table::{self, TableLayout}, template::{CompiledTemplate, DEFAULT_MAX_DEPTH}};
 
 const TEMPLATE: &str = "template";
 const TEMPLATE_FILE: &str = "template-file";
 const OUTPUT_FORMAT: &str = "output-format";
 const URL: &str = "url";
+const INPUT: &str = "input";
+const URL_LIST: &str = "url-list";
+const GLOB: &str = "glob";
+const SITEMAP: &str = "sitemap";
+const SITEMAP_FILTER: &str = "sitemap-filter";
+const SITEMAP_SINCE: &str = "sitemap-since";
+const TABLE_LAYOUT: &str = "table-layout";
+const OUTPUT_DIR: &str = "output-dir";
+const CONCURRENCY: &str = "concurrency";
+const PER_HOST: &str = "per-host";
+const HOST_DELAY: &str = "host-delay";
+const ORDERED: &str = "ordered";
+const HEADER: &str = "header";
+const COOKIE_JAR: &str = "cookie-jar";
+const USER_AGENT: &str = "user-agent";
+const CONNECT_TIMEOUT: &str = "connect-timeout";
+const TIMEOUT: &str = "timeout";
+const PROXY: &str = "proxy";
+const MAX_REDIRECTS: &str = "max-redirects";
+const BASIC_AUTH: &str = "basic-auth";
+const BEARER: &str = "bearer";
+const RETRIES: &str = "retries";
+const RETRY_ON: &str = "retry-on";
+const RETRY_DELAY: &str = "retry-delay";
+const ALLOW_STATUS: &str = "allow-status";
+const VERBOSE: &str = "verbose";
+const ENCODING: &str = "encoding";
+const BASE_URL: &str = "base-url";
+const MAX_DEPTH: &str = "max-depth";
+const IGNORE_ROBOTS: &str = "ignore-robots";
+const CACHE_DIR: &str = "cache-dir";
+const OFFLINE: &str = "offline";
+const HELP: &str = "help";
+const VERSION: &str = "version";
+
+/// A command line option. Options without a `value` are flags, `repeatable` ones can be given more
+/// than once.
+struct OptionSpec {
+    long: &'static str,
+    short: Option<char>,
+    value: Option<&'static str>,
+    repeatable: bool,
+    help: &'static str
+}
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { long: URL, short: Some('u'), value: Some("URL"), repeatable: true, help: "Read the HTML from the URL instead of stdin, repeat it for a batch" },
+    OptionSpec { long: INPUT, short: Some('i'), value: Some("PATH"), repeatable: true, help: "Read the HTML file, or every HTML file under the directory, also .gz or .br" },
+    OptionSpec { long: URL_LIST, short: Some('l'), value: Some("FILE"), repeatable: false, help: "Read a batch of URLs and paths from the file, one per line, `-` for stdin" },
+    OptionSpec { long: GLOB, short: Some('g'), value: Some("PATTERN"), repeatable: true, help: "Add the files matching the glob pattern to the batch" },
+    OptionSpec { long: SITEMAP, short: None, value: Some("LOCATION"), repeatable: true, help: "Add the URLs of the sitemap or sitemap index, a file or a URL, also .gz" },
+    OptionSpec { long: SITEMAP_FILTER, short: None, value: Some("REGEX"), repeatable: false, help: "Keep the URLs of the sitemaps that match the regular expression" },
+    OptionSpec { long: SITEMAP_SINCE, short: None, value: Some("DATE"), repeatable: false, help: "Keep the URLs of the sitemaps modified on the day, like 2024-05-01, or later" },
+    OptionSpec { long: TEMPLATE, short: Some('t'), value: Some("TEMPLATE"), repeatable: false, help: "The template, as YAML if it starts with `---`, as JSON otherwise" },
+    OptionSpec { long: TEMPLATE_FILE, short: Some('f'), value: Some("FILE"), repeatable: false, help: "Read the template from a JSON or YAML file" },
+    OptionSpec { long: OUTPUT_FORMAT, short: Some('o'), value: Some("FORMAT"), repeatable: false, help: "yaml (default), json, text, csv, tsv or ndjson" },
+    OptionSpec { long: TABLE_LAYOUT, short: None, value: Some("LAYOUT"), repeatable: false, help: "For csv and tsv: stream (default) or per-object" },
+    OptionSpec { long: OUTPUT_DIR, short: Some('d'), value: Some("DIR"), repeatable: false, help: "The directory of the per-object tables, the current one by default" },
+    OptionSpec { long: CONCURRENCY, short: Some('j'), value: Some("N"), repeatable: false, help: "Fetch and extract up to N inputs of a batch at the same time, 1 by default" },
+    OptionSpec { long: PER_HOST, short: None, value: Some("N"), repeatable: false, help: "Fetch up to N pages of the same host at the same time, 2 by default" },
+    OptionSpec { long: HOST_DELAY, short: None, value: Some("MS"), repeatable: false, help: "Wait at least MS milliseconds between two requests to the same host" },
+    OptionSpec { long: ORDERED, short: None, value: None, repeatable: false, help: "Output the results of a batch in the order of the inputs" },
+    OptionSpec { long: HEADER, short: Some('H'), value: Some("HEADER"), repeatable: true, help: "Send the `Name: value` header with every request" },
+    OptionSpec { long: COOKIE_JAR, short: Some('b'), value: Some("FILE"), repeatable: false, help: "Send the cookies of the Netscape cookie jar file" },
+    OptionSpec { long: USER_AGENT, short: Some('A'), value: Some("AGENT"), repeatable: false, help: "The User-Agent header, `dessert2/<version>` by default" },
+    OptionSpec { long: CONNECT_TIMEOUT, short: None, value: Some("SECS"), repeatable: false, help: "Give up connecting to a host after SECS seconds" },
+    OptionSpec { long: TIMEOUT, short: None, value: Some("SECS"), repeatable: false, help: "Give up a request after SECS seconds" },
+    OptionSpec { long: PROXY, short: None, value: Some("URL"), repeatable: false, help: "Send the requests through the http or https proxy" },
+    OptionSpec { long: MAX_REDIRECTS, short: None, value: Some("N"), repeatable: false, help: "Follow up to N redirects, 10 by default, 0 to follow none" },
+    OptionSpec { long: BASIC_AUTH, short: None, value: Some("USER:PASS"), repeatable: false, help: "Authenticate with HTTP basic authentication, the password is optional" },
+    OptionSpec { long: BEARER, short: None, value: Some("TOKEN"), repeatable: false, help: "Authenticate with the bearer token" },
+    OptionSpec { long: RETRIES, short: None, value: Some("N"), repeatable: false, help: "Retry a failed request up to N times, 2 by default" },
+    OptionSpec { long: RETRY_ON, short: None, value: Some("CODES"), repeatable: false, help: "The statuses retried, 408,429,500,502,503,504 by default" },
+    OptionSpec { long: RETRY_DELAY, short: None, value: Some("SECS"), repeatable: false, help: "Wait SECS seconds before the first retry, doubled for every other one" },
+    OptionSpec { long: ALLOW_STATUS, short: None, value: Some("CODES"), repeatable: false, help: "Extract the pages of these statuses other than 2xx instead of failing" },
+    OptionSpec { long: CACHE_DIR, short: None, value: Some("DIR"), repeatable: false, help: "Keep the fetched pages in DIR, and revalidate them instead of downloading them again" },
+    OptionSpec { long: OFFLINE, short: None, value: None, repeatable: false, help: "Read the pages from the cache only, a page that is not cached is an error" },
+    OptionSpec { long: ENCODING, short: Some('e'), value: Some("LABEL"), repeatable: false, help: "Decode the inputs with this encoding instead of the detected one" },
+    OptionSpec { long: IGNORE_ROBOTS, short: None, value: None, repeatable: false, help: "Do not obey the robots.txt of the sites, for the sites you own" },
+    OptionSpec { long: MAX_DEPTH, short: None, value: Some("N"), repeatable: false, help: "Follow the links of the template down to N pages deep, 3 by default" },
+    OptionSpec { long: BASE_URL, short: None, value: Some("URL"), repeatable: false, help: "Resolve the relative links of the files and of stdin against the URL" },
+    OptionSpec { long: VERBOSE, short: Some('v'), value: None, repeatable: false, help: "Report the retries and the status of every request on stderr" },
+    OptionSpec { long: HELP, short: Some('h'), value: None, repeatable: false, help: "Print this help and exit" },
+    OptionSpec { long: VERSION, short: Some('V'), value: None, repeatable: false, help: "Print the version and exit" },
+];
+
+/// Where the template is read from.
+#[derive(Debug, PartialEq)]
+pub enum TemplateSource {
+    Text(String),
+    File(String)
+}
+
+/// The settings of a run, checked and typed from the command line.
+#[derive(Debug, PartialEq)]
+pub struct Config {
+    pub urls: Vec<String>,
+    pub inputs: Vec<String>,
+    pub url_list: Option<String>,
+    pub globs: Vec<String>,
+    pub sitemaps: Vec<String>,
+    /// The regular expression the URLs of the sitemaps must match.
+    pub sitemap_filter: Option<String>,
+    /// The day, in UTC, the URLs of the sitemaps must be modified on or after.
+    pub sitemap_since: Option<Day>,
+    pub template: TemplateSource,
+    pub output_format: OutputFormat,
+    pub output_dir: String,
+    pub pool: PoolOptions,
+    pub http: HttpOptions,
+    pub cache_dir: Option<String>,
+    pub offline: bool,
+    /// The URL the `Url` values of the files and of stdin are resolved against.
+    pub base_url: Option<Url>,
+    /// How many links are followed at most from the page of an input.
+    pub max_depth: usize,
+    /// Fetch the URLs that the robots.txt of their site disallows.
+    pub ignore_robots: bool,
+    pub verbose: bool
+}
 
-pub fn parse_params<I: Iterator<Item=String>>(params: I) -> HashMap<String, String> {
-    let mut result = HashMap::new();
-    let mut key = String::new();
-    for arg in params {
-        if arg.starts_with("--") {
-            if !key.is_empty() {
-                result.insert(key, "".to_string());
+impl Config {
+    /// Whether the run is a batch, whose results are tagged with their input. A single `--url`,
+    /// a single `--input` file or stdin is not.
+    pub fn is_batch(&self) -> bool {
+        self.urls.len() + self.inputs.len() > 1 || self.url_list.is_some() || !self.globs.is_empty() || !self.sitemaps.is_empty()
+            || self.inputs.iter().any(|path| Path::new(path).is_dir())
+    }
+}
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Box<Config>),
+    Help,
+    Version
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParamError {
+    UnknownOption(String),
+    MissingValue(String),
+    UnexpectedValue(String),
+    InvalidValue { option: String, value: String, expected: String },
+    Duplicate(String),
+    UnexpectedArgument(String),
+    MissingTemplate,
+    Conflict(String, String),
+    Requires(String, String),
+}
+
+impl std::error::Error for ParamError {}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Self::UnknownOption(option) => write!(f, "unknown option `{}`", option),
+            Self::MissingValue(option) => write!(f, "option `--{}` requires a value", option),
+            Self::UnexpectedValue(option) => write!(f, "option `--{}` does not take a value", option),
+            Self::InvalidValue { option, value, expected } => write!(f, "invalid value `{}` for `--{}`, expected {}", value, option, expected),
+            Self::Duplicate(option) => write!(f, "option `--{}` is given more than once", option),
+            Self::UnexpectedArgument(arg) => write!(f, "unexpected argument `{}`", arg),
+            Self::MissingTemplate => write!(f, "either `--{}` or `--{}` must be specified", TEMPLATE, TEMPLATE_FILE),
+            Self::Conflict(first, second) => write!(f, "options `--{}` and `--{}` cannot be used together", first, second),
+            Self::Requires(option, required) => write!(f, "option `--{}` requires `--{}`", option, required),
+        }
+    }
+}
+
+fn find_option(arg: &str) -> Option<(&'static OptionSpec, Option<String>)> {
+    if let Some(long) = arg.strip_prefix("--") {
+        let (name, inline) = match long.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (long, None)
+        };
+        OPTIONS.iter().find(|o| o.long == name).map(|o| (o, inline))
+    } else {
+        let mut chars = arg.strip_prefix('-')?.chars();
+        let short = chars.next()?;
+        let rest = chars.as_str();
+        let inline = if rest.is_empty() { None } else { Some(rest.strip_prefix('=').unwrap_or(rest).to_string()) };
+        OPTIONS.iter().find(|o| o.short == Some(short)).map(|o| (o, inline))
+    }
+}
+
+/// Parse the command line arguments, without the program name. `--option=value`, `-o value` and
+/// `-ovalue` are accepted.
+pub fn parse_params<I: Iterator<Item=String>>(params: I) -> Result<Command, ParamError> {
+    let mut values: HashMap<&'static str, Vec<String>> = HashMap::new();
+    let mut params = params.peekable();
+    while let Some(arg) = params.next() {
+        let (spec, inline) = match find_option(&arg) {
+            Some(found) => found,
+            None if arg.starts_with('-') && arg.len() > 1 => return Err(ParamError::UnknownOption(arg)),
+            None => return Err(ParamError::UnexpectedArgument(arg))
+        };
+        if spec.value.is_none() {
+            if inline.is_some() {
+                return Err(ParamError::UnexpectedValue(spec.long.to_string()));
             }
-            key = String::from(&arg[2..]);
-        } else {
-            if key.is_empty() {
-                println!("[WARN]: ignore the param without leading \"--\"");
-                continue;
+            match spec.long {
+                HELP => return Ok(Command::Help),
+                VERSION => return Ok(Command::Version),
+                _ => {}
+            }
+        }
+        let value = match (spec.value, inline) {
+            (None, _) => "true".to_string(),
+            (Some(_), Some(value)) => value,
+            // a YAML template starts with `---`, so only a known option is taken for a missing value
+            (Some(_), None) => match params.next_if(|next| find_option(next).is_none()) {
+                Some(value) => value,
+                None => return Err(ParamError::MissingValue(spec.long.to_string()))
             }
-            result.insert(key, arg);
-            key = "".to_string();
+        };
+        let given = values.entry(spec.long).or_default();
+        if !given.is_empty() && !spec.repeatable {
+            return Err(ParamError::Duplicate(spec.long.to_string()));
         }
+        given.push(value);
     }
+    let headers = parse_headers(values.remove(HEADER).unwrap_or_default())?;
+    let sitemaps = values.remove(SITEMAP).unwrap_or_default();
+    let mut single = |name: &str| values.remove(name).and_then(|mut given| given.pop());
 
-    if !key.is_empty() {
-        result.insert(key, "".to_string());
+    let template = match (single(TEMPLATE), single(TEMPLATE_FILE)) {
+        (Some(_), Some(_)) => return Err(ParamError::Conflict(TEMPLATE.to_string(), TEMPLATE_FILE.to_string())),
+        (Some(template), None) => TemplateSource::Text(template),
+        (None, Some(file)) => TemplateSource::File(file),
+        (None, None) => return Err(ParamError::MissingTemplate)
+    };
+    let table_layout = match single(TABLE_LAYOUT).as_deref() {
+        None | Some("stream") => TableLayout::Stream,
+        Some("per-object") => TableLayout::PerObject,
+        Some(other) => return Err(invalid_value(TABLE_LAYOUT, other, "`stream` or `per-object`"))
+    };
+    let output_format = match single(OUTPUT_FORMAT) {
+        Some(format_str) => parse_output_format(&format_str, table_layout)?,
+        None => OutputFormat::Yaml
+    };
+    let pool = PoolOptions {
+        concurrency: parse_count(CONCURRENCY, single(CONCURRENCY), 1)?,
+        per_host: parse_count(PER_HOST, single(PER_HOST), DEFAULT_PER_HOST)?,
+        host_delay: Duration::from_millis(parse_number(HOST_DELAY, single(HOST_DELAY), 0)?),
+        ordered: single(ORDERED).is_some()
+    };
+    let auth = match (single(BASIC_AUTH), single(BEARER)) {
+        (Some(_), Some(_)) => return Err(ParamError::Conflict(BASIC_AUTH.to_string(), BEARER.to_string())),
+        (Some(credentials), None) => Some(match credentials.split_once(':') {
+            Some((user, password)) => Auth::Basic { user: user.to_string(), password: Some(password.to_string()) },
+            None => Auth::Basic { user: credentials, password: None }
+        }),
+        (None, Some(token)) => Some(Auth::Bearer(token)),
+        (None, None) => None
+    };
+    let http = HttpOptions {
+        headers,
+        user_agent: single(USER_AGENT),
+        cookie_jar: single(COOKIE_JAR).map(PathBuf::from),
+        connect_timeout: parse_seconds(CONNECT_TIMEOUT, single(CONNECT_TIMEOUT))?,
+        timeout: parse_seconds(TIMEOUT, single(TIMEOUT))?,
+        proxy: single(PROXY),
+        max_redirects: match single(MAX_REDIRECTS) {
+            Some(value) => Some(value.parse().map_err(|_| invalid_value(MAX_REDIRECTS, &value, "a number"))?),
+            None => None
+        },
+        auth,
+        retries: match single(RETRIES) {
+            Some(value) => Some(value.parse().map_err(|_| invalid_value(RETRIES, &value, "a number"))?),
+            None => None
+        },
+        retry_statuses: single(RETRY_ON).map(|codes| parse_statuses(RETRY_ON, &codes)).transpose()?,
+        retry_delay: parse_seconds(RETRY_DELAY, single(RETRY_DELAY))?,
+        allow_statuses: single(ALLOW_STATUS).map(|codes| parse_statuses(ALLOW_STATUS, &codes)).transpose()?.unwrap_or_default(),
+        encoding: single(ENCODING).map(|label| encoding::for_label(&label).map(|_| label)).transpose()?
+    };
+    let cache_dir = single(CACHE_DIR);
+    let offline = single(OFFLINE).is_some();
+    if offline && cache_dir.is_none() {
+        return Err(ParamError::Requires(OFFLINE.to_string(), CACHE_DIR.to_string()));
     }
-    result
+    let base_url = match single(BASE_URL) {
+        Some(value) => Some(Url::parse(&value).map_err(|_| invalid_value(BASE_URL, &value, "an absolute URL"))?),
+        None => None
+    };
+    let max_depth = parse_number(MAX_DEPTH, single(MAX_DEPTH), DEFAULT_MAX_DEPTH as u64)? as usize;
+    let sitemap_filter = single(SITEMAP_FILTER);
+    if let Some(pattern) = &sitemap_filter {
+        Regex::new(pattern).map_err(|err| invalid_value(SITEMAP_FILTER, pattern, &format!("a regular expression ({})", err)))?;
+    }
+    let sitemap_since = match single(SITEMAP_SINCE) {
+        Some(value) => Some(Day::parse(&value).ok_or_else(|| invalid_value(SITEMAP_SINCE, &value, "a W3C date like `2024-05-01`"))?),
+        None => None
+    };
+    if sitemaps.is_empty() {
+        if sitemap_filter.is_some() {
+            return Err(ParamError::Requires(SITEMAP_FILTER.to_string(), SITEMAP.to_string()));
+        }
+        if sitemap_since.is_some() {
+            return Err(ParamError::Requires(SITEMAP_SINCE.to_string(), SITEMAP.to_string()));
+        }
+    }
+    let ignore_robots = single(IGNORE_ROBOTS).is_some();
+    let verbose = single(VERBOSE).is_some();
+    let url_list = single(URL_LIST);
+    let output_dir = single(OUTPUT_DIR).unwrap_or_else(|| ".".to_string());
+    Ok(Command::Run(Box::new(Config {
+        urls: values.remove(URL).unwrap_or_default(),
+        inputs: values.remove(INPUT).unwrap_or_default(),
+        url_list,
+        globs: values.remove(GLOB).unwrap_or_default(),
+        sitemaps,
+        sitemap_filter,
+        sitemap_since,
+        template,
+        output_format,
+        output_dir,
+        pool,
+        http,
+        cache_dir,
+        offline,
+        base_url,
+        max_depth,
+        ignore_robots,
+        verbose
+    })))
 }
 
-pub fn read_from_stdin() -> String {
-    let mut buff = String::new();
-    while let Ok(n) = io::stdin().read_line(&mut buff) {
-        if n <= 0 {break};
+fn parse_number(option: &str, value: Option<String>, default: u64) -> Result<u64, ParamError> {
+    match value {
+        Some(value) => value.parse().map_err(|_| invalid_value(option, &value, "a number")),
+        None => Ok(default)
     }
-    buff
+}
+
+// a number of workers or of requests, at least 1
+fn parse_count(option: &str, value: Option<String>, default: usize) -> Result<usize, ParamError> {
+    match value {
+        Some(value) => value.parse().ok().filter(|n| *n > 0).ok_or_else(|| invalid_value(option, &value, "a positive number")),
+        None => Ok(default)
+    }
+}
+
+fn parse_seconds(option: &str, value: Option<String>) -> Result<Option<f64>, ParamError> {
+    match value {
+        Some(value) => value.parse().ok().filter(|secs: &f64| secs.is_finite() && *secs > 0.0).map(Some)
+            .ok_or_else(|| invalid_value(option, &value, "a positive number of seconds")),
+        None => Ok(None)
+    }
+}
+
+// comma-separated HTTP statuses
+fn parse_statuses(option: &str, codes: &str) -> Result<Vec<u16>, ParamError> {
+    codes.split(',')
+        .map(|code| code.trim().parse().ok().filter(|code| (100..600).contains(code)))
+        .collect::<Option<Vec<u16>>>()
+        .ok_or_else(|| invalid_value(option, codes, "comma-separated HTTP statuses"))
+}
+
+// `Name: value` headers, in the order they are given
+fn parse_headers(headers: Vec<String>) -> Result<IndexMap<String, String>, ParamError> {
+    headers.into_iter().map(|header| {
+        let valid = header.split_once(':')
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .filter(|(name, value)| HeaderName::from_bytes(name.as_bytes()).is_ok() && HeaderValue::from_str(value).is_ok());
+        match valid {
+            Some((name, value)) => Ok((name.to_string(), value.to_string())),
+            None => Err(invalid_value(HEADER, &header, "`Name: value`"))
+        }
+    }).collect()
+}
+
+fn invalid_value(option: &str, value: &str, expected: &str) -> ParamError {
+    ParamError::InvalidValue { option: option.to_string(), value: value.to_string(), expected: expected.to_string() }
+}
+
+/// The `--help` text, built from the declared options.
+pub fn usage() -> String {
+    let mut text = format!("Usage: dessert2 [OPTIONS] (--{} <TEMPLATE> | --{} <FILE>)\n\n", TEMPLATE, TEMPLATE_FILE);
+    text.push_str("Extract values from an HTML document with a template of CSS or XPath selectors.\n");
+    text.push_str("The HTML is read from `--url` or `--input`, or from stdin if they are absent. Several of them,\n");
+    text.push_str("a directory, a `--url-list` or a `--glob` run a batch, whose results are tagged with their source.\n\nOptions:\n");
+    for option in OPTIONS {
+        let short = option.short.map(|c| format!("-{}, ", c)).unwrap_or_else(|| "    ".to_string());
+        let value = option.value.map(|v| format!(" <{}>", v)).unwrap_or_default();
+        text.push_str(&format!("  {:<32}{}\n", format!("{}--{}{}", short, option.long, value), option.help));
+    }
+    text
+}
+
+pub fn read_from_stdin() -> Result<String, Error> {
+    let mut buff = String::new();
+    io::stdin().read_to_string(&mut buff).map_err(|err| Error::Io(format!("cannot read stdin: {}", err)))?;
+    Ok(buff)
 }
 
 // return (type: json|yaml, content)
-fn read_template_from_file(file_path: &String) -> (String, String) {
-    let mut file_type = "yaml";
-    if file_path.contains("yaml") || file_path.contains("yml") {
-       file_type = "yaml"; 
-    } else if file_path.contains("json") {
-        file_type = "json";
+fn read_template_from_file(file_path: &str) -> Result<(String, String), Error> {
+    let file_type = if !file_path.contains("yaml") && !file_path.contains("yml") && file_path.contains("json") {
+        "json"
+    } else {
+        "yaml"
+    };
+    let content = read_to_string(file_path).map_err(|err| Error::Io(format!("cannot read `{}`: {}", file_path, err)))?;
+    Ok((file_type.to_string(), content))
+}
+
+fn parse_output_format(format_str: &str, table_layout: TableLayout) -> Result<OutputFormat, ParamError> {
+    match format_str {
+        "yaml" | "Yaml" | "YAML" => Ok(OutputFormat::Yaml),
+        "json" | "Json" | "JSON" => Ok(OutputFormat::Json),
+        "text"| "Text" | "TEXT" => Ok(OutputFormat::Text),
+        "csv" | "Csv" | "CSV" => Ok(OutputFormat::Csv(table_layout)),
+        "tsv" | "Tsv" | "TSV" => Ok(OutputFormat::Tsv(table_layout)),
+        "ndjson" | "Ndjson" | "NDJSON" => Ok(OutputFormat::Ndjson),
+        _ => Err(invalid_value(OUTPUT_FORMAT, format_str, "`yaml`, `json`, `text`, `csv`, `tsv` or `ndjson`"))
     }
-    (file_type.to_string(), read_to_string(&file_path).unwrap())
 }
 
-pub fn parse_output_format(params: &HashMap<String, String>) -> OutputFormat {
-    if let Some(format_str) = params.get(&OUTPUT_FORMAT.to_owned()) {
-        match &**format_str {
-            "yaml" | "Yaml" | "YAML" => OutputFormat::Yaml,
-            "json" | "Json" | "JSON" => OutputFormat::Json,
-            "text"| "Text" | "TEXT" => OutputFormat::Text,
-            _ => OutputFormat::Yaml
+// write the tables of the `per-object` layout into the output directory
+pub fn write_output_files(config: &Config, files: &[(String, String)]) -> Result<(), Error> {
+    let cannot_write = |path: &Path, err: io::Error| Error::Io(format!("cannot write `{}`: {}", path.display(), err));
+    let dir = Path::new(&config.output_dir);
+    // no file is written out of the directory, nor any before one that would be
+    if let Some((file_name, _)) = files.iter().find(|(file_name, _)| !table::is_file_name(file_name.rsplit_once('.').map_or(file_name.as_str(), |(stem, _)| stem))) {
+        return Err(Error::Io(format!("cannot write `{}`: not a file of `{}`", dir.join(file_name).display(), dir.display())));
+    }
+    fs::create_dir_all(dir).map_err(|err| cannot_write(dir, err))?;
+    for (file_name, content) in files {
+        let path = dir.join(file_name);
+        fs::write(&path, content).map_err(|err| cannot_write(&path, err))?;
+    }
+    Ok(())
+}
 
+// read template, and compile its selectors and regular expressions
+pub fn read_template(config: &Config) -> Result<CompiledTemplate, Error> {
+    let template = CompiledTemplate::compile_template(&load_template(config)?)?.max_depth(config.max_depth);
+    // every object of the `per-object` layout is written to the file named after its id
+    if let OutputFormat::Csv(TableLayout::PerObject) | OutputFormat::Tsv(TableLayout::PerObject) = config.output_format {
+        template.check_file_names()?;
+    }
+    Ok(template)
+}
+
+fn load_template(config: &Config) -> Result<Template, Error> {
+    let (is_json, text) = match &config.template {
+        TemplateSource::Text(template) => (!template.starts_with("---"), template.clone()),
+        TemplateSource::File(file_name) => {
+            let (file_type, file_content) = read_template_from_file(file_name)?;
+            (file_type == "json", file_content)
         }
+    };
+    if is_json {
+        idents::template_from_json_str(&text).map_err(|err| Error::Template(err.to_string()))
     } else {
-        OutputFormat::Yaml
+        idents::template_from_yaml_str(&text).map_err(|err| Error::Template(err.to_string()))
+    }
+}
+
+/// The URLs of the `--sitemap`s, filtered by `--sitemap-filter` and `--sitemap-since`. The
+/// sitemaps are read with the fetcher of the run, so they are fetched like the pages.
+pub fn sitemap_inputs(config: &Config, fetcher: &Fetcher) -> Result<Vec<Input>, Error> {
+    let filter = SitemapFilter {
+        pattern: config.sitemap_filter.as_deref().map(Regex::new).transpose()
+            .map_err(|err| Error::Args(invalid_value(SITEMAP_FILTER, config.sitemap_filter.as_deref().unwrap_or_default(), &err.to_string())))?,
+        since: config.sitemap_since
+    };
+    let mut inputs = Vec::new();
+    for location in &config.sitemaps {
+        inputs.extend(sitemap::urls(location, fetcher, &filter)?.into_iter().map(|url| Input::from(Source::Url(url))));
     }
+    Ok(inputs)
 }
 
-// read template
-pub fn read_template(params: &HashMap<String, String>) -> Vec<IdentObj>{
-    if params.contains_key(&TEMPLATE.to_owned()) {
-        let template = params.get(&TEMPLATE.to_owned()).unwrap();
-        if template.starts_with("---") {
-            idents::from_yaml_str(template).unwrap()
+// whether a file found in an `--input` directory is a page: `.html`, `.htm` or `.xhtml`, possibly
+// compressed
+fn is_html_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let name = name.strip_suffix(".gz").or_else(|| name.strip_suffix(".br")).unwrap_or(&name);
+    [".html", ".htm", ".xhtml"].iter().any(|extension| name.ends_with(extension))
+}
+
+// the pages under a directory, recursively, sorted by path. Symbolic links to directories are not
+// followed, so that a link loop cannot make the walk endless.
+fn html_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.path());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            html_files(&path, files)?;
+        } else if is_html_file(&path) && path.is_file() {
+            files.push(path);
         }
-        else {
-            idents::from_json_str(template).unwrap()
+    }
+    Ok(())
+}
+
+/// The inputs of the run: the `--url`s, the `--input` files and directories, the entries of the
+/// `--url-list`, and the files matching the `--glob` patterns, in this order. Stdin if there is
+/// none of them, unless there are `--sitemap`s, whose URLs are added by `sitemap_inputs`. The
+/// inputs that are not URLs get the `--base-url`.
+pub fn collect_inputs(config: &Config) -> Result<Vec<Input>, Error> {
+    let mut inputs: Vec<Input> = config.urls.iter().map(|url| Source::Url(url.clone()).into()).collect();
+    for path in config.inputs.iter().map(PathBuf::from) {
+        if !path.is_dir() {
+            inputs.push(Source::File(path).into());
+            continue;
         }
-    } else if params.contains_key(&TEMPLATE_FILE.to_owned()) {
-        let file_name = params.get(&TEMPLATE_FILE.to_owned()).unwrap();
-        let (file_type, file_content) = read_template_from_file(&file_name);
-        if file_type == "json" {
-            idents::from_json_str(&*file_content).unwrap()
-        } else if file_type == "yaml" {
-            idents::from_yaml_str(&*file_content).unwrap()
+        let mut files = Vec::new();
+        html_files(&path, &mut files).map_err(|err| Error::Io(format!("cannot read `{}`: {}", path.display(), err)))?;
+        if files.is_empty() {
+            return Err(Error::Io(format!("no HTML file in `{}`", path.display())));
+        }
+        inputs.extend(files.into_iter().map(|file| Source::File(file).into()));
+    }
+    if let Some(url_list) = &config.url_list {
+        let list = if url_list == "-" {
+            read_from_stdin()?
         } else {
-            panic!("the file type should be either `json` or `yaml`");
+            read_to_string(url_list).map_err(|err| Error::Io(format!("cannot read `{}`: {}", url_list, err)))?
+        };
+        for (number, line) in list.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            inputs.push(Input::parse(line).map_err(|err| Error::Io(format!("{}:{}: {}", url_list, number + 1, err)))?);
         }
-    } else {
-        panic!("Either {} or {} must be specified", TEMPLATE, TEMPLATE_FILE);
     }
-}
-
-pub fn read_html(params: &HashMap<String, String>) -> String {
-    if let Some(url) = params.get(&URL.to_string()){
-       reqwest::blocking::get(url).unwrap().text().unwrap()
-    } else {
-       read_from_stdin() 
-    } 
+    for pattern in &config.globs {
+        let paths = glob::glob(pattern).map_err(|err| Error::Args(invalid_value(GLOB, pattern, &err.to_string())))?;
+        let before = inputs.len();
+        for path in paths {
+            inputs.push(Source::File(path.map_err(|err| Error::Io(err.to_string()))?).into());
+        }
+        if inputs.len() == before {
+            return Err(Error::Io(format!("no file matches `{}`", pattern)));
+        }
+    }
+    if inputs.is_empty() && !config.is_batch() {
+        inputs.push(Source::Stdin.into());
+    }
+    for input in inputs.iter_mut().filter(|input| !matches!(input.source, Source::Url(_))) {
+        input.base_url = config.base_url.clone();
+    }
+    Ok(inputs)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::batch::read_html;
+
+    fn args(args: &[&str]) -> impl Iterator<Item=String> {
+        args.iter().map(|a| a.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    fn run_config(args_list: &[&str]) -> Config {
+        match parse_params(args(args_list)).unwrap() {
+            Command::Run(config) => *config,
+            other => panic!("expected a run, found {:?}", other)
+        }
+    }
 
     #[test]
     fn parse_params_happy() {
-        let params = vec!["--url".to_string(), "https://www.google.com".to_string(), "--output_format".to_string(), "json".to_string()];
-        let parsed = parse_params(params.into_iter());
-        assert_eq!(parsed.len(), 2);
-        assert_eq!(parsed.get(&"output_format".to_owned()).unwrap(), &"json".to_owned());
-        assert_eq!(parsed.get(&"url".to_owned()).unwrap(), &"https://www.google.com".to_owned());
+        let config = run_config(&["--url", "https://www.google.com", "--output-format", "json", "--template", "[]"]);
+        assert_eq!(config, Config {
+            urls: vec!["https://www.google.com".to_string()],
+            inputs: vec![],
+            url_list: None,
+            globs: vec![],
+            sitemaps: vec![],
+            sitemap_filter: None,
+            sitemap_since: None,
+            template: TemplateSource::Text("[]".to_string()),
+            output_format: OutputFormat::Json,
+            output_dir: ".".to_string(),
+            pool: PoolOptions::default(),
+            http: HttpOptions::default(),
+            cache_dir: None,
+            offline: false,
+            base_url: None,
+            max_depth: 3,
+            ignore_robots: false,
+            verbose: false
+        });
     }
 
     #[test]
-    fn parse_params_key_only() {
-        let params = vec!["--text".to_string()];
-        let parsed = parse_params(params.into_iter());
-        assert_eq!(parsed.len(), 1);
-        assert_eq!(parsed.get(&"text".to_owned()).unwrap(), &"".to_owned());
+    fn parse_params_inline_and_short() {
+        let config = run_config(&["--url=https://a.com/?q=1", "-o", "csv", "--table-layout=per-object", "-fpage.yaml", "-d=out"]);
+        assert_eq!(config.urls, vec!["https://a.com/?q=1".to_string()]);
+        assert_eq!(config.output_format, OutputFormat::Csv(TableLayout::PerObject));
+        assert_eq!(config.template, TemplateSource::File("page.yaml".to_string()));
+        assert_eq!(config.output_dir, "out".to_string());
+    }
+
+    #[test]
+    fn parse_params_yaml_template_value() {
+        let config = run_config(&["-t", "---\n- object_id: a"]);
+        assert_eq!(config.template, TemplateSource::Text("---\n- object_id: a".to_string()));
+        assert_eq!(config.output_format, OutputFormat::Yaml);
+    }
+
+    #[test]
+    fn parse_params_help_and_version() {
+        assert_eq!(parse_params(args(&["--help"])), Ok(Command::Help));
+        assert_eq!(parse_params(args(&["-V"])), Ok(Command::Version));
+        assert_eq!(parse_params(args(&["--version=1"])), Err(ParamError::UnexpectedValue("version".to_string())));
+    }
+
+    #[test]
+    fn parse_params_unknown_option() {
+        assert_eq!(parse_params(args(&["--ouput-format", "json", "-t", "[]"])), Err(ParamError::UnknownOption("--ouput-format".to_string())));
+        assert_eq!(parse_params(args(&["-x"])), Err(ParamError::UnknownOption("-x".to_string())));
+        assert_eq!(parse_params(args(&["-t", "[]", "json"])), Err(ParamError::UnexpectedArgument("json".to_string())));
+    }
+
+    #[test]
+    fn parse_params_missing_value() {
+        assert_eq!(parse_params(args(&["--url"])), Err(ParamError::MissingValue("url".to_string())));
+        assert_eq!(parse_params(args(&["--url", "--template", "[]"])), Err(ParamError::MissingValue("url".to_string())));
+    }
+
+    #[test]
+    fn parse_params_invalid_values() {
+        assert!(matches!(parse_params(args(&["-t", "[]", "-o", "xml"])), Err(ParamError::InvalidValue { .. })));
+        assert!(matches!(parse_params(args(&["-t", "[]", "--table-layout", "wide"])), Err(ParamError::InvalidValue { .. })));
+        assert_eq!(parse_params(args(&["-t", "[]", "-t", "[]"])), Err(ParamError::Duplicate("template".to_string())));
+        assert_eq!(parse_params(args(&["-o", "json"])), Err(ParamError::MissingTemplate));
+        assert_eq!(parse_params(args(&["-t", "[]", "-f", "a.json"])), Err(ParamError::Conflict("template".to_string(), "template-file".to_string())));
+    }
+
+    #[test]
+    fn usage_lists_every_option() {
+        let text = usage();
+        for option in OPTIONS {
+            assert!(text.contains(&format!("--{}", option.long)));
+        }
+    }
+
+    #[test]
+    fn read_template_errors() {
+        let mut config = run_config(&["-t", "[{\"object_id\": 1}]"]);
+        assert_eq!(read_template(&config).unwrap_err().exit_code(), 3);
+        // a template file that cannot be read is an IO error, like an input file
+        config.template = TemplateSource::File("/nonexistent/template.json".to_string());
+        let error = read_template(&config).unwrap_err();
+        assert!(matches!(&error, Error::Io(reason) if reason.starts_with("cannot read `/nonexistent/template.json`")), "{}", error);
+        assert_eq!(error.exit_code(), 4);
+        config.template = TemplateSource::Text("[]".to_string());
+        assert!(read_template(&config).unwrap().idents().is_empty());
+    }
+
+    #[test]
+    fn per_object_file_names() {
+        let template = r#"[{"object_id": "a/b", "css_selector": "li", "properties": []}, {"object_id": "c", "css_selector": "li", "properties": []}, {"object_id": "c", "css_selector": "p", "properties": []}]"#;
+        assert!(read_template(&run_config(&["-t", template, "-o", "csv"])).is_ok());
+        let error = read_template(&run_config(&["-t", template, "-o", "tsv", "--table-layout", "per-object"])).unwrap_err();
+        assert_eq!(error.exit_code(), 3);
+        assert_eq!(error.to_string(), "invalid template: [0].object_id: `a/b` cannot name a file of the output directory\n  [2].object_id: `c` is the id of another object, whose file it would overwrite");
+        let dir = std::env::temp_dir().join(format!("dessert2-files-{}", std::process::id()));
+        let config = run_config(&["-t", "[]", "-d", &dir.display().to_string()]);
+        let error = write_output_files(&config, &[("ok.csv".to_string(), "a\n".to_string()), ("../out.csv".to_string(), "a\n".to_string())]).unwrap_err();
+        assert!(matches!(&error, Error::Io(reason) if reason.ends_with(&format!("not a file of `{}`", dir.display()))), "{}", error);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn parse_params_batch() {
+        let config = run_config(&["-t", "[]", "-u", "https://a.com/", "--url", "https://b.com/", "-g", "pages/*.html"]);
+        assert_eq!(config.urls, vec!["https://a.com/".to_string(), "https://b.com/".to_string()]);
+        assert_eq!(config.globs, vec!["pages/*.html".to_string()]);
+        assert!(config.is_batch());
+        assert!(!run_config(&["-t", "[]", "-u", "https://a.com/"]).is_batch());
+        assert_eq!(parse_params(args(&["-t", "[]", "-l", "a.txt", "-l", "b.txt"])), Err(ParamError::Duplicate("url-list".to_string())));
+    }
+
+    #[test]
+    fn collect_inputs_from_list_and_glob() {
+        let dir = std::env::temp_dir().join(format!("dessert2-sources-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.html"), "<p>a</p>").unwrap();
+        fs::write(dir.join("b.html"), "<p>b</p>").unwrap();
+        let list = dir.join("urls.txt");
+        fs::write(&list, "# pages\nhttps://a.com/\n\n  local.html  \n{\"url\": \"https://b.com/\", \"timeout\": 5}\n").unwrap();
+        let pattern = format!("{}/*.html", dir.display());
+        let config = run_config(&["-t", "[]", "-u", "https://z.com/", "-l", &list.display().to_string(), "-g", &pattern]);
+        let inputs = collect_inputs(&config).unwrap();
+        assert_eq!(inputs.iter().map(|input| input.source.clone()).collect::<Vec<_>>(), vec![
+            Source::Url("https://z.com/".to_string()),
+            Source::Url("https://a.com/".to_string()),
+            Source::File("local.html".into()),
+            Source::Url("https://b.com/".to_string()),
+            Source::File(dir.join("a.html")),
+            Source::File(dir.join("b.html")),
+        ]);
+        assert_eq!(inputs[3].http, Some(HttpOptions { timeout: Some(5.0), ..Default::default() }));
+        assert!(inputs.iter().enumerate().all(|(index, input)| index == 3 || input.http.is_none()));
+        fs::write(&list, "https://a.com/\n{\"url\": \"https://b.com/\", \"timout\": 5}\n").unwrap();
+        let error = collect_inputs(&run_config(&["-t", "[]", "-l", &list.display().to_string()])).unwrap_err().to_string();
+        assert!(error.contains("urls.txt:2: unknown field `timout`"), "{}", error);
+        let config = run_config(&["-t", "[]", "-g", &format!("{}/*.xml", dir.display())]);
+        assert!(matches!(collect_inputs(&config), Err(Error::Io(_))));
+        assert_eq!(collect_inputs(&run_config(&["-t", "[]"])).unwrap(), vec![Source::Stdin.into()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_params_pool() {
+        let config = run_config(&["-t", "[]", "-j", "8", "--per-host=3", "--host-delay", "250", "--ordered"]);
+        assert_eq!(config.pool, PoolOptions { concurrency: 8, per_host: 3, host_delay: Duration::from_millis(250), ordered: true });
+        assert!(matches!(parse_params(args(&["-t", "[]", "-j", "0"])), Err(ParamError::InvalidValue { .. })));
+        assert!(matches!(parse_params(args(&["-t", "[]", "--host-delay", "soon"])), Err(ParamError::InvalidValue { .. })));
+        assert_eq!(parse_params(args(&["-t", "[]", "--ordered=yes"])), Err(ParamError::UnexpectedValue("ordered".to_string())));
+    }
+
+    #[test]
+    fn parse_params_http() {
+        let config = run_config(&["-t", "[]", "-H", "Accept-Language: fr-CA", "--header=X-Token:abc", "-A", "bot/1.0", "-b", "cookies.txt",
+            "--connect-timeout", "2.5", "--timeout=30", "--proxy", "http://127.0.0.1:3128", "--max-redirects", "0", "--basic-auth", "ann:p:w"]);
+        assert_eq!(config.http.headers.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect::<Vec<_>>(), vec![("Accept-Language", "fr-CA"), ("X-Token", "abc")]);
+        assert_eq!(config.http.user_agent, Some("bot/1.0".to_string()));
+        assert_eq!(config.http.cookie_jar, Some(PathBuf::from("cookies.txt")));
+        assert_eq!((config.http.connect_timeout, config.http.timeout), (Some(2.5), Some(30.0)));
+        assert_eq!(config.http.proxy, Some("http://127.0.0.1:3128".to_string()));
+        assert_eq!(config.http.max_redirects, Some(0));
+        assert_eq!(config.http.auth, Some(Auth::Basic { user: "ann".to_string(), password: Some("p:w".to_string()) }));
+        assert_eq!(run_config(&["-t", "[]", "--bearer", "t0k"]).http.auth, Some(Auth::Bearer("t0k".to_string())));
+        assert_eq!(parse_params(args(&["-t", "[]", "--bearer", "t", "--basic-auth", "a"])), Err(ParamError::Conflict("basic-auth".to_string(), "bearer".to_string())));
+        assert!(matches!(parse_params(args(&["-t", "[]", "-H", "no colon"])), Err(ParamError::InvalidValue { .. })));
+        assert!(matches!(parse_params(args(&["-t", "[]", "-H", "Bad Name: x"])), Err(ParamError::InvalidValue { .. })));
+        assert!(matches!(parse_params(args(&["-t", "[]", "--timeout", "0"])), Err(ParamError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn parse_params_retries() {
+        let config = run_config(&["-t", "[]", "--retries", "0", "--retry-on", "503, 520", "--retry-delay=0.5", "--allow-status", "404,410", "-v"]);
+        assert_eq!(config.http.retries, Some(0));
+        assert_eq!(config.http.retry_statuses, Some(vec![503, 520]));
+        assert_eq!(config.http.retry_delay, Some(0.5));
+        assert_eq!(config.http.allow_statuses, vec![404, 410]);
+        assert!(config.verbose);
+        assert!(!run_config(&["-t", "[]"]).verbose);
+        assert!(matches!(parse_params(args(&["-t", "[]", "--retry-on", "503,abc"])), Err(ParamError::InvalidValue { .. })));
+        assert!(matches!(parse_params(args(&["-t", "[]", "--allow-status", "99"])), Err(ParamError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn parse_params_cache() {
+        let config = run_config(&["-t", "[]", "--cache-dir", ".cache", "--offline"]);
+        assert_eq!((config.cache_dir, config.offline), (Some(".cache".to_string()), true));
+        assert_eq!(parse_params(args(&["-t", "[]", "--offline"])), Err(ParamError::Requires("offline".to_string(), "cache-dir".to_string())));
+    }
+
+    #[test]
+    fn parse_params_base_url() {
+        let config = run_config(&["-t", "[]", "-u", "https://a.com/", "-i", "page.html", "--base-url", "https://b.com/docs/"]);
+        let base = Url::parse("https://b.com/docs/").unwrap();
+        assert_eq!(config.base_url, Some(base.clone()));
+        let inputs = collect_inputs(&config).unwrap();
+        assert_eq!((inputs[0].base_url.as_ref(), inputs[1].base_url.as_ref()), (None, Some(&base)));
+        assert_eq!(collect_inputs(&run_config(&["-t", "[]", "--base-url", "https://b.com/"])).unwrap()[0].source, Source::Stdin);
+        assert!(matches!(parse_params(args(&["-t", "[]", "--base-url", "/docs/"])), Err(ParamError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn parse_params_sitemaps() {
+        let config = run_config(&["-t", "[]", "--sitemap", "https://a.com/sitemap.xml", "--sitemap", "local.xml.gz", "--sitemap-filter", "/p/[0-9]+", "--sitemap-since", "2024-05-01"]);
+        assert_eq!(config.sitemaps, vec!["https://a.com/sitemap.xml".to_string(), "local.xml.gz".to_string()]);
+        assert_eq!((config.sitemap_filter.as_deref(), config.sitemap_since), (Some("/p/[0-9]+"), Some(Day { year: 2024, month: 5, day: 1 })));
+        assert!(config.is_batch());
+        assert_eq!(collect_inputs(&config).unwrap(), vec![]);
+        assert!(matches!(parse_params(args(&["-t", "[]", "--sitemap", "s.xml", "--sitemap-filter", "("])), Err(ParamError::InvalidValue { .. })));
+        assert!(matches!(parse_params(args(&["-t", "[]", "--sitemap", "s.xml", "--sitemap-since", "May 1st"])), Err(ParamError::InvalidValue { .. })));
+        assert!(matches!(parse_params(args(&["-t", "[]", "--sitemap", "s.xml", "--sitemap-since", "2024-13-45"])), Err(ParamError::InvalidValue { .. })));
+        assert_eq!(run_config(&["-t", "[]", "--sitemap", "s.xml", "--sitemap-since", "2024-05-01T01:00:00+02:00"]).sitemap_since, Some(Day { year: 2024, month: 4, day: 30 }));
+        assert_eq!(parse_params(args(&["-t", "[]", "--sitemap-since", "2024-05-01"])), Err(ParamError::Requires("sitemap-since".to_string(), "sitemap".to_string())));
+    }
+
+    #[test]
+    fn parse_params_max_depth() {
+        assert_eq!(run_config(&["-t", "[]"]).max_depth, DEFAULT_MAX_DEPTH);
+        assert_eq!(run_config(&["-t", "[]", "--max-depth", "1"]).max_depth, 1);
+        assert!(run_config(&["-t", "[]", "--ignore-robots"]).ignore_robots);
+        assert!(matches!(parse_params(args(&["-t", "[]", "--max-depth", "deep"])), Err(ParamError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn encoding_option() {
+        assert_eq!(run_config(&["-t", "[]", "-e", "Shift_JIS"]).http.encoding, Some("Shift_JIS".to_string()));
+        assert!(matches!(parse_params(args(&["-t", "[]", "--encoding", "klingon"])), Err(ParamError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn collect_inputs_from_directories() {
+        use std::io::Write;
+        let dir = std::env::temp_dir().join(format!("dessert2-inputs-{}", std::process::id()));
+        fs::create_dir_all(dir.join("2023/01")).unwrap();
+        fs::write(dir.join("index.html"), "<p>index</p>").unwrap();
+        fs::write(dir.join("notes.txt"), "not a page").unwrap();
+        fs::write(dir.join("2023/b.htm"), "<p>b</p>").unwrap();
+        let mut gz = flate2::write::GzEncoder::new(fs::File::create(dir.join("2023/01/a.html.gz")).unwrap(), flate2::Compression::default());
+        gz.write_all(b"<p>gzip</p>").unwrap();
+        gz.finish().unwrap();
+        let mut br = brotli::CompressorWriter::new(fs::File::create(dir.join("2023/01/c.HTML.br")).unwrap(), 4096, 5, 22);
+        br.write_all(b"<p>brotli</p>").unwrap();
+        drop(br);
+
+        let config = run_config(&["-t", "[]", "--input", &dir.display().to_string(), "-i", "single.html"]);
+        assert!(config.is_batch());
+        let inputs = collect_inputs(&config).unwrap();
+        assert_eq!(inputs.iter().map(|input| input.source.clone()).collect::<Vec<_>>(), vec![
+            Source::File(dir.join("2023/01/a.html.gz")),
+            Source::File(dir.join("2023/01/c.HTML.br")),
+            Source::File(dir.join("2023/b.htm")),
+            Source::File(dir.join("index.html")),
+            Source::File("single.html".into()),
+        ]);
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        let pages: Vec<String> = inputs[..4].iter().map(|input| read_html(input, &fetcher).unwrap()).collect();
+        assert_eq!(pages, vec!["<p>gzip</p>", "<p>brotli</p>", "<p>b</p>", "<p>index</p>"]);
+        assert!(matches!(read_html(&inputs[4], &fetcher), Err(Error::Io(_))));
+        assert!(!run_config(&["-t", "[]", "-i", "single.html"]).is_batch());
+        let empty = dir.join("2023/empty");
+        fs::create_dir_all(&empty).unwrap();
+        assert!(matches!(collect_inputs(&run_config(&["-t", "[]", "-i", &empty.display().to_string()])), Err(Error::Io(_))));
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,301 @@
+use url::Url;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The number of requests to the same host at the same time when none is configured.
+pub const DEFAULT_PER_HOST: usize = 2;
+
+/// The limits of a worker pool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolOptions {
+    /// The number of workers.
+    pub concurrency: usize,
+    /// The number of items of the same host processed at the same time.
+    pub per_host: usize,
+    /// The minimum time between the starts of two items of the same host.
+    pub host_delay: Duration,
+    /// Hand the results over in the order of the items, instead of as soon as they are done.
+    pub ordered: bool
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            per_host: DEFAULT_PER_HOST,
+            host_delay: Duration::from_millis(0),
+            ordered: false
+        }
+    }
+}
+
+/// The `host:port` of a URL, the key of the per-host limits.
+pub fn host_key(url: &Url) -> Option<String> {
+    let host = url.host_str()?.to_string();
+    Some(match url.port_or_known_default() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host
+    })
+}
+
+/// The per-host limits of `PoolOptions` applied to every request, whichever input, next page or
+/// detail page it is for: at most `per_host` requests to a host at a time, starting `host_delay`
+/// apart.
+#[derive(Debug)]
+pub struct HostLimits {
+    per_host: usize,
+    host_delay: Duration,
+    hosts: Mutex<HashMap<String, HostState>>,
+    changed: Condvar
+}
+
+#[derive(Debug, Default)]
+struct HostState {
+    active: usize,
+    last_start: Option<Instant>
+}
+
+/// A request under the limits of its host, until it is dropped.
+pub(crate) struct HostPermit<'a> {
+    limits: &'a HostLimits,
+    host: String
+}
+
+impl HostLimits {
+    pub fn new(options: &PoolOptions) -> Self {
+        Self { per_host: options.per_host.max(1), host_delay: options.host_delay, hosts: Mutex::new(HashMap::new()), changed: Condvar::new() }
+    }
+
+    /// Wait until a request to the host is under its limits.
+    pub(crate) fn acquire(&self, host: &str) -> HostPermit<'_> {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            let now = Instant::now();
+            let state = hosts.entry(host.to_string()).or_default();
+            let ready_at = state.last_start.map(|start| start + self.host_delay);
+            if state.active >= self.per_host {
+                hosts = self.changed.wait(hosts).unwrap_or_else(|poisoned| poisoned.into_inner());
+            } else if let Some(ready_at) = ready_at.filter(|ready_at| *ready_at > now) {
+                hosts = self.changed.wait_timeout(hosts, ready_at - now).unwrap_or_else(|poisoned| poisoned.into_inner()).0;
+            } else {
+                state.active += 1;
+                state.last_start = Some(now);
+                return HostPermit { limits: self, host: host.to_string() };
+            }
+        }
+    }
+}
+
+impl Drop for HostPermit<'_> {
+    fn drop(&mut self) {
+        let mut hosts = self.limits.hosts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(state) = hosts.get_mut(&self.host) {
+            state.active -= 1;
+        }
+        self.limits.changed.notify_all();
+    }
+}
+
+struct Schedule {
+    pending: VecDeque<usize>,
+    active: HashMap<String, usize>,
+    last_start: HashMap<String, Instant>
+}
+
+enum Next {
+    Item(usize),
+    Wait(Option<Duration>),
+    Done
+}
+
+impl Schedule {
+    /// The first pending item whose host is under its limits, or how long to wait for one.
+    fn next(&mut self, hosts: &[Option<String>], options: &PoolOptions) -> Next {
+        if self.pending.is_empty() {
+            return Next::Done;
+        }
+        let now = Instant::now();
+        let mut wait: Option<Duration> = None;
+        let chosen = self.pending.iter().position(|index| {
+            let host = match &hosts[*index] {
+                Some(host) => host,
+                None => return true
+            };
+            if self.active.get(host).copied().unwrap_or(0) >= options.per_host {
+                return false;
+            }
+            match self.last_start.get(host).map(|start| *start + options.host_delay) {
+                Some(ready_at) if ready_at > now => {
+                    let remaining = ready_at - now;
+                    wait = Some(wait.map_or(remaining, |w| w.min(remaining)));
+                    false
+                },
+                _ => true
+            }
+        });
+        match chosen.and_then(|position| self.pending.remove(position)) {
+            Some(index) => {
+                if let Some(host) = &hosts[index] {
+                    *self.active.entry(host.clone()).or_default() += 1;
+                    self.last_start.insert(host.clone(), now);
+                }
+                Next::Item(index)
+            },
+            None => Next::Wait(wait)
+        }
+    }
+
+    fn finish(&mut self, host: &Option<String>) {
+        if let Some(count) = host.as_ref().and_then(|host| self.active.get_mut(host)) {
+            *count -= 1;
+        }
+    }
+}
+
+/// Run `work` over the items with a bounded pool of threads, and hand every result to `sink` on
+/// the calling thread, with the position of its item. `host` gives the host of an item for the
+/// per-host limits, items without one are not limited.
+pub fn run<T, R, H, F, S>(items: &[T], options: &PoolOptions, host: H, work: F, mut sink: S)
+    where T: Sync, R: Send, H: Fn(&T) -> Option<String>, F: Fn(&T) -> R + Sync, S: FnMut(usize, R) {
+    let hosts: Vec<Option<String>> = items.iter().map(host).collect();
+    let schedule = Mutex::new(Schedule {
+        pending: (0..items.len()).collect(),
+        active: HashMap::new(),
+        last_start: HashMap::new()
+    });
+    let changed = Condvar::new();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency.max(1).min(items.len()) {
+            let sender = sender.clone();
+            let (schedule, changed, hosts, work) = (&schedule, &changed, &hosts, &work);
+            scope.spawn(move || loop {
+                let index = {
+                    let mut guard = schedule.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    loop {
+                        match guard.next(hosts, options) {
+                            Next::Item(index) => break index,
+                            Next::Done => return,
+                            Next::Wait(Some(timeout)) => guard = changed.wait_timeout(guard, timeout).unwrap_or_else(|poisoned| poisoned.into_inner()).0,
+                            Next::Wait(None) => guard = changed.wait(guard).unwrap_or_else(|poisoned| poisoned.into_inner())
+                        }
+                    }
+                };
+                let result = work(&items[index]);
+                schedule.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).finish(&hosts[index]);
+                changed.notify_all();
+                if sender.send((index, result)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut waiting = BTreeMap::new();
+        let mut next = 0;
+        for (index, result) in receiver {
+            if !options.ordered {
+                sink(index, result);
+                continue;
+            }
+            waiting.insert(index, result);
+            while let Some(result) = waiting.remove(&next) {
+                sink(next, result);
+                next += 1;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn options(concurrency: usize, per_host: usize, ordered: bool) -> PoolOptions {
+        PoolOptions { concurrency, per_host, ordered, ..Default::default() }
+    }
+
+    #[test]
+    fn ordered_results() {
+        let items: Vec<u64> = vec![40, 10, 30, 0, 20];
+        let mut seen = Vec::new();
+        run(&items, &options(4, 4, true), |_| None, |delay| {
+            thread::sleep(Duration::from_millis(*delay));
+            delay * 2
+        }, |index, result| seen.push((index, result)));
+        assert_eq!(seen, vec![(0, 80), (1, 20), (2, 60), (3, 0), (4, 40)]);
+    }
+
+    #[test]
+    fn unordered_results_as_they_finish() {
+        let items: Vec<u64> = vec![60, 0];
+        let mut seen = Vec::new();
+        run(&items, &options(2, 2, false), |_| None, |delay| thread::sleep(Duration::from_millis(*delay)), |index, _| seen.push(index));
+        assert_eq!(seen, vec![1, 0]);
+    }
+
+    fn max_parallel(items: &[&str], options: &PoolOptions) -> usize {
+        let active = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let mut count = 0;
+        run(items, options, |host| Some(host.to_string()), |_| {
+            let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            active.fetch_sub(1, Ordering::SeqCst);
+        }, |_, _| count += 1);
+        assert_eq!(count, items.len());
+        peak.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn concurrency_limit() {
+        let items = vec!["a", "b", "c", "d", "e", "f", "g", "h"];
+        assert_eq!(max_parallel(&items, &options(3, 1, false)), 3);
+        assert_eq!(max_parallel(&items, &options(1, 1, false)), 1);
+    }
+
+    #[test]
+    fn per_host_limit() {
+        let items = vec!["a", "a", "a", "a", "a", "a"];
+        assert_eq!(max_parallel(&items, &options(4, 2, false)), 2);
+    }
+
+    #[test]
+    fn host_limits_of_requests() {
+        let limits = HostLimits::new(&PoolOptions { per_host: 2, ..Default::default() });
+        let (active, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|scope| {
+            for host in ["a", "a", "a", "a", "a", "b"] {
+                let (limits, active, peak) = (&limits, &active, &peak);
+                scope.spawn(move || {
+                    let _permit = limits.acquire(host);
+                    if host == "a" {
+                        let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(20));
+                        active.fetch_sub(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(PoolOptions::default().per_host, DEFAULT_PER_HOST);
+        assert_eq!(host_key(&Url::parse("https://a.com/p").unwrap()), Some("a.com:443".to_string()));
+    }
+
+    #[test]
+    fn host_delay() {
+        let items = vec!["a", "a", "a"];
+        let options = PoolOptions { concurrency: 3, per_host: 3, host_delay: Duration::from_millis(30), ordered: false };
+        let starts = Mutex::new(Vec::new());
+        run(&items, &options, |host| Some(host.to_string()), |_| starts.lock().unwrap().push(Instant::now()), |_, _| {});
+        let starts = starts.into_inner().unwrap();
+        assert!(starts.windows(2).all(|pair| pair[1] - pair[0] >= Duration::from_millis(25)));
+    }
+}
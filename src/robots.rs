@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How much of a robots.txt is read at most, as RFC 9309 allows.
+const MAX_ROBOTS_LEN: usize = 500 * 1024;
+
+/// The rules of a robots.txt, as of RFC 9309: groups of `Allow` and `Disallow` rules, each for
+/// the user agents of its `User-agent` lines, with their `Crawl-delay`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RobotsTxt {
+    groups: Vec<Group>
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    allow: bool,
+    pattern: String
+}
+
+impl RobotsTxt {
+    /// Parse a robots.txt. The lines that are not understood are ignored, like crawlers do.
+    pub fn parse(text: &str) -> Self {
+        let text = match text.char_indices().nth(MAX_ROBOTS_LEN) {
+            Some((end, _)) => &text[..end],
+            None => text
+        };
+        let mut groups: Vec<Group> = Vec::new();
+        // consecutive `User-agent` lines share the group that follows them
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+                None => continue
+            };
+            if key == "user-agent" {
+                if !in_agents {
+                    groups.push(Group::default());
+                }
+                in_agents = true;
+                if let Some(group) = groups.last_mut() {
+                    group.agents.push(value.to_ascii_lowercase());
+                }
+                continue;
+            }
+            let group = match groups.last_mut() {
+                Some(group) => group,
+                None => continue
+            };
+            match key.as_str() {
+                "allow" | "disallow" if !value.is_empty() => group.rules.push(Rule { allow: key == "allow", pattern: value.to_string() }),
+                "crawl-delay" => group.crawl_delay = value.parse::<f64>().ok()
+                    .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                    .map(Duration::from_secs_f64),
+                _ => {}
+            }
+            in_agents = false;
+        }
+        Self { groups }
+    }
+
+    /// A robots.txt that disallows everything, for a site whose robots.txt is unreachable.
+    pub fn disallow_all() -> Self {
+        Self::parse("User-agent: *\nDisallow: /")
+    }
+
+    /// Whether the user agent may fetch the path, with its query, like `/search?q=a`. The longest
+    /// matching rule wins, and `Allow` wins over a `Disallow` of the same length.
+    pub fn is_allowed(&self, user_agent: &str, path: &str) -> bool {
+        if path == "/robots.txt" {
+            return true;
+        }
+        self.groups_of(user_agent).iter()
+            .flat_map(|group| group.rules.iter())
+            .filter(|rule| matches(&rule.pattern, path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+
+    /// The time to wait between two requests of the user agent to the site.
+    pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.groups_of(user_agent).iter().find_map(|group| group.crawl_delay)
+    }
+
+    // the groups of the product token of the user agent, `dessert2` for `dessert2/0.1.0`, or the
+    // ones of `*` when none is
+    fn groups_of(&self, user_agent: &str) -> Vec<&Group> {
+        let token = user_agent.split(|c: char| c == '/' || c.is_whitespace()).next().unwrap_or_default().to_ascii_lowercase();
+        let named: Vec<&Group> = self.groups.iter().filter(|group| group.agents.contains(&token)).collect();
+        if !named.is_empty() {
+            return named;
+        }
+        self.groups.iter().filter(|group| group.agents.iter().any(|agent| agent == "*")).collect()
+    }
+}
+
+// whether the pattern matches the start of the path: `*` matches any sequence of characters, and
+// a trailing `$` the end of the path
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false)
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let mut rest = match path.strip_prefix(parts[0]) {
+        Some(rest) => rest,
+        None => return false
+    };
+    if parts.len() == 1 {
+        return !anchored || rest.is_empty();
+    }
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false
+        }
+    }
+    let last = parts[parts.len() - 1];
+    if anchored { rest.ends_with(last) } else { rest.contains(last) }
+}
+
+// the robots.txt of a site once it is fetched
+type Site = Arc<OnceLock<RobotsTxt>>;
+
+/// The robots.txt of the sites of a run, each fetched once, and the time of the next request to
+/// every site that asks for a `Crawl-delay`.
+#[derive(Debug, Default)]
+pub struct Robots {
+    sites: Mutex<HashMap<String, Site>>,
+    next_requests: Mutex<HashMap<String, Instant>>
+}
+
+impl Robots {
+    /// The robots.txt of the origin, like `https://example.com`, read with `fetch` the first
+    /// time. The workers of a batch that ask for the same site wait for the same robots.txt.
+    pub(crate) fn get<F>(&self, origin: &str, fetch: F) -> RobotsTxt
+        where F: FnOnce() -> RobotsTxt {
+        let site = self.sites.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).entry(origin.to_string()).or_default().clone();
+        site.get_or_init(fetch).clone()
+    }
+
+    /// Wait until `delay` has passed since the last request to the origin.
+    pub(crate) fn wait(&self, origin: &str, delay: Duration) {
+        let now = Instant::now();
+        let start = {
+            let mut next_requests = self.next_requests.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let start = next_requests.get(origin).map_or(now, |next| (*next).max(now));
+            next_requests.insert(origin.to_string(), start + delay);
+            start
+        };
+        thread::sleep(start - now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "# the rules of example.com\n\
+        User-agent: *\n\
+        Disallow: /private\n\
+        Allow: /private/press\n\
+        Disallow: /*.pdf$\n\
+        Crawl-delay: 2\n\
+        \n\
+        User-agent: dessert2\n\
+        User-agent: OtherBot\n\
+        Disallow: /search\n\
+        Disallow: /cart/ # no carts\n\
+        Allow: /search/about\n\
+        Disallow:\n\
+        Sitemap: https://example.com/sitemap.xml\n\
+        \n\
+        User-agent: dessert2\n\
+        Crawl-delay: 0.5\n";
+
+    #[test]
+    fn rules_of_the_user_agent() {
+        let robots = RobotsTxt::parse(ROBOTS);
+        assert!(!robots.is_allowed("Mozilla/5.0", "/private/page"));
+        assert!(robots.is_allowed("Mozilla/5.0", "/private/press/2024"));
+        assert!(!robots.is_allowed("Mozilla/5.0", "/docs/a.pdf"));
+        assert!(robots.is_allowed("Mozilla/5.0", "/docs/a.pdf?download=1"));
+        assert!(robots.is_allowed("Mozilla/5.0", "/search"));
+        assert_eq!(robots.crawl_delay("Mozilla/5.0"), Some(Duration::from_secs(2)));
+        // the groups of the same agent are merged, and replace the ones of `*`
+        assert!(!robots.is_allowed("dessert2/0.1.0", "/search?q=a"));
+        assert!(robots.is_allowed("dessert2/0.1.0", "/search/about"));
+        assert!(!robots.is_allowed("DESSERT2", "/cart/"));
+        assert!(robots.is_allowed("dessert2/0.1.0", "/private/page"));
+        assert!(!robots.is_allowed("otherbot", "/search"));
+        assert_eq!(robots.crawl_delay("dessert2/0.1.0"), Some(Duration::from_millis(500)));
+        assert_eq!(robots.crawl_delay("otherbot"), None);
+        assert!(RobotsTxt::parse("").is_allowed("dessert2", "/anything"));
+        assert!(!RobotsTxt::disallow_all().is_allowed("dessert2", "/"));
+        assert!(RobotsTxt::disallow_all().is_allowed("dessert2", "/robots.txt"));
+    }
+
+    #[test]
+    fn longest_match_wins() {
+        let robots = RobotsTxt::parse("User-agent: *\nAllow: /page\nDisallow: /*.php\nDisallow: /pages\nAllow: /pages$");
+        assert!(robots.is_allowed("a", "/page/1"));
+        assert!(!robots.is_allowed("a", "/index.php"));
+        assert!(!robots.is_allowed("a", "/pages/1"));
+        assert!(robots.is_allowed("a", "/pages"));
+        assert!(matches("/*/b*c$", "/a/bxyc"));
+        assert!(!matches("/*/b*c$", "/a/bxycd"));
+        assert!(matches("/a*", "/a"));
+        assert!(!matches("/a$", "/ab"));
+    }
+}
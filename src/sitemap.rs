@@ -0,0 +1,302 @@
+use crate::batch::{self, Source};
+use crate::error::Error;
+use crate::http::Fetcher;
+
+use regex::Regex;
+use roxmltree::{Document, Node};
+use url::Url;
+
+use std::collections::HashSet;
+
+/// Which URLs of a sitemap are kept.
+#[derive(Debug, Clone, Default)]
+pub struct SitemapFilter {
+    /// Keep the URLs that match the regular expression.
+    pub pattern: Option<Regex>,
+    /// Keep the URLs whose `lastmod` is this day or later, in UTC. A `lastmod` of a year or of a
+    /// month, like `2024` or `2024-06`, is kept if the day is in it or before it. The URLs without
+    /// a valid `lastmod` are left out.
+    pub since: Option<Day>
+}
+
+impl SitemapFilter {
+    fn keeps(&self, url: &str, lastmod: Option<&str>) -> bool {
+        let recent = match &self.since {
+            None => true,
+            Some(since) => lastmod.and_then(W3cDatetime::parse).is_some_and(|lastmod| lastmod.last_day() >= *since)
+        };
+        recent && self.pattern.as_ref().is_none_or(|pattern| pattern.is_match(url))
+    }
+}
+
+/// A day of the Gregorian calendar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Day {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32
+}
+
+impl Day {
+    /// The first day of a W3C datetime, like `2024-05-01`, `2024-05` or `2024-05-01T08:00:00+02:00`,
+    /// in UTC.
+    pub fn parse(text: &str) -> Option<Self> {
+        W3cDatetime::parse(text).map(|datetime| datetime.first_day())
+    }
+
+    // the day `days` days after 1970-01-01, and back, as of http://howardhinnant.github.io/date_algorithms.html
+    fn from_days(days: i64) -> Self {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+        Self { year: year_of_era + era * 400 + i64::from(month <= 2), month, day }
+    }
+
+    fn days(&self) -> i64 {
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let shifted_month = i64::from((self.month + 9) % 12);
+        let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+// a datetime of https://www.w3.org/TR/NOTE-datetime, the format of `lastmod`, down to its precision
+enum W3cDatetime {
+    Year(i64),
+    Month(i64, u32),
+    // the day in UTC, once the time is shifted by its offset
+    Day(Day)
+}
+
+impl W3cDatetime {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let number = |digits: &str, len: usize| if digits.len() == len && digits.bytes().all(|b| b.is_ascii_digit()) { digits.parse::<u32>().ok() } else { None };
+        let (date, time) = match text.split_once('T') {
+            Some((date, time)) => (date, Some(time)),
+            None => (text, None)
+        };
+        let mut parts = date.split('-');
+        let year = i64::from(number(parts.next()?, 4)?);
+        let month = match parts.next() {
+            Some(month) => number(month, 2).filter(|month| (1..=12).contains(month))?,
+            None => return Some(W3cDatetime::Year(year))
+        };
+        let day = match parts.next() {
+            Some(day) => number(day, 2).filter(|day| (1..=days_in_month(year, month)).contains(day))?,
+            None if time.is_none() => return Some(W3cDatetime::Month(year, month)),
+            None => return None
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        let day = Day { year, month, day };
+        let time = match time {
+            Some(time) => time,
+            None => return Some(W3cDatetime::Day(day))
+        };
+        // hh:mm, then :ss and .s, then the offset, Z or +hh:mm or -hh:mm
+        let (clock, offset) = match time.find(['Z', '+', '-']) {
+            Some(index) => time.split_at(index),
+            None => return None
+        };
+        let mut clock = clock.split(':');
+        let hours = number(clock.next()?, 2).filter(|hours| *hours < 24)?;
+        let minutes = number(clock.next()?, 2).filter(|minutes| *minutes < 60)?;
+        if let Some(seconds) = clock.next() {
+            let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, "0"));
+            number(seconds, 2).filter(|seconds| *seconds <= 60)?;
+            if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+        }
+        if clock.next().is_some() {
+            return None;
+        }
+        let offset_minutes = match offset {
+            "Z" => 0,
+            _ => {
+                let (hours, minutes) = offset[1..].split_once(':')?;
+                let minutes = i64::from(number(hours, 2).filter(|hours| *hours < 24)? * 60 + number(minutes, 2).filter(|minutes| *minutes < 60)?);
+                if offset.starts_with('-') { -minutes } else { minutes }
+            }
+        };
+        let minutes = day.days() * 1440 + i64::from(hours * 60 + minutes) - offset_minutes;
+        Some(W3cDatetime::Day(Day::from_days(minutes.div_euclid(1440))))
+    }
+
+    fn first_day(&self) -> Day {
+        match *self {
+            W3cDatetime::Year(year) => Day { year, month: 1, day: 1 },
+            W3cDatetime::Month(year, month) => Day { year, month, day: 1 },
+            W3cDatetime::Day(day) => day
+        }
+    }
+
+    fn last_day(&self) -> Day {
+        match *self {
+            W3cDatetime::Year(year) => Day { year, month: 12, day: 31 },
+            W3cDatetime::Month(year, month) => Day { year, month, day: days_in_month(year, month) },
+            W3cDatetime::Day(day) => day
+        }
+    }
+}
+
+/// The URLs of a sitemap, a local file or a URL, compressed or not, read with `batch::read_html`.
+/// The sitemaps of a sitemap index are read in turn, every one of them once. The URLs are in the
+/// order of the sitemaps, without duplicates.
+pub fn urls(location: &str, fetcher: &Fetcher, filter: &SitemapFilter) -> Result<Vec<String>, Error> {
+    let mut urls = Vec::new();
+    expand(location, fetcher, filter, &mut HashSet::new(), &mut urls)?;
+    let mut seen = HashSet::new();
+    urls.retain(|url| seen.insert(url.clone()));
+    Ok(urls)
+}
+
+fn expand(location: &str, fetcher: &Fetcher, filter: &SitemapFilter, visited: &mut HashSet<String>, urls: &mut Vec<String>) -> Result<(), Error> {
+    if !visited.insert(location.to_string()) {
+        return Ok(());
+    }
+    let text = batch::read_html(&Source::parse(location).into(), fetcher)?;
+    let document = Document::parse(&text).map_err(|err| Error::Io(format!("invalid sitemap `{}`: {}", location, err)))?;
+    let root = document.root_element();
+    // the locations of a remote sitemap may be relative to it
+    let base = Url::parse(location).ok();
+    let resolve = |loc: &str| match &base {
+        Some(base) => base.join(loc).map(String::from).unwrap_or_else(|_| loc.to_string()),
+        None => loc.to_string()
+    };
+    match root.tag_name().name() {
+        "urlset" => for url in root.children().filter(|node| node.has_tag_name("url")) {
+            if let Some(loc) = child_text(url, "loc") {
+                let loc = resolve(loc);
+                if filter.keeps(&loc, child_text(url, "lastmod")) {
+                    urls.push(loc);
+                }
+            }
+        },
+        "sitemapindex" => for sitemap in root.children().filter(|node| node.has_tag_name("sitemap")) {
+            if let Some(loc) = child_text(sitemap, "loc") {
+                expand(&resolve(loc), fetcher, filter, visited, urls)?;
+            }
+        },
+        name => return Err(Error::Io(format!("`{}` is not a sitemap, its root element is <{}>", location, name)))
+    }
+    Ok(())
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children().find(|child| child.has_tag_name(name))?.text().map(str::trim).filter(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpOptions;
+    use crate::test_server::{Response, TestServer};
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const NS: &str = r#"xmlns="http://www.sitemaps.org/schemas/sitemap/0.9""#;
+
+    fn gzip(text: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn site() -> TestServer {
+        TestServer::start(|request| match request.path.as_str() {
+            "/sitemap.xml" => Response::html(&format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?><sitemapindex {}>\
+                <sitemap><loc>/products.xml.gz</loc></sitemap><sitemap><loc>/blog.xml</loc></sitemap><sitemap><loc>/sitemap.xml</loc></sitemap>\
+                </sitemapindex>", NS)),
+            "/products.xml.gz" => {
+                let mut response = Response::html("");
+                response.body = gzip(&format!("<urlset {}><url><loc>https://a.com/p/1</loc><lastmod>2024-05-01</lastmod></url>\
+                    <url><loc> https://a.com/p/2 </loc><lastmod>2023-12-31T23:00:00+00:00</lastmod></url></urlset>", NS));
+                response
+            },
+            "/blog.xml" => Response::html(&format!("<urlset {}><url><loc>https://a.com/blog/1</loc><lastmod>2024-06-02T08:00:00Z</lastmod></url>\
+                <url><loc>https://a.com/p/1</loc></url><url><loc>https://a.com/blog/2</loc></url></urlset>", NS)),
+            _ => Response::html("<html><p>not a sitemap</p></html>")
+        })
+    }
+
+    #[test]
+    fn expand_sitemap_index() {
+        let server = site();
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        let all = urls(&server.url("/sitemap.xml"), &fetcher, &SitemapFilter::default()).unwrap();
+        assert_eq!(all, vec!["https://a.com/p/1", "https://a.com/p/2", "https://a.com/blog/1", "https://a.com/blog/2"]);
+        let paths: Vec<String> = server.requests().iter().map(|request| request.path.clone()).collect();
+        assert_eq!(paths, vec!["/sitemap.xml", "/products.xml.gz", "/blog.xml"]);
+
+        let recent = SitemapFilter { since: Day::parse("2024-01-01"), ..Default::default() };
+        assert_eq!(urls(&server.url("/sitemap.xml"), &fetcher, &recent).unwrap(), vec!["https://a.com/p/1", "https://a.com/blog/1"]);
+        let products = SitemapFilter { pattern: Some(Regex::new("/p/").unwrap()), ..Default::default() };
+        assert_eq!(urls(&server.url("/sitemap.xml"), &fetcher, &products).unwrap(), vec!["https://a.com/p/1", "https://a.com/p/2"]);
+
+        let error = urls(&server.url("/page.html"), &fetcher, &SitemapFilter::default()).unwrap_err();
+        assert!(matches!(&error, Error::Io(reason) if reason.ends_with("is not a sitemap, its root element is <html>")), "{}", error);
+    }
+
+    #[test]
+    fn parse_w3c_datetimes() {
+        let day = |year, month, day| Some(Day { year, month, day });
+        assert_eq!(Day::parse("2024-05-01"), day(2024, 5, 1));
+        assert_eq!(Day::parse(" 2024 "), day(2024, 1, 1));
+        assert_eq!(Day::parse("2024-06"), day(2024, 6, 1));
+        assert_eq!(Day::parse("2024-05-01T08:00Z"), day(2024, 5, 1));
+        assert_eq!(Day::parse("2024-05-01T01:30:00+02:00"), day(2024, 4, 30));
+        assert_eq!(Day::parse("2023-12-31T23:00:00.5-01:00"), day(2024, 1, 1));
+        assert_eq!(Day::parse("2024-02-29"), day(2024, 2, 29));
+        for invalid in vec!["2024-13-45", "2023-02-29", "2024-5-1", "May 1st", "2024-05-01T08:00", "2024-05-01T25:00Z", "2024-05-01T08:00+2", "2024-05-01T08:00:00.Z", ""] {
+            assert_eq!(Day::parse(invalid), None, "{}", invalid);
+        }
+        assert_eq!(Day::from_days(Day { year: 1969, month: 12, day: 31 }.days()), Day { year: 1969, month: 12, day: 31 });
+        assert_eq!(Day { year: 2000, month: 3, day: 1 }.days(), 11_017);
+    }
+
+    #[test]
+    fn filter_by_lastmod() {
+        let since = SitemapFilter { since: Day::parse("2024-05-01"), ..Default::default() };
+        assert!(since.keeps("a", Some("2024")));
+        assert!(since.keeps("a", Some("2024-05")));
+        assert!(!since.keeps("a", Some("2024-04")));
+        assert!(since.keeps("a", Some("2024-04-30T23:30:00-01:00")));
+        assert!(!since.keeps("a", Some("2024-05-01T01:00:00+02:00")));
+        assert!(!since.keeps("a", Some("2024-13-45")));
+        assert!(!since.keeps("a", None));
+        assert!(SitemapFilter::default().keeps("a", Some("not a date")));
+    }
+
+    #[test]
+    fn read_local_sitemaps() {
+        let path = std::env::temp_dir().join(format!("dessert2-sitemap-{}.xml.gz", std::process::id()));
+        std::fs::write(&path, gzip(&format!("<urlset {}><url><loc>https://a.com/</loc></url></urlset>", NS))).unwrap();
+        let fetcher = Fetcher::new(HttpOptions::default()).unwrap();
+        assert_eq!(urls(&path.display().to_string(), &fetcher, &SitemapFilter::default()).unwrap(), vec!["https://a.com/"]);
+        std::fs::write(&path, gzip("<urlset><url>")).unwrap();
+        assert!(matches!(urls(&path.display().to_string(), &fetcher, &SitemapFilter::default()), Err(Error::Io(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+use crate::extract::{IntermediateObject, PropertyValue};
+use crate::idents::IdentObj;
+
+/// How the objects of a template are laid out as tables.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TableLayout {
+    /// A single table with a leading `object_id` column. Its columns are the properties of all the
+    /// objects, in the order they first appear in the template.
+    Stream,
+    /// One table per object, named after its `object_id`.
+    PerObject
+}
+
+/// The field separator of a table, and the escaping that goes with it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delimiter {
+    /// RFC 4180: fields containing a comma, a quote or a line break are quoted, quotes are doubled.
+    Comma,
+    /// The text format of PostgreSQL `COPY`: backslashes, tabs and line breaks are escaped with a
+    /// backslash, and missing values are `\N`.
+    Tab
+}
+
+impl Delimiter {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Delimiter::Comma => "csv",
+            Delimiter::Tab => "tsv"
+        }
+    }
+
+    fn separator(&self) -> char {
+        match self {
+            Delimiter::Comma => ',',
+            Delimiter::Tab => '\t'
+        }
+    }
+
+    // the text of a field, `None` for a missing value
+    fn field(&self, field: Option<&str>) -> String {
+        match (self, field) {
+            (_, Some(field)) => self.escape(field),
+            (Delimiter::Comma, None) => String::new(),
+            (Delimiter::Tab, None) => "\\N".to_string()
+        }
+    }
+
+    fn escape(&self, field: &str) -> String {
+        match self {
+            Delimiter::Comma => {
+                if field.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.to_string()
+                }
+            },
+            Delimiter::Tab => field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+        }
+    }
+}
+
+/// Whether the `object_id` of an object can name its file in the `per-object` layout: a file of
+/// the output directory, not a path out of it.
+pub fn is_file_name(object_id: &str) -> bool {
+    !object_id.is_empty() && object_id != "." && object_id != ".." && !object_id.contains(['/', '\\', '\0'])
+}
+
+/// The text of a cell, `None` for missing and `NA` values. Nested values are written as JSON.
+fn cell(value: Option<&PropertyValue>) -> Option<String> {
+    match value {
+        None | Some(PropertyValue::NA) => None,
+        Some(value) => Some(value.to_string())
+    }
+}
+
+fn write_row(table: &mut String, fields: &[Option<String>], delimiter: Delimiter) {
+    let row: Vec<String> = fields.iter().map(|f| delimiter.field(f.as_deref())).collect();
+    table.push_str(&row.join(&delimiter.separator().to_string()));
+    table.push('\n');
+}
+
+// the property ids of an object, and the detail record of the link it follows, as JSON
+fn columns(ident: &IdentObj) -> impl Iterator<Item=&String> {
+    ident.properties.iter().map(|p| &p.id).chain(ident.follow.iter().map(|follow| &follow.object.object_id))
+}
+
+/// Render the records of one object as a table, with a header row of its property ids. In a batch
+/// the same object is extracted from every input, and a `source` column tells them apart.
+pub fn object_to_table(objects: &[&IntermediateObject], ident: &IdentObj, delimiter: Delimiter) -> String {
+    let with_source = objects.iter().any(|object| object.source.is_some());
+    let header: Vec<String> = columns(ident).cloned().collect();
+    let mut table = String::new();
+    let mut header_row = if with_source { vec![Some("source".to_string())] } else { Vec::new() };
+    header_row.extend(header.iter().cloned().map(Some));
+    write_row(&mut table, &header_row, delimiter);
+    for object in objects {
+        for record in &object.records {
+            let mut row = if with_source { vec![object.source.clone()] } else { Vec::new() };
+            row.extend(header.iter().map(|h| cell(record.get(h))));
+            write_row(&mut table, &row, delimiter);
+        }
+    }
+    table
+}
+
+/// Render the records of all the objects as a single table, with an `object_id` column, and a
+/// `source` column in a batch.
+pub fn objects_to_table(objects: &[IntermediateObject], idents: &[IdentObj], delimiter: Delimiter) -> String {
+    let with_source = objects.iter().any(|object| object.source.is_some());
+    let mut header: Vec<String> = Vec::new();
+    for column in idents.iter().flat_map(columns) {
+        if !header.contains(column) {
+            header.push(column.clone());
+        }
+    }
+    let mut table = String::new();
+    let mut header_row = vec![Some("object_id".to_string())];
+    if with_source {
+        header_row.push(Some("source".to_string()));
+    }
+    header_row.extend(header.iter().cloned().map(Some));
+    write_row(&mut table, &header_row, delimiter);
+    for object in objects {
+        for record in &object.records {
+            let mut row = vec![Some(object.object_id.clone())];
+            if with_source {
+                row.push(object.source.clone());
+            }
+            row.extend(header.iter().map(|h| cell(record.get(h))));
+            write_row(&mut table, &row, delimiter);
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_csv() {
+        assert_eq!(Delimiter::Comma.escape("plain text"), "plain text");
+        assert_eq!(Delimiter::Comma.escape("a,b"), "\"a,b\"");
+        assert_eq!(Delimiter::Comma.escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(Delimiter::Comma.escape("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn escape_tsv() {
+        assert_eq!(Delimiter::Tab.escape("plain text"), "plain text");
+        assert_eq!(Delimiter::Tab.escape("a\tb\nc\\d\r"), "a\\tb\\nc\\\\d\\r");
+        assert_eq!(Delimiter::Tab.escape("a,\"b\""), "a,\"b\"");
+    }
+
+    #[test]
+    fn file_names() {
+        assert!(is_file_name("users"));
+        assert!(is_file_name("user.info"));
+        for path in &["", ".", "..", "a/b", "../../etc/x", "a\\b", "/etc"] {
+            assert!(!is_file_name(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn missing_values() {
+        let record: crate::extract::Record = vec![("name".to_string(), PropertyValue::Str("Ada".to_string())), ("age".to_string(), PropertyValue::NA)].into_iter().collect();
+        let object = IntermediateObject { object_id: "users".to_string(), records: vec![record], ..Default::default() };
+        let ident: IdentObj = serde_json::from_str(r#"{"object_id": "users", "css_selector": "li", "properties": [
+            {"id": "name", "css_selector": "b", "value_type": "Str"}, {"id": "age", "css_selector": "i", "value_type": "Int"}, {"id": "city", "css_selector": "u", "value_type": "Str"}
+        ]}"#).unwrap();
+        // `COPY FROM` loads `\N` as NULL, the CSV cells are empty
+        assert_eq!(object_to_table(&[&object], &ident, Delimiter::Tab), "name\tage\tcity\nAda\t\\N\t\\N\n");
+        assert_eq!(object_to_table(&[&object], &ident, Delimiter::Comma), "name,age,city\nAda,,\n");
+        assert_eq!(objects_to_table(&[object], &[ident], Delimiter::Tab), "object_id\tname\tage\tcity\nusers\tAda\t\\N\t\\N\n");
+    }
+}
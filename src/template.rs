@@ -0,0 +1,379 @@
+use crate::extract::ExtractError;
+use crate::table;
+use crate::idents::{Cardinality, CaptureGroup, Follow, IdentObj, Identity, IdentityValueType, Pagination, SelectorType, Template, Transform, ValueFrom, Whitespace};
+use crate::xpath::XPath;
+use regex::Regex;
+use scraper::Selector;
+use url::Url;
+
+/// A template checked and compiled once: every CSS selector, XPath expression and regular
+/// expression is parsed when the template is built, and reused for every match of every page it
+/// is run on.
+///
+/// ```
+/// use dessert2::{idents, CompiledTemplate, Extractor, OutputFormat};
+///
+/// let idents = idents::from_json_str(r#"[{"object_id": "links", "css_selector": "li", "properties": [
+///     {"id": "href", "css_selector": "a", "value_type": "Str", "value_from": {"Property": "href"}}
+/// ]}]"#).unwrap();
+/// let template = CompiledTemplate::compile(&idents)?;
+/// for page in &["<li><a href='/a'>a</a></li>", "<li><a href='/b'>b</a></li><li><a href='/c'>c</a></li>"] {
+///     let result = Extractor::new(page, OutputFormat::Json).extract(&template)?;
+///     assert!(!result[0].records.is_empty());
+/// }
+/// # Ok::<(), dessert2::ExtractError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompiledTemplate {
+    pub(crate) idents: Vec<IdentObj>,
+    pub(crate) objects: Vec<CompiledObject>,
+    pub(crate) pagination: Option<CompiledPagination>,
+    pub(crate) max_depth: usize
+}
+
+/// The number of pages of a listing read at most when the template does not say.
+pub const DEFAULT_MAX_PAGES: usize = 100;
+/// The number of links followed at most from the page of an input down to a detail page.
+pub const DEFAULT_MAX_DEPTH: usize = 3;
+
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledObject {
+    pub(crate) object_id: String,
+    pub(crate) selector: CompiledSelector,
+    pub(crate) properties: Vec<CompiledProperty>,
+    pub(crate) follow: Option<Box<CompiledFollow>>
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledFollow {
+    pub(crate) property: String,
+    pub(crate) object: CompiledObject,
+    pub(crate) any_domain: bool
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledProperty {
+    pub(crate) id: String,
+    pub(crate) selector: CompiledSelector,
+    pub(crate) value_type: IdentityValueType,
+    pub(crate) value_from: ValueFrom,
+    pub(crate) whitespace: Whitespace,
+    pub(crate) cardinality: Cardinality,
+    pub(crate) transforms: Vec<CompiledTransform>,
+    pub(crate) properties: Vec<CompiledProperty>
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum CompiledSelector {
+    Css(Selector),
+    XPath(XPath)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledPagination {
+    pub(crate) next: NextPage,
+    pub(crate) max_pages: usize
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum NextPage {
+    Link(CompiledSelector),
+    /// The pattern, and the number of the first page.
+    Pattern(String, u64)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum CompiledTransform {
+    Capture { regex: Regex, group: Option<CaptureGroup> },
+    Replace { regex: Regex, with: String },
+    Trim
+}
+
+impl CompiledTemplate {
+    /// Compile the template. Every invalid selector, regular expression and capture group is
+    /// reported at once in `ExtractError::InvalidTemplate`, with its path in the template, like
+    /// `[0].properties[2].css_selector`.
+    pub fn compile(idents: &[IdentObj]) -> Result<Self, ExtractError> {
+        Self::compile_template(&Template { objects: idents.to_vec(), pagination: None })
+    }
+
+    /// Compile a template in its document form, with its pagination. Its errors are reported like
+    /// the ones of `compile`, with paths like `pagination.next_selector`.
+    pub fn compile_template(template: &Template) -> Result<Self, ExtractError> {
+        let idents = &template.objects;
+        let mut errors = Vec::new();
+        let objects: Vec<CompiledObject> = idents.iter().enumerate()
+            .filter_map(|(index, ident)| compile_object(ident, &format!("[{}]", index), &mut errors))
+            .collect();
+        let pagination = template.pagination.as_ref().and_then(|pagination| compile_pagination(pagination, &mut errors));
+        if !errors.is_empty() {
+            return Err(ExtractError::InvalidTemplate(errors));
+        }
+        Ok(Self { idents: idents.to_vec(), objects, pagination, max_depth: DEFAULT_MAX_DEPTH })
+    }
+
+    /// Check that every object can be written to a file of its own, as the tables of the
+    /// `per-object` layout are: its `object_id` is a file name, not a path, and no other object
+    /// has it. The errors are reported like the ones of `compile`.
+    pub fn check_file_names(&self) -> Result<(), ExtractError> {
+        let mut errors = Vec::new();
+        for (index, ident) in self.idents.iter().enumerate() {
+            if !table::is_file_name(&ident.object_id) {
+                errors.push(format!("[{}].object_id: `{}` cannot name a file of the output directory", index, ident.object_id));
+            } else if self.idents[..index].iter().any(|other| other.object_id == ident.object_id) {
+                errors.push(format!("[{}].object_id: `{}` is the id of another object, whose file it would overwrite", index, ident.object_id));
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(ExtractError::InvalidTemplate(errors)) }
+    }
+
+    /// Follow the links of the records down to `max_depth` pages from the one of the input, 3 by
+    /// default. The links deeper than that are not followed, their detail record is `NA`.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The template it was compiled from.
+    pub fn idents(&self) -> &[IdentObj] {
+        &self.idents
+    }
+
+    /// Whether the next pages of a listing are read as well.
+    pub fn has_pagination(&self) -> bool {
+        self.pagination.is_some()
+    }
+
+    /// Whether the links of some records are followed to detail pages.
+    pub fn has_follow(&self) -> bool {
+        self.objects.iter().any(|object| object.follow.is_some())
+    }
+}
+
+fn compile_object(ident: &IdentObj, path: &str, errors: &mut Vec<String>) -> Option<CompiledObject> {
+    let selector = compile_selector(&ident.css_selector, &ident.selector_type, &format!("{}.css_selector", path), errors);
+    let properties = compile_properties(&ident.properties, path, errors);
+    let follow = match &ident.follow {
+        Some(follow) => Some(Box::new(compile_follow(follow, ident, &format!("{}.follow", path), errors)?)),
+        None => None
+    };
+    Some(CompiledObject { object_id: ident.object_id.clone(), selector: selector?, properties, follow })
+}
+
+fn compile_follow(follow: &Follow, parent: &IdentObj, path: &str, errors: &mut Vec<String>) -> Option<CompiledFollow> {
+    match parent.properties.iter().find(|prop| prop.id == follow.property) {
+        None => errors.push(format!("{}.property: no property `{}` in `{}`", path, follow.property, parent.object_id)),
+        Some(prop) if !matches!(prop.value_type, IdentityValueType::Url | IdentityValueType::Str) =>
+            errors.push(format!("{}.property: `{}` is not a `Url` or `Str` property", path, follow.property)),
+        Some(_) => {}
+    }
+    if parent.properties.iter().any(|prop| prop.id == follow.object.object_id) {
+        errors.push(format!("{}.object.object_id: `{}` is already a property of `{}`", path, follow.object.object_id, parent.object_id));
+    }
+    let object = compile_object(&follow.object, &format!("{}.object", path), errors)?;
+    Some(CompiledFollow { property: follow.property.clone(), object, any_domain: follow.any_domain })
+}
+
+fn compile_selector(selector: &str, selector_type: &SelectorType, path: &str, errors: &mut Vec<String>) -> Option<CompiledSelector> {
+    let compiled = match selector_type {
+        SelectorType::Css => Selector::parse(selector).map(CompiledSelector::Css)
+            .map_err(|err| format!("invalid CSS selector `{}`: {:?}", selector, err.kind)),
+        SelectorType::XPath => XPath::parse(selector).map(CompiledSelector::XPath)
+            .map_err(|err| format!("invalid XPath `{}`: {}", selector, err))
+    };
+    compiled.map_err(|err| errors.push(format!("{}: {}", path, err))).ok()
+}
+
+fn compile_pagination(pagination: &Pagination, errors: &mut Vec<String>) -> Option<CompiledPagination> {
+    let max_pages = pagination.max_pages.unwrap_or(DEFAULT_MAX_PAGES);
+    if max_pages == 0 {
+        errors.push("pagination.max_pages: must be at least 1".to_string());
+    }
+    let next = match (&pagination.next_selector, &pagination.url_pattern) {
+        (Some(selector), None) => NextPage::Link(compile_selector(selector, &pagination.selector_type, "pagination.next_selector", errors)?),
+        (None, Some(pattern)) if !pattern.contains("{page}") => {
+            errors.push(format!("pagination.url_pattern: no `{{page}}` in `{}`", pattern));
+            return None;
+        },
+        (None, Some(pattern)) => match Url::parse(&pattern.replace("{page}", "1")) {
+            Ok(_) => NextPage::Pattern(pattern.clone(), pagination.start.unwrap_or(1)),
+            Err(err) => {
+                errors.push(format!("pagination.url_pattern: invalid URL `{}`: {}", pattern, err));
+                return None;
+            }
+        },
+        _ => {
+            errors.push("pagination: either `next_selector` or `url_pattern` must be set".to_string());
+            return None;
+        }
+    };
+    Some(CompiledPagination { next, max_pages })
+}
+
+fn compile_properties(props: &[Identity], parent_path: &str, errors: &mut Vec<String>) -> Vec<CompiledProperty> {
+    props.iter().enumerate().filter_map(|(index, prop)| {
+        let path = format!("{}.properties[{}]", parent_path, index);
+        let selector = compile_selector(&prop.css_selector, &prop.selector_type, &format!("{}.css_selector", path), errors);
+        let transforms: Vec<Option<CompiledTransform>> = prop.transforms.iter().enumerate()
+            .map(|(index, transform)| compile_transform(transform, &format!("{}.transforms[{}]", path, index), errors))
+            .collect();
+        let properties = compile_properties(&prop.properties, &path, errors);
+        Some(CompiledProperty {
+            id: prop.id.clone(),
+            selector: selector?,
+            value_type: prop.value_type.clone(),
+            value_from: prop.value_from.clone(),
+            whitespace: prop.whitespace.clone(),
+            cardinality: prop.cardinality.clone(),
+            transforms: transforms.into_iter().collect::<Option<Vec<_>>>()?,
+            properties
+        })
+    }).collect()
+}
+
+pub(crate) fn compile_transform(transform: &Transform, path: &str, errors: &mut Vec<String>) -> Option<CompiledTransform> {
+    let compile_regex = |pattern: &str, errors: &mut Vec<String>| Regex::new(pattern)
+        .map_err(|err| errors.push(format!("{}.pattern: {}", path, err)))
+        .ok();
+    match transform {
+        Transform::Trim => Some(CompiledTransform::Trim),
+        Transform::Replace { pattern, with } => Some(CompiledTransform::Replace { regex: compile_regex(pattern, errors)?, with: with.clone() }),
+        Transform::Capture { pattern, group } => {
+            let regex = compile_regex(pattern, errors)?;
+            let exists = match group {
+                None => true,
+                Some(CaptureGroup::Index(index)) => *index < regex.captures_len(),
+                Some(CaptureGroup::Name(name)) => regex.capture_names().any(|n| n == Some(name.as_str()))
+            };
+            if !exists {
+                errors.push(format!("{}.group: no capture group {:?} in `{}`", path, group, pattern));
+                return None;
+            }
+            Some(CompiledTransform::Capture { regex, group: group.clone() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idents;
+
+    fn price_template(transforms: Vec<Transform>) -> Vec<IdentObj> {
+        vec![IdentObj {
+            object_id: "book-info".to_owned(),
+            css_selector: "div#book_info".to_owned(),
+            properties: vec![Identity {
+                    id: "price".to_owned(),
+                    css_selector: "div#price".to_owned(),
+                    transforms,
+                    ..Default::default()
+            }],
+            ..Default::default()
+        }]
+    }
+
+    fn compile_errors(idents: &[IdentObj]) -> Vec<String> {
+        match CompiledTemplate::compile(idents) {
+            Err(ExtractError::InvalidTemplate(errors)) => errors,
+            other => panic!("expected an invalid template, found {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn check_file_names() {
+        let idents = idents::from_json_str(r#"[{"object_id": "users", "css_selector": "li", "properties": []},
+            {"object_id": "../../etc/x", "css_selector": "li", "properties": []}, {"object_id": "users", "css_selector": "p", "properties": []}]"#).unwrap();
+        let errors = match CompiledTemplate::compile(&idents).unwrap().check_file_names() {
+            Err(ExtractError::InvalidTemplate(errors)) => errors,
+            other => panic!("expected an invalid template, found {:?}", other)
+        };
+        assert_eq!(errors, vec![
+            "[1].object_id: `../../etc/x` cannot name a file of the output directory".to_string(),
+            "[2].object_id: `users` is the id of another object, whose file it would overwrite".to_string()
+        ]);
+        assert!(CompiledTemplate::compile(&idents[..1]).unwrap().check_file_names().is_ok());
+    }
+
+    #[test]
+    fn compile_invalid_regex() {
+        let errors = compile_errors(&price_template(vec![Transform::Replace { pattern: "([0-9]".to_string(), with: "".to_string() }]));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("[0].properties[0].transforms[0].pattern: "));
+    }
+
+    #[test]
+    fn compile_missing_group() {
+        let errors = compile_errors(&price_template(vec![Transform::Capture { pattern: "([0-9]+)".to_string(), group: Some(CaptureGroup::Name("amount".to_string())) }]));
+        assert_eq!(errors, vec!["[0].properties[0].transforms[0].group: no capture group Some(Name(\"amount\")) in `([0-9]+)`".to_string()]);
+        let template = price_template(vec![Transform::Capture { pattern: "(?P<amount>[0-9]+)".to_string(), group: Some(CaptureGroup::Name("amount".to_string())) }]);
+        assert!(CompiledTemplate::compile(&template).is_ok());
+    }
+
+    #[test]
+    fn compile_reports_every_error() {
+        let mut template = price_template(vec![Transform::Trim, Transform::Replace { pattern: "(".to_string(), with: "".to_string() }]);
+        template[0].css_selector = "div[".to_string();
+        template[0].properties.push(Identity {
+            id: "details".to_owned(),
+            css_selector: "div.details".to_owned(),
+            value_type: IdentityValueType::Object,
+            properties: vec![Identity {
+                id: "name".to_owned(),
+                css_selector: "//span[".to_owned(),
+                selector_type: SelectorType::XPath,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let errors = compile_errors(&template);
+        let paths: Vec<&str> = errors.iter().map(|e| e.split(": ").next().unwrap()).collect();
+        assert_eq!(paths, vec!["[0].css_selector", "[0].properties[0].transforms[1].pattern", "[0].properties[1].properties[0].css_selector"]);
+    }
+
+    #[test]
+    fn compiled_template_is_shareable() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<CompiledTemplate>();
+    }
+
+    #[test]
+    fn compile_pagination_errors() {
+        let template = |pagination: Pagination| Template { objects: price_template(vec![]), pagination: Some(pagination) };
+        let errors = |pagination| match CompiledTemplate::compile_template(&template(pagination)) {
+            Err(ExtractError::InvalidTemplate(errors)) => errors,
+            other => panic!("expected an invalid template, found {:?}", other.map(|_| ()))
+        };
+        assert_eq!(errors(Pagination::default()), vec!["pagination: either `next_selector` or `url_pattern` must be set".to_string()]);
+        assert_eq!(errors(Pagination { url_pattern: Some("https://a.com/?p=1".to_owned()), max_pages: Some(0), ..Default::default() }),
+            vec!["pagination.max_pages: must be at least 1".to_string(), "pagination.url_pattern: no `{page}` in `https://a.com/?p=1`".to_string()]);
+        assert!(errors(Pagination { url_pattern: Some("/cities?page={page}".to_owned()), ..Default::default() })[0].starts_with("pagination.url_pattern: invalid URL"));
+        assert!(errors(Pagination { next_selector: Some("a[".to_owned()), ..Default::default() })[0].starts_with("pagination.next_selector: invalid CSS selector"));
+        let compiled = CompiledTemplate::compile_template(&template(Pagination { next_selector: Some("//a[@rel='next']/@href".to_owned()), selector_type: SelectorType::XPath, ..Default::default() })).unwrap();
+        assert!(compiled.has_pagination());
+        assert!(!CompiledTemplate::compile(&price_template(vec![])).unwrap().has_pagination());
+    }
+
+    #[test]
+    fn compile_follow_errors() {
+        let follow = |property: &str, object_id: &str| {
+            let mut idents = price_template(vec![]);
+            idents[0].properties[0].value_type = IdentityValueType::Float;
+            idents[0].properties.push(Identity { id: "link".to_owned(), css_selector: "a".to_owned(), value_type: IdentityValueType::Url, ..Default::default() });
+            let object = IdentObj { object_id: object_id.to_owned(), css_selector: "main[".to_owned(), ..Default::default() };
+            idents[0].follow = Some(Follow { property: property.to_owned(), object: Box::new(object), any_domain: false });
+            compile_errors(&idents)
+        };
+        assert_eq!(follow("link", "detail"), vec!["[0].follow.object.css_selector: invalid CSS selector `main[`: Basic(EndOfInput)".to_string()]);
+        assert_eq!(follow("url", "price")[..2], [
+            "[0].follow.property: no property `url` in `book-info`".to_string(),
+            "[0].follow.object.object_id: `price` is already a property of `book-info`".to_string()
+        ]);
+        assert_eq!(follow("price", "detail")[0], "[0].follow.property: `price` is not a `Url` or `Str` property");
+        let mut idents = price_template(vec![]);
+        idents[0].follow = Some(Follow { property: "price".to_owned(), object: Box::new(IdentObj { object_id: "detail".to_owned(), css_selector: "main".to_owned(), ..Default::default() }), any_domain: false });
+        let compiled = CompiledTemplate::compile(&idents).unwrap();
+        assert!(compiled.has_follow());
+        assert!(!CompiledTemplate::compile(&price_template(vec![])).unwrap().has_follow());
+    }
+}
@@ -0,0 +1,121 @@
+//! A local HTTP/1.1 stand-in server for the tests that fetch pages.
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>
+}
+
+impl Request {
+    /// The value of a header, by its case-insensitive name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// How long to wait before answering.
+    pub delay: Duration
+}
+
+impl Response {
+    pub fn html(body: &str) -> Self {
+        Self::status(200, body).header("Content-Type", "text/html; charset=utf-8")
+    }
+
+    pub fn status(status: u16, body: &str) -> Self {
+        Self { status, headers: Vec::new(), body: body.as_bytes().to_vec(), delay: Duration::from_millis(0) }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+pub struct TestServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>
+}
+
+impl TestServer {
+    /// Serve every connection on its own thread, answering one request per connection.
+    pub fn start<F: Fn(&Request) -> Response + Send + Sync + 'static>(handler: F) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (handler, recorded) = (handler.clone(), recorded.clone());
+                thread::spawn(move || serve(stream, &*handler, &recorded));
+            }
+        });
+        Self { addr, requests }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// `host:port` of the server.
+    pub fn host(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// The requests received so far, in the order they arrived.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn serve<F: Fn(&Request) -> Response>(stream: TcpStream, handler: &F, recorded: &Mutex<Vec<Request>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+        return;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let request = Request { method, path, headers };
+    recorded.lock().unwrap().push(request.clone());
+    let response = handler(&request);
+    thread::sleep(response.delay);
+    let mut stream = stream;
+    let mut head = format!("HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, response.body.len());
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(&response.body);
+}
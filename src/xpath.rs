@@ -0,0 +1,1126 @@
+use ego_tree::{NodeId, NodeRef};
+use scraper::Node;
+
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::iter;
+
+/// A node of the document as seen by XPath. Attributes are not nodes of the `scraper` tree, so they
+/// are represented by their owner element plus their name and value.
+#[derive(Debug, Clone, Copy)]
+pub enum XNode<'a> {
+    Node(NodeRef<'a, Node>),
+    Attribute(NodeRef<'a, Node>, &'a str, &'a str)
+}
+
+impl<'a> XNode<'a> {
+    /// The string-value of the node as defined by XPath: the text of every descendant text node for
+    /// elements and the document, the value for attributes, text and comments.
+    pub fn string_value(&self) -> String {
+        match self {
+            XNode::Attribute(_, _, value) => value.to_string(),
+            XNode::Node(node) => match node.value() {
+                Node::Text(text) => text.to_string(),
+                Node::Comment(comment) => comment.to_string(),
+                Node::ProcessingInstruction(pi) => pi.data.to_string(),
+                Node::Doctype(_) => String::new(),
+                _ => node.descendants().filter_map(|d| d.value().as_text()).map(|t| &**t).collect()
+            }
+        }
+    }
+
+    fn name(&self) -> &'a str {
+        match self {
+            XNode::Attribute(_, name, _) => name,
+            XNode::Node(node) => match node.value() {
+                Node::Element(elm) => elm.name(),
+                Node::ProcessingInstruction(pi) => &pi.target,
+                _ => ""
+            }
+        }
+    }
+
+    fn same(&self, other: &XNode) -> bool {
+        match (self, other) {
+            (XNode::Node(a), XNode::Node(b)) => a.id() == b.id(),
+            (XNode::Attribute(a, a_name, _), XNode::Attribute(b, b_name, _)) => a.id() == b.id() && a_name == b_name,
+            _ => false
+        }
+    }
+
+    fn owner(&self) -> NodeRef<'a, Node> {
+        match self {
+            XNode::Node(node) => *node,
+            XNode::Attribute(owner, _, _) => *owner
+        }
+    }
+}
+
+/// The result of an XPath expression.
+#[derive(Debug, Clone)]
+pub enum Value<'a> {
+    NodeSet(Vec<XNode<'a>>),
+    Bool(bool),
+    Num(f64),
+    Str(String)
+}
+
+impl<'a> Value<'a> {
+    pub fn to_xpath_string(&self) -> String {
+        match self {
+            Value::NodeSet(nodes) => nodes.first().map(|n| n.string_value()).unwrap_or_default(),
+            Value::Bool(b) => b.to_string(),
+            Value::Num(n) => number_to_string(*n),
+            Value::Str(s) => s.clone()
+        }
+    }
+
+    fn to_bool(&self) -> bool {
+        match self {
+            Value::NodeSet(nodes) => !nodes.is_empty(),
+            Value::Bool(b) => *b,
+            Value::Num(n) => *n != 0.0 && !n.is_nan(),
+            Value::Str(s) => !s.is_empty()
+        }
+    }
+
+    fn to_number(&self) -> f64 {
+        match self {
+            Value::Num(n) => *n,
+            Value::Bool(b) => if *b { 1.0 } else { 0.0 },
+            _ => string_to_number(&self.to_xpath_string())
+        }
+    }
+}
+
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity".to_string() } else { "-Infinity".to_string() }
+    } else if n == 0.0 {
+        "0".to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+fn string_to_number(s: &str) -> f64 {
+    let s = s.trim_matches(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n');
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let valid = !digits.is_empty() && digits != "."
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && digits.chars().filter(|c| *c == '.').count() <= 1;
+    if valid { s.parse().unwrap_or(f64::NAN) } else { f64::NAN }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum XPathError {
+    Syntax(String),
+    Eval(String),
+}
+
+impl Error for XPathError {}
+
+impl fmt::Display for XPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Self::Syntax(syntax_error) => write!(f, "[XPath Syntax Error]: {}", syntax_error),
+            Self::Eval(eval_error) => write!(f, "[XPath Evaluation Error]: {}", eval_error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Slash,
+    DoubleSlash,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Dot,
+    DoubleDot,
+    At,
+    Comma,
+    DoubleColon,
+    Pipe,
+    Plus,
+    Minus,
+    Eq,
+    NotEq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    // `*` as a name test, and as the multiply operator
+    Star,
+    Multiply,
+    And,
+    Or,
+    Mod,
+    Div,
+    Literal(String),
+    Number(f64),
+    Name(String),
+    Variable(String)
+}
+
+impl Token {
+    fn is_operator(&self) -> bool {
+        matches!(self, Token::Slash | Token::DoubleSlash | Token::Pipe | Token::Plus | Token::Minus | Token::Eq | Token::NotEq
+            | Token::Lt | Token::Le | Token::Gt | Token::Ge | Token::Multiply | Token::And | Token::Or | Token::Mod | Token::Div)
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, XPathError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        // https://www.w3.org/TR/xpath-10/#exprlex: `*` and names are operators unless they follow
+        // nothing, `@`, `::`, `(`, `[`, `,` or another operator.
+        let operator_expected = match tokens.last() {
+            None => false,
+            Some(prev) => !(matches!(prev, Token::At | Token::DoubleColon | Token::LParen | Token::LBracket | Token::Comma) || prev.is_operator())
+        };
+        let (token, len) = match c {
+            ' ' | '\t' | '\r' | '\n' => { i += 1; continue; },
+            '/' if next == Some('/') => (Token::DoubleSlash, 2),
+            '/' => (Token::Slash, 1),
+            '(' => (Token::LParen, 1),
+            ')' => (Token::RParen, 1),
+            '[' => (Token::LBracket, 1),
+            ']' => (Token::RBracket, 1),
+            '.' if next == Some('.') => (Token::DoubleDot, 2),
+            '.' if next.is_some_and(|n| n.is_ascii_digit()) => lex_number(&chars[i..]),
+            '.' => (Token::Dot, 1),
+            '@' => (Token::At, 1),
+            ',' => (Token::Comma, 1),
+            ':' if next == Some(':') => (Token::DoubleColon, 2),
+            '|' => (Token::Pipe, 1),
+            '+' => (Token::Plus, 1),
+            '-' => (Token::Minus, 1),
+            '=' => (Token::Eq, 1),
+            '!' if next == Some('=') => (Token::NotEq, 2),
+            '<' if next == Some('=') => (Token::Le, 2),
+            '<' => (Token::Lt, 1),
+            '>' if next == Some('=') => (Token::Ge, 2),
+            '>' => (Token::Gt, 1),
+            '*' if operator_expected => (Token::Multiply, 1),
+            '*' => (Token::Star, 1),
+            '"' | '\'' => {
+                let end = chars[i + 1..].iter().position(|ch| *ch == c)
+                    .ok_or_else(|| XPathError::Syntax(format!("unterminated literal at {}", i)))?;
+                (Token::Literal(chars[i + 1..i + 1 + end].iter().collect()), end + 2)
+            },
+            '$' => {
+                let (name, len) = lex_name(&chars[i + 1..]);
+                (Token::Variable(name), len + 1)
+            },
+            c if c.is_ascii_digit() => lex_number(&chars[i..]),
+            c if is_name_start(c) => {
+                let (name, len) = lex_name(&chars[i..]);
+                let token = if operator_expected {
+                    match &*name {
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "mod" => Token::Mod,
+                        "div" => Token::Div,
+                        _ => return Err(XPathError::Syntax(format!("unexpected name `{}` at {}", name, i)))
+                    }
+                } else {
+                    Token::Name(name)
+                };
+                (token, len)
+            },
+            _ => return Err(XPathError::Syntax(format!("unexpected character `{}` at {}", c, i)))
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+fn lex_number(chars: &[char]) -> (Token, usize) {
+    let mut len = chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    if chars.get(len) == Some(&'.') {
+        len += 1;
+        len += chars[len..].iter().take_while(|c| c.is_ascii_digit()).count();
+    }
+    let text: String = chars[..len].iter().collect();
+    (Token::Number(text.parse().unwrap_or(f64::NAN)), len)
+}
+
+// A (possibly prefixed) name. The `:` of a prefix is kept, but not the one of an axis `::`.
+fn lex_name(chars: &[char]) -> (String, usize) {
+    let mut len = chars.iter().take_while(|c| is_name_char(**c)).count();
+    if chars.get(len) == Some(&':') {
+        match chars.get(len + 1) {
+            Some('*') => len += 2,
+            Some(c) if is_name_start(*c) => {
+                len += 1;
+                len += chars[len..].iter().take_while(|c| is_name_char(**c)).count();
+            },
+            _ => {}
+        }
+    }
+    (chars[..len].iter().collect(), len)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Namespace,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    Itself
+}
+
+impl Axis {
+    fn from_name(name: &str) -> Option<Axis> {
+        Some(match name {
+            "ancestor" => Axis::Ancestor,
+            "ancestor-or-self" => Axis::AncestorOrSelf,
+            "attribute" => Axis::Attribute,
+            "child" => Axis::Child,
+            "descendant" => Axis::Descendant,
+            "descendant-or-self" => Axis::DescendantOrSelf,
+            "following" => Axis::Following,
+            "following-sibling" => Axis::FollowingSibling,
+            "namespace" => Axis::Namespace,
+            "parent" => Axis::Parent,
+            "preceding" => Axis::Preceding,
+            "preceding-sibling" => Axis::PrecedingSibling,
+            "self" => Axis::Itself,
+            _ => return None
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum NodeTest {
+    // `*`, or `prefix:*`
+    Any,
+    Name(String),
+    Text,
+    Comment,
+    ProcessingInstruction(Option<String>),
+    Node
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    NotEq,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+    Arithmetic(ArithOp, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Union(Box<Expr>, Box<Expr>),
+    Literal(String),
+    Number(f64),
+    Function(String, Vec<Expr>),
+    Filter(Box<Expr>, Vec<Expr>),
+    // The root node of the document containing the context node
+    Root,
+    // Location steps applied to the node-set of the start expression, or to the context node
+    Path(Option<Box<Expr>>, Vec<Step>)
+}
+
+fn descendant_or_self_step() -> Step {
+    Step { axis: Axis::DescendantOrSelf, test: NodeTest::Node, predicates: Vec::new() }
+}
+
+fn is_node_type(name: &str) -> bool {
+    matches!(name, "comment" | "text" | "processing-instruction" | "node")
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), XPathError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(XPathError::Syntax(format!("expected {:?}, found {:?}", token, self.peek())))
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut left = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, XPathError> {
+        let mut left = self.parse_equality()?;
+        while self.eat(&Token::And) {
+            left = Expr::And(Box::new(left), Box::new(self.parse_equality()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr, XPathError> {
+        let mut left = self.parse_relational()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Eq) => CompareOp::Eq,
+                Some(Token::NotEq) => CompareOp::NotEq,
+                _ => return Ok(left)
+            };
+            self.pos += 1;
+            left = Expr::Compare(op, Box::new(left), Box::new(self.parse_relational()?));
+        }
+    }
+
+    fn parse_relational(&mut self) -> Result<Expr, XPathError> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Lt) => CompareOp::Lt,
+                Some(Token::Le) => CompareOp::Le,
+                Some(Token::Gt) => CompareOp::Gt,
+                Some(Token::Ge) => CompareOp::Ge,
+                _ => return Ok(left)
+            };
+            self.pos += 1;
+            left = Expr::Compare(op, Box::new(left), Box::new(self.parse_additive()?));
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, XPathError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => ArithOp::Add,
+                Some(Token::Minus) => ArithOp::Sub,
+                _ => return Ok(left)
+            };
+            self.pos += 1;
+            left = Expr::Arithmetic(op, Box::new(left), Box::new(self.parse_multiplicative()?));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, XPathError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Multiply) => ArithOp::Mul,
+                Some(Token::Div) => ArithOp::Div,
+                Some(Token::Mod) => ArithOp::Mod,
+                _ => return Ok(left)
+            };
+            self.pos += 1;
+            left = Expr::Arithmetic(op, Box::new(left), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, XPathError> {
+        if self.eat(&Token::Minus) {
+            Ok(Expr::Negate(Box::new(self.parse_unary()?)))
+        } else {
+            self.parse_union()
+        }
+    }
+
+    fn parse_union(&mut self) -> Result<Expr, XPathError> {
+        let mut left = self.parse_path()?;
+        while self.eat(&Token::Pipe) {
+            left = Expr::Union(Box::new(left), Box::new(self.parse_path()?));
+        }
+        Ok(left)
+    }
+
+    fn starts_filter(&self) -> bool {
+        match self.peek() {
+            Some(Token::Literal(_)) | Some(Token::Number(_)) | Some(Token::LParen) | Some(Token::Variable(_)) => true,
+            Some(Token::Name(name)) => self.peek_at(1) == Some(&Token::LParen) && !is_node_type(name),
+            _ => false
+        }
+    }
+
+    fn starts_step(&self) -> bool {
+        matches!(self.peek(), Some(Token::Dot) | Some(Token::DoubleDot) | Some(Token::At) | Some(Token::Star) | Some(Token::Name(_)))
+    }
+
+    fn parse_path(&mut self) -> Result<Expr, XPathError> {
+        match self.peek() {
+            Some(Token::Slash) => {
+                self.pos += 1;
+                if self.starts_step() {
+                    Ok(Expr::Path(Some(Box::new(Expr::Root)), self.parse_relative(Vec::new())?))
+                } else {
+                    Ok(Expr::Root)
+                }
+            },
+            Some(Token::DoubleSlash) => {
+                self.pos += 1;
+                Ok(Expr::Path(Some(Box::new(Expr::Root)), self.parse_relative(vec![descendant_or_self_step()])?))
+            },
+            _ if self.starts_filter() => {
+                let primary = self.parse_primary()?;
+                let predicates = self.parse_predicates()?;
+                let filter = if predicates.is_empty() { primary } else { Expr::Filter(Box::new(primary), predicates) };
+                if self.eat(&Token::Slash) {
+                    Ok(Expr::Path(Some(Box::new(filter)), self.parse_relative(Vec::new())?))
+                } else if self.eat(&Token::DoubleSlash) {
+                    Ok(Expr::Path(Some(Box::new(filter)), self.parse_relative(vec![descendant_or_self_step()])?))
+                } else {
+                    Ok(filter)
+                }
+            },
+            _ => Ok(Expr::Path(None, self.parse_relative(Vec::new())?))
+        }
+    }
+
+    fn parse_relative(&mut self, mut steps: Vec<Step>) -> Result<Vec<Step>, XPathError> {
+        steps.push(self.parse_step()?);
+        loop {
+            if self.eat(&Token::Slash) {
+                steps.push(self.parse_step()?);
+            } else if self.eat(&Token::DoubleSlash) {
+                steps.push(descendant_or_self_step());
+                steps.push(self.parse_step()?);
+            } else {
+                return Ok(steps);
+            }
+        }
+    }
+
+    fn parse_step(&mut self) -> Result<Step, XPathError> {
+        if self.eat(&Token::Dot) {
+            return Ok(Step { axis: Axis::Itself, test: NodeTest::Node, predicates: Vec::new() });
+        }
+        if self.eat(&Token::DoubleDot) {
+            return Ok(Step { axis: Axis::Parent, test: NodeTest::Node, predicates: Vec::new() });
+        }
+        let axis = if self.eat(&Token::At) {
+            Axis::Attribute
+        } else if let (Some(Token::Name(name)), Some(Token::DoubleColon)) = (self.peek(), self.peek_at(1)) {
+            let axis = Axis::from_name(name).ok_or_else(|| XPathError::Syntax(format!("unknown axis `{}`", name)))?;
+            self.pos += 2;
+            axis
+        } else {
+            Axis::Child
+        };
+        let test = match self.next() {
+            Some(Token::Star) => NodeTest::Any,
+            Some(Token::Name(name)) if self.peek() == Some(&Token::LParen) && is_node_type(&name) => {
+                self.pos += 1;
+                let test = match &*name {
+                    "comment" => NodeTest::Comment,
+                    "text" => NodeTest::Text,
+                    "node" => NodeTest::Node,
+                    _ => match self.peek() {
+                        Some(Token::Literal(target)) => {
+                            let target = target.clone();
+                            self.pos += 1;
+                            NodeTest::ProcessingInstruction(Some(target))
+                        },
+                        _ => NodeTest::ProcessingInstruction(None)
+                    }
+                };
+                self.expect(&Token::RParen)?;
+                test
+            },
+            Some(Token::Name(name)) if name.ends_with(":*") => NodeTest::Any,
+            Some(Token::Name(name)) => NodeTest::Name(name),
+            other => return Err(XPathError::Syntax(format!("expected a node test, found {:?}", other)))
+        };
+        let predicates = self.parse_predicates()?;
+        Ok(Step { axis, test, predicates })
+    }
+
+    fn parse_predicates(&mut self) -> Result<Vec<Expr>, XPathError> {
+        let mut predicates = Vec::new();
+        while self.eat(&Token::LBracket) {
+            predicates.push(self.parse_expr()?);
+            self.expect(&Token::RBracket)?;
+        }
+        Ok(predicates)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, XPathError> {
+        match self.next() {
+            Some(Token::Literal(literal)) => Ok(Expr::Literal(literal)),
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::LParen) => {
+                let expr = self.parse_expr()?;
+                self.expect(&Token::RParen)?;
+                Ok(expr)
+            },
+            Some(Token::Variable(name)) => Err(XPathError::Syntax(format!("variables are not supported: `${}`", name))),
+            Some(Token::Name(name)) => {
+                self.expect(&Token::LParen)?;
+                let mut args = Vec::new();
+                if !self.eat(&Token::RParen) {
+                    args.push(self.parse_expr()?);
+                    while self.eat(&Token::Comma) {
+                        args.push(self.parse_expr()?);
+                    }
+                    self.expect(&Token::RParen)?;
+                }
+                check_function(&name, args.len())?;
+                Ok(Expr::Function(name, args))
+            },
+            other => Err(XPathError::Syntax(format!("unexpected token {:?}", other)))
+        }
+    }
+}
+
+// Reject unknown functions and wrong arities when the expression is compiled, not when evaluated.
+fn check_function(name: &str, arity: usize) -> Result<(), XPathError> {
+    let (min, max) = match name {
+        "last" | "position" | "true" | "false" => (0, 0),
+        "count" | "id" | "boolean" | "not" | "lang" | "sum" | "floor" | "ceiling" | "round" => (1, 1),
+        "local-name" | "namespace-uri" | "name" | "string" | "string-length" | "normalize-space" | "number" => (0, 1),
+        "starts-with" | "contains" | "substring-before" | "substring-after" => (2, 2),
+        "substring" => (2, 3),
+        "translate" => (3, 3),
+        "concat" => (2, usize::MAX),
+        _ => return Err(XPathError::Syntax(format!("unknown function `{}`", name)))
+    };
+    if arity < min || arity > max {
+        return Err(XPathError::Syntax(format!("wrong number of arguments for `{}`: {}", name, arity)));
+    }
+    Ok(())
+}
+
+/// A compiled XPath 1.0 expression.
+///
+/// Element and attribute names are matched case-insensitively, because the HTML parser lower-cases
+/// them. Namespaces and variables are not supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XPath {
+    expr: Expr
+}
+
+impl XPath {
+    pub fn parse(source: &str) -> Result<Self, XPathError> {
+        let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+        let expr = parser.parse_expr()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(XPathError::Syntax(format!("unexpected token {:?} in `{}`", parser.tokens[parser.pos], source)));
+        }
+        Ok(Self { expr })
+    }
+
+    /// Evaluate the expression with `context` as the context node.
+    pub fn evaluate<'a>(&self, context: XNode<'a>) -> Result<Value<'a>, XPathError> {
+        self.evaluate_in(context, &DocumentOrder::default())
+    }
+
+    /// Evaluate the expression like `evaluate`, with the document order of the document of
+    /// `context`, shared by all the evaluations on the same document.
+    pub fn evaluate_in<'a>(&self, context: XNode<'a>, order: &DocumentOrder) -> Result<Value<'a>, XPathError> {
+        let evaluator = Evaluator::new(context.owner(), order);
+        evaluator.eval(&self.expr, &Context { node: context, position: 1, size: 1 })
+    }
+
+    /// Evaluate the expression, which must result in a node-set, and return the nodes in document
+    /// order.
+    pub fn select<'a>(&self, context: XNode<'a>) -> Result<Vec<XNode<'a>>, XPathError> {
+        match self.evaluate(context)? {
+            Value::NodeSet(nodes) => Ok(nodes),
+            other => Err(XPathError::Eval(format!("expected a node-set, found {:?}", other)))
+        }
+    }
+}
+
+struct Context<'a> {
+    node: XNode<'a>,
+    position: usize,
+    size: usize
+}
+
+/// The position of every node of a document in document order. It is built the first time nodes
+/// are sorted, and kept for all the evaluations on the same document: use one per document.
+#[derive(Debug, Default)]
+pub struct DocumentOrder {
+    positions: OnceCell<HashMap<NodeId, usize>>
+}
+
+impl DocumentOrder {
+    fn positions(&self, root: NodeRef<Node>) -> &HashMap<NodeId, usize> {
+        self.positions.get_or_init(|| root.descendants().enumerate().map(|(i, n)| (n.id(), i)).collect())
+    }
+}
+
+struct Evaluator<'a, 'o> {
+    root: NodeRef<'a, Node>,
+    order: &'o DocumentOrder
+}
+
+impl<'a, 'o> Evaluator<'a, 'o> {
+    fn new(node: NodeRef<'a, Node>, order: &'o DocumentOrder) -> Self {
+        Self { root: node.tree().root(), order }
+    }
+
+    fn document_order(&self, mut nodes: Vec<XNode<'a>>) -> Vec<XNode<'a>> {
+        let positions = self.order.positions(self.root);
+        let position = |node: &NodeRef<Node>| positions.get(&node.id()).copied().unwrap_or(0);
+        nodes.sort_by_key(|n| match n {
+            XNode::Node(node) => (position(node), 0, ""),
+            XNode::Attribute(owner, name, _) => (position(owner), 1, *name)
+        });
+        nodes.dedup_by(|a, b| a.same(b));
+        nodes
+    }
+
+    fn eval(&self, expr: &Expr, ctx: &Context<'a>) -> Result<Value<'a>, XPathError> {
+        Ok(match expr {
+            Expr::Or(left, right) => Value::Bool(self.eval(left, ctx)?.to_bool() || self.eval(right, ctx)?.to_bool()),
+            Expr::And(left, right) => Value::Bool(self.eval(left, ctx)?.to_bool() && self.eval(right, ctx)?.to_bool()),
+            Expr::Compare(op, left, right) => Value::Bool(compare(*op, &self.eval(left, ctx)?, &self.eval(right, ctx)?)),
+            Expr::Arithmetic(op, left, right) => {
+                let (left, right) = (self.eval(left, ctx)?.to_number(), self.eval(right, ctx)?.to_number());
+                Value::Num(match op {
+                    ArithOp::Add => left + right,
+                    ArithOp::Sub => left - right,
+                    ArithOp::Mul => left * right,
+                    ArithOp::Div => left / right,
+                    ArithOp::Mod => left % right
+                })
+            },
+            Expr::Negate(inner) => Value::Num(-self.eval(inner, ctx)?.to_number()),
+            Expr::Union(left, right) => {
+                let mut nodes = self.eval_node_set(left, ctx)?;
+                nodes.extend(self.eval_node_set(right, ctx)?);
+                Value::NodeSet(self.document_order(nodes))
+            },
+            Expr::Literal(literal) => Value::Str(literal.clone()),
+            Expr::Number(number) => Value::Num(*number),
+            Expr::Function(name, args) => self.call(name, args, ctx)?,
+            Expr::Filter(primary, predicates) => {
+                let mut nodes = self.eval_node_set(primary, ctx)?;
+                for predicate in predicates {
+                    nodes = self.filter(nodes, predicate)?;
+                }
+                Value::NodeSet(nodes)
+            },
+            Expr::Root => Value::NodeSet(vec![XNode::Node(self.root)]),
+            Expr::Path(start, steps) => {
+                let mut nodes = match start {
+                    Some(start) => self.eval_node_set(start, ctx)?,
+                    None => vec![ctx.node]
+                };
+                for step in steps {
+                    nodes = self.eval_step(nodes, step)?;
+                }
+                Value::NodeSet(nodes)
+            }
+        })
+    }
+
+    fn eval_node_set(&self, expr: &Expr, ctx: &Context<'a>) -> Result<Vec<XNode<'a>>, XPathError> {
+        match self.eval(expr, ctx)? {
+            Value::NodeSet(nodes) => Ok(nodes),
+            other => Err(XPathError::Eval(format!("expected a node-set, found {:?}", other)))
+        }
+    }
+
+    fn eval_step(&self, nodes: Vec<XNode<'a>>, step: &Step) -> Result<Vec<XNode<'a>>, XPathError> {
+        let mut result = Vec::new();
+        for node in nodes {
+            let mut candidates: Vec<XNode<'a>> = axis_nodes(node, step.axis).into_iter()
+                .filter(|candidate| test_node(candidate, step.axis, &step.test))
+                .collect();
+            for predicate in &step.predicates {
+                candidates = self.filter(candidates, predicate)?;
+            }
+            result.extend(candidates);
+        }
+        Ok(self.document_order(result))
+    }
+
+    // Keep the nodes for which the predicate holds. A number predicate is compared to the position.
+    fn filter(&self, nodes: Vec<XNode<'a>>, predicate: &Expr) -> Result<Vec<XNode<'a>>, XPathError> {
+        let size = nodes.len();
+        let mut result = Vec::new();
+        for (i, node) in nodes.into_iter().enumerate() {
+            let ctx = Context { node, position: i + 1, size };
+            let keep = match self.eval(predicate, &ctx)? {
+                Value::Num(n) => n == (i + 1) as f64,
+                other => other.to_bool()
+            };
+            if keep {
+                result.push(node);
+            }
+        }
+        Ok(result)
+    }
+
+    fn call(&self, name: &str, args: &[Expr], ctx: &Context<'a>) -> Result<Value<'a>, XPathError> {
+        let arg = |i: usize| self.eval(&args[i], ctx);
+        let string_arg = |i: usize| -> Result<String, XPathError> {
+            if args.len() > i { Ok(arg(i)?.to_xpath_string()) } else { Ok(ctx.node.string_value()) }
+        };
+        let node_arg = |i: usize| -> Result<Option<XNode<'a>>, XPathError> {
+            if args.len() > i { Ok(self.eval_node_set(&args[i], ctx)?.first().copied()) } else { Ok(Some(ctx.node)) }
+        };
+        Ok(match name {
+            "last" => Value::Num(ctx.size as f64),
+            "position" => Value::Num(ctx.position as f64),
+            "count" => Value::Num(self.eval_node_set(&args[0], ctx)?.len() as f64),
+            "id" => {
+                let ids: HashSet<String> = match arg(0)? {
+                    Value::NodeSet(nodes) => nodes.iter().flat_map(|n| n.string_value().split_whitespace().map(String::from).collect::<Vec<_>>()).collect(),
+                    other => other.to_xpath_string().split_whitespace().map(String::from).collect()
+                };
+                Value::NodeSet(self.root.descendants()
+                    .filter(|n| n.value().as_element().and_then(|e| e.id()).is_some_and(|id| ids.contains(id)))
+                    .map(XNode::Node)
+                    .collect())
+            },
+            "local-name" => Value::Str(node_arg(0)?.map(|n| n.name().rsplit(':').next().unwrap_or("").to_string()).unwrap_or_default()),
+            "name" => Value::Str(node_arg(0)?.map(|n| n.name().to_string()).unwrap_or_default()),
+            "namespace-uri" => Value::Str(String::new()),
+            "string" => Value::Str(string_arg(0)?),
+            "concat" => {
+                let mut result = String::new();
+                for i in 0..args.len() {
+                    result.push_str(&arg(i)?.to_xpath_string());
+                }
+                Value::Str(result)
+            },
+            "starts-with" => Value::Bool(string_arg(0)?.starts_with(&string_arg(1)?)),
+            "contains" => Value::Bool(string_arg(0)?.contains(&string_arg(1)?)),
+            "substring-before" => {
+                let (haystack, needle) = (string_arg(0)?, string_arg(1)?);
+                Value::Str(haystack.find(&needle).map(|i| haystack[..i].to_string()).unwrap_or_default())
+            },
+            "substring-after" => {
+                let (haystack, needle) = (string_arg(0)?, string_arg(1)?);
+                Value::Str(haystack.find(&needle).map(|i| haystack[i + needle.len()..].to_string()).unwrap_or_default())
+            },
+            "substring" => {
+                let text = string_arg(0)?;
+                let start = arg(1)?.to_number().round();
+                let end = if args.len() > 2 { start + arg(2)?.to_number().round() } else { f64::INFINITY };
+                // positions are 1-based, and characters count rather than bytes
+                Value::Str(text.chars().enumerate()
+                    .filter(|(i, _)| { let p = (*i + 1) as f64; p >= start && p < end })
+                    .map(|(_, c)| c)
+                    .collect())
+            },
+            "string-length" => Value::Num(string_arg(0)?.chars().count() as f64),
+            "normalize-space" => Value::Str(string_arg(0)?.split_whitespace().collect::<Vec<_>>().join(" ")),
+            "translate" => {
+                let (text, from, to) = (string_arg(0)?, string_arg(1)?.chars().collect::<Vec<_>>(), string_arg(2)?.chars().collect::<Vec<_>>());
+                Value::Str(text.chars().filter_map(|c| match from.iter().position(|f| *f == c) {
+                    Some(i) => to.get(i).copied(),
+                    None => Some(c)
+                }).collect())
+            },
+            "boolean" => Value::Bool(arg(0)?.to_bool()),
+            "not" => Value::Bool(!arg(0)?.to_bool()),
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            "lang" => {
+                let lang = string_arg(0)?.to_lowercase();
+                let declared = iter::once(ctx.node.owner()).chain(ctx.node.owner().ancestors())
+                    .find_map(|n| n.value().as_element().and_then(|e| e.attr("lang").or_else(|| e.attr("xml:lang"))))
+                    .map(|l| l.to_lowercase());
+                Value::Bool(declared.is_some_and(|l| l == lang || l.starts_with(&format!("{}-", lang))))
+            },
+            "number" => if args.is_empty() { Value::Num(string_to_number(&ctx.node.string_value())) } else { Value::Num(arg(0)?.to_number()) },
+            "sum" => Value::Num(self.eval_node_set(&args[0], ctx)?.iter().map(|n| string_to_number(&n.string_value())).sum()),
+            "floor" => Value::Num(arg(0)?.to_number().floor()),
+            "ceiling" => Value::Num(arg(0)?.to_number().ceil()),
+            "round" => Value::Num((arg(0)?.to_number() + 0.5).floor()),
+            _ => return Err(XPathError::Eval(format!("unknown function `{}`", name)))
+        })
+    }
+}
+
+fn compare(op: CompareOp, left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::NodeSet(_), Value::Bool(_)) | (Value::Bool(_), Value::NodeSet(_)) => compare_atoms(op, &Value::Bool(left.to_bool()), &Value::Bool(right.to_bool())),
+        (Value::NodeSet(nodes), other) => nodes.iter().any(|n| compare(op, &Value::Str(n.string_value()), other)),
+        (other, Value::NodeSet(nodes)) => nodes.iter().any(|n| compare(op, other, &Value::Str(n.string_value()))),
+        _ => compare_atoms(op, left, right)
+    }
+}
+
+fn compare_atoms(op: CompareOp, left: &Value, right: &Value) -> bool {
+    match op {
+        CompareOp::Eq | CompareOp::NotEq => {
+            let equal = match (left, right) {
+                (Value::Bool(_), _) | (_, Value::Bool(_)) => left.to_bool() == right.to_bool(),
+                (Value::Num(_), _) | (_, Value::Num(_)) => left.to_number() == right.to_number(),
+                _ => left.to_xpath_string() == right.to_xpath_string()
+            };
+            equal == (op == CompareOp::Eq)
+        },
+        _ => {
+            let (left, right) = (left.to_number(), right.to_number());
+            match op {
+                CompareOp::Lt => left < right,
+                CompareOp::Le => left <= right,
+                CompareOp::Gt => left > right,
+                _ => left >= right
+            }
+        }
+    }
+}
+
+fn attributes(node: NodeRef<Node>) -> Vec<XNode> {
+    let mut attrs: Vec<XNode> = match node.value().as_element() {
+        Some(elm) => elm.attrs().map(|(name, value)| XNode::Attribute(node, name, value)).collect(),
+        None => Vec::new()
+    };
+    attrs.sort_by_key(|a| a.name());
+    attrs
+}
+
+fn wrap<'a>(nodes: impl Iterator<Item = NodeRef<'a, Node>>) -> Vec<XNode<'a>> {
+    nodes.map(XNode::Node).collect()
+}
+
+// The nodes of an axis, in the axis order: reverse document order for the reverse axes.
+fn axis_nodes(node: XNode, axis: Axis) -> Vec<XNode> {
+    match (node, axis) {
+        (_, Axis::Itself) => vec![node],
+        (_, Axis::Namespace) => Vec::new(),
+        (XNode::Attribute(owner, _, _), Axis::Parent) => vec![XNode::Node(owner)],
+        (XNode::Attribute(owner, _, _), Axis::Ancestor) => wrap(iter::once(owner).chain(owner.ancestors())),
+        (XNode::Attribute(owner, _, _), Axis::AncestorOrSelf) => {
+            let mut nodes = vec![node];
+            nodes.extend(wrap(iter::once(owner).chain(owner.ancestors())));
+            nodes
+        },
+        (XNode::Attribute(owner, _, _), Axis::Following) => {
+            let mut nodes = wrap(owner.descendants().skip(1));
+            nodes.extend(axis_nodes(XNode::Node(owner), Axis::Following));
+            nodes
+        },
+        (XNode::Attribute(owner, _, _), Axis::Preceding) => axis_nodes(XNode::Node(owner), Axis::Preceding),
+        (XNode::Attribute(_, _, _), _) => Vec::new(),
+        (XNode::Node(n), Axis::Child) => wrap(n.children()),
+        (XNode::Node(n), Axis::Descendant) => wrap(n.descendants().skip(1)),
+        (XNode::Node(n), Axis::DescendantOrSelf) => wrap(n.descendants()),
+        (XNode::Node(n), Axis::Parent) => wrap(n.parent().into_iter()),
+        (XNode::Node(n), Axis::Ancestor) => wrap(n.ancestors()),
+        (XNode::Node(n), Axis::AncestorOrSelf) => wrap(iter::once(n).chain(n.ancestors())),
+        (XNode::Node(n), Axis::FollowingSibling) => wrap(n.next_siblings()),
+        (XNode::Node(n), Axis::PrecedingSibling) => wrap(n.prev_siblings()),
+        (XNode::Node(n), Axis::Following) => wrap(iter::once(n).chain(n.ancestors())
+            .flat_map(|a| a.next_siblings())
+            .flat_map(|s| s.descendants())),
+        (XNode::Node(n), Axis::Preceding) => {
+            let ancestors: HashSet<NodeId> = n.ancestors().map(|a| a.id()).collect();
+            let mut nodes: Vec<XNode> = n.tree().root().descendants()
+                .take_while(|d| d.id() != n.id())
+                .filter(|d| !ancestors.contains(&d.id()))
+                .map(XNode::Node)
+                .collect();
+            nodes.reverse();
+            nodes
+        },
+        (XNode::Node(n), Axis::Attribute) => attributes(n)
+    }
+}
+
+fn test_node(node: &XNode, axis: Axis, test: &NodeTest) -> bool {
+    // the principal node type of the attribute axis is attribute, element for the others
+    let principal = match node {
+        XNode::Attribute(_, _, _) => axis == Axis::Attribute,
+        XNode::Node(n) => axis != Axis::Attribute && n.value().is_element()
+    };
+    match test {
+        NodeTest::Any => principal,
+        NodeTest::Name(name) => principal && node.name().eq_ignore_ascii_case(name.rsplit(':').next().unwrap_or(name)),
+        NodeTest::Text => matches!(node, XNode::Node(n) if n.value().is_text()),
+        NodeTest::Comment => matches!(node, XNode::Node(n) if n.value().is_comment()),
+        NodeTest::ProcessingInstruction(target) => match node {
+            XNode::Node(n) => n.value().as_processing_instruction().is_some_and(|pi| target.as_ref().is_none_or(|t| **t == *pi.target)),
+            _ => false
+        },
+        NodeTest::Node => true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::Html;
+
+    const HTML: &str = r#"<html><head><title>Books</title></head><body>
+<table id="specs"><tr><th>Author</th><td>Jane Doe</td></tr><tr><th>Price</th><td class="v">$42.00</td></tr><tr><th>Pages</th><td class="v">320</td></tr></table>
+<ul><li lang="en-CA">one</li><li>two <b>bold</b></li><li>three</li></ul>
+<!-- note --><a href="/a">A</a><a href="/b">B</a>
+</body></html>"#;
+
+    fn strings(html: &Html, xpath: &str) -> Vec<String> {
+        let xpath = XPath::parse(xpath).unwrap();
+        xpath.select(XNode::Node(html.tree.root())).unwrap().iter().map(|n| n.string_value()).collect()
+    }
+
+    fn string(html: &Html, xpath: &str) -> String {
+        XPath::parse(xpath).unwrap().evaluate(XNode::Node(html.tree.root())).unwrap().to_xpath_string()
+    }
+
+    #[test]
+    fn following_sibling_of_header() {
+        let html = Html::parse_document(HTML);
+        assert_eq!(strings(&html, "//th[text()='Price']/following-sibling::td"), vec!["$42.00"]);
+        assert_eq!(strings(&html, "//tr[th = 'Pages']/td"), vec!["320"]);
+    }
+
+    #[test]
+    fn abbreviated_steps() {
+        let html = Html::parse_document(HTML);
+        assert_eq!(strings(&html, "/html/body/ul/li[2]/b/../text()"), vec!["two "]);
+        assert_eq!(strings(&html, "//li[last()]"), vec!["three"]);
+        assert_eq!(strings(&html, "//a/@href"), vec!["/a", "/b"]);
+        assert_eq!(strings(&html, "//td[@class='v'][1]/."), vec!["$42.00", "320"]);
+        assert_eq!(strings(&html, "(//td[@class='v'])[1]"), vec!["$42.00"]);
+        assert_eq!(strings(&html, "(//td)[position() > 1]"), vec!["$42.00", "320"]);
+    }
+
+    #[test]
+    fn reverse_axes_positions() {
+        let html = Html::parse_document(HTML);
+        assert_eq!(strings(&html, "//li[3]/preceding-sibling::li[1]"), vec!["two bold"]);
+        assert_eq!(strings(&html, "//b/ancestor::*[2]/@lang"), Vec::<String>::new());
+        assert_eq!(strings(&html, "//b/ancestor::*[2]/li[1]/@lang"), vec!["en-CA"]);
+        assert_eq!(strings(&html, "//ul/preceding::th[1]"), vec!["Pages"]);
+        assert_eq!(strings(&html, "//table/following::a[2]"), vec!["B"]);
+        assert_eq!(strings(&html, "//comment()"), vec![" note "]);
+    }
+
+    #[test]
+    fn union_in_document_order() {
+        let html = Html::parse_document(HTML);
+        assert_eq!(strings(&html, "//a | //title | //a[1]"), vec!["Books", "A", "B"]);
+    }
+
+    #[test]
+    fn document_order_is_built_once() {
+        let html = Html::parse_document(HTML);
+        let order = DocumentOrder::default();
+        let rows: Vec<XNode> = XPath::parse("//tr").unwrap().select(XNode::Node(html.tree.root())).unwrap();
+        assert!(order.positions.get().is_none());
+        let xpath = XPath::parse("td | th").unwrap();
+        let mut cells = Vec::new();
+        for row in rows {
+            if let Value::NodeSet(nodes) = xpath.evaluate_in(row, &order).unwrap() {
+                cells.extend(nodes.iter().map(XNode::string_value));
+            }
+        }
+        assert_eq!(cells, vec!["Author", "Jane Doe", "Price", "$42.00", "Pages", "320"]);
+        let positions = order.positions.get().unwrap() as *const _;
+        xpath.evaluate_in(XNode::Node(html.tree.root()), &order).unwrap();
+        assert_eq!(order.positions.get().unwrap() as *const _, positions);
+    }
+
+    #[test]
+    fn string_functions() {
+        let html = Html::parse_document(HTML);
+        assert_eq!(string(&html, "normalize-space(//li[2])"), "two bold");
+        assert_eq!(string(&html, "concat(//th[1], ': ', //td[1])"), "Author: Jane Doe");
+        assert_eq!(string(&html, "substring-after(//td[@class='v'], '$')"), "42.00");
+        assert_eq!(string(&html, "substring-before('2021-10-01', '-')"), "2021");
+        assert_eq!(string(&html, "substring('12345', 1.5, 2.6)"), "234");
+        assert_eq!(string(&html, "translate('bar', 'abc', 'ABC')"), "BAr");
+        assert_eq!(string(&html, "string-length(//title)"), "5");
+        assert_eq!(string(&html, "count(//li[contains(., 'o')])"), "2");
+        assert_eq!(strings(&html, "//th[starts-with(., 'Pa')]"), vec!["Pages"]);
+        assert_eq!(string(&html, "local-name(//a[1]/@href)"), "href");
+    }
+
+    #[test]
+    fn number_and_boolean_functions() {
+        let html = Html::parse_document(HTML);
+        assert_eq!(string(&html, "sum(//td[. > 100]) + 1"), "321");
+        assert_eq!(string(&html, "7 mod 3 * -2"), "-2");
+        assert_eq!(string(&html, "10 div 4"), "2.5");
+        assert_eq!(string(&html, "round(2.5) + floor(-1.5) + ceiling(0.2)"), "2");
+        assert_eq!(string(&html, "number('abc')"), "NaN");
+        assert_eq!(string(&html, "not(//li) or true()"), "true");
+        assert_eq!(string(&html, "boolean(//li[lang('en')])"), "true");
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(matches!(XPath::parse("//div["), Err(XPathError::Syntax(_))));
+        assert!(matches!(XPath::parse("//div/foo::span"), Err(XPathError::Syntax(_))));
+        assert!(matches!(XPath::parse("unknown(1)"), Err(XPathError::Syntax(_))));
+        assert!(matches!(XPath::parse("//div[$var]"), Err(XPathError::Syntax(_))));
+        assert!(matches!(XPath::parse("contains('a')"), Err(XPathError::Syntax(_))));
+    }
+}